@@ -2,16 +2,34 @@
 //!
 //! Options:
 //! - level: Select the log level
+//! - session: Select the session
+//! - global: Use the global state instead of the one of the project
+//! - config-dir: Use another configuration directory
+//! - output: Print text or a JSON object
 //!
 //! Subcommands:
 //! - start: Initialize configuration, setting next position to 0
 //! - next: Prints the next snippet to stdout and increments counter
 //! - rewind: Decrements counter
 //! - forward: Increments counter
+//! - goto: Sets counter to a snippet given by position, offset, or label
+//! - undo: Restores the position before the last change
+//! - redo: Repeats the last undone change of the position
+//! - history: Prints the changes of the position
+//! - status: Prints the deck, the position, and the next snippets
+//! - list: Prints all the snippets of the deck
+//! - refresh: Updates the copy of a frozen deck
+//! - relink: Uses the deck at its new path after it has been moved
+//! - sessions: Lists, switches, renames, or deletes sessions
+//! - check: Reports the problems of a deck
+//! - config: Gets, sets, or lists the user settings
+//! - paths: Prints the paths of the configuration and state in use
 //!
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
+use crate::{deck::DeckFormat, goto_target::GotoTarget, template};
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(help_template = "{before-help}{name} {version}
@@ -24,6 +42,22 @@ pub struct CliArgs {
     #[arg(short, long, value_enum, value_name = "LEVEL")]
     pub level: Option<log::LevelFilter>,
 
+    /// Session to use instead of the active one
+    #[arg(long, global = true, value_name = "NAME")]
+    pub session: Option<String>,
+
+    /// Use the global state even inside a project with a .lazycoder directory
+    #[arg(long, global = true)]
+    pub global: bool,
+
+    /// Directory for the settings and, outside projects, the state
+    #[arg(long, global = true, value_name = "DIR")]
+    pub config_dir: Option<PathBuf>,
+
+    /// Format of the output of the subcommand
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -35,6 +69,15 @@ pub enum Command {
     Start {
         /// Path to snippet file
         filename: PathBuf,
+        /// Format of the snippet file (detected from its extension by default)
+        #[arg(long, value_enum)]
+        format: Option<DeckFormat>,
+        /// Value of a template variable used in the snippets
+        #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_variable)]
+        vars: Vec<(String, String)>,
+        /// Provide the snippets from a copy of the file, so changes to it don't affect them
+        #[arg(long)]
+        freeze: bool,
     },
     /// Provide next snippet
     #[command(visible_alias = "n")]
@@ -54,6 +97,148 @@ pub enum Command {
         /// Set n (by default is 1)
         count: Option<usize>,
     },
+    /// Go to the snippet of *TARGET*
+    #[command(visible_alias = "g")]
+    Goto {
+        /// Position (12), offset from the current one (+3, -2), offset from the last one ($, $-1), or id or title
+        #[arg(allow_hyphen_values = true)]
+        target: GotoTarget,
+    },
+    /// Undo the last change of the position
+    Undo {},
+    /// Redo the last undone change of the position
+    Redo {},
+    /// Show the changes of the position
+    History {},
+    /// Show the snippet file, the position out of the total, and the next snippets
+    Status {
+        /// Format of the report
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// List the snippets with their position, id or title, size, and first line, marking the next one
+    List {},
+    /// Update the copy of a frozen snippet file and report the snippets that changed
+    Refresh {},
+    /// Use the snippet file after it has been moved, keeping the position
+    Relink {
+        /// New path to snippet file (found by its contents near the previous one by default)
+        filename: Option<PathBuf>,
+    },
+    /// Manage the sessions, each one with its own snippet file and position
+    Sessions {
+        #[command(subcommand)]
+        command: Option<SessionsCommand>,
+    },
+    /// Report the problems of *FILENAME*
+    Check {
+        /// Path to snippet file
+        filename: PathBuf,
+        /// Format of the snippet file (detected from its extension by default)
+        #[arg(long, value_enum)]
+        format: Option<DeckFormat>,
+        /// Rewrite the snippet file in canonical form before checking it
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Manage the user settings
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Show the paths of the configuration and state in use
+    Paths {},
+}
+
+impl Command {
+    /// Name of the subcommand, as reported in the JSON output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Start { .. } => "start",
+            Command::Next {} => "next",
+            Command::Peek {} => "peek",
+            Command::Rewind { .. } => "rewind",
+            Command::Forward { .. } => "forward",
+            Command::Goto { .. } => "goto",
+            Command::Undo {} => "undo",
+            Command::Redo {} => "redo",
+            Command::History {} => "history",
+            Command::Status { .. } => "status",
+            Command::List {} => "list",
+            Command::Refresh {} => "refresh",
+            Command::Relink { .. } => "relink",
+            Command::Sessions { .. } => "sessions",
+            Command::Check { .. } => "check",
+            Command::Config { .. } => "config",
+            Command::Paths {} => "paths",
+        }
+    }
+}
+
+/// Format of the reports printed by the subcommands.
+#[derive(Clone, Copy, PartialEq, Debug, ValueEnum)]
+pub enum OutputFormat {
+    /// Text for people
+    Text,
+    /// JSON for scripts
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum SessionsCommand {
+    /// List the sessions, marking the active one (default)
+    List {},
+    /// Make *NAME* the active session
+    Switch {
+        /// Name of the session
+        name: String,
+    },
+    /// Rename session *FROM* to *TO*
+    Rename {
+        /// Current name of the session
+        from: String,
+        /// New name of the session
+        to: String,
+    },
+    /// Delete session *NAME*
+    Delete {
+        /// Name of the session
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the value of setting *KEY*
+    Get {
+        /// Name of the setting
+        key: String,
+    },
+    /// Set setting *KEY* to *VALUE*
+    Set {
+        /// Name of the setting
+        key: String,
+        /// New value (comma-separated for lists)
+        value: String,
+    },
+    /// Remove setting *KEY*, so its default is used
+    Unset {
+        /// Name of the setting
+        key: String,
+    },
+    /// List the settings that are set
+    List {},
+}
+
+/// Parses a template variable definition with the form `key=value`.
+fn parse_variable(definition: &str) -> Result<(String, String), String> {
+    match definition.split_once('=') {
+        Some((key, value)) if template::is_variable_name(key) => {
+            Ok((key.to_owned(), value.to_owned()))
+        }
+        Some((key, _)) => Err(format!("invalid variable name: {key:?}")),
+        None => Err(format!("expected KEY=VALUE, found {definition:?}")),
+    }
 }
 
 #[cfg(test)]
@@ -66,4 +251,71 @@ mod tests {
 
         CliArgs::command().debug_assert();
     }
+
+    #[test]
+    fn negative_goto_target_is_not_an_option() {
+        let result = CliArgs::try_parse_from(["lazycoder", "goto", "-2"]);
+
+        assert!(
+            matches!(result, Ok(CliArgs { command: Command::Goto { ref target }, .. }) if *target == GotoTarget::Backward(2)),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn command_names_are_the_subcommands() {
+        for args in [
+            &["start", "talk.lazycoder"][..],
+            &["next"],
+            &["peek"],
+            &["rewind"],
+            &["forward", "2"],
+            &["goto", "$"],
+            &["undo"],
+            &["redo"],
+            &["history"],
+            &["status"],
+            &["list"],
+            &["refresh"],
+            &["relink"],
+            &["sessions"],
+            &["check", "talk.lazycoder"],
+            &["config", "list"],
+            &["paths"],
+        ] {
+            let cli = CliArgs::try_parse_from(["lazycoder"].iter().chain(args)).unwrap();
+
+            assert_eq!(cli.command.name(), args[0]);
+        }
+    }
+
+    #[test]
+    fn output_format_is_global() {
+        let result = CliArgs::try_parse_from(["lazycoder", "next", "--output", "json"]);
+
+        assert!(
+            matches!(
+                result,
+                Ok(CliArgs {
+                    output: OutputFormat::Json,
+                    ..
+                })
+            ),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn variable_definition_is_parsed() {
+        assert_eq!(
+            parse_variable("project_name=my shapes=1"),
+            Ok((String::from("project_name"), String::from("my shapes=1")))
+        );
+        assert_eq!(
+            parse_variable("empty="),
+            Ok((String::from("empty"), String::new()))
+        );
+        assert!(parse_variable("project_name").is_err());
+        assert!(parse_variable("project-name=shapes").is_err());
+    }
 }
@@ -1,27 +1,141 @@
-use crate::lazy_coder_error::LazyCoderError;
-use log::{debug, error};
+use crate::{
+    deck::{self, DeckFormat, DeckSettings, EndOfDeck, Snippet},
+    deck_status::{DeckStatus, PREVIEW_COUNT, SnippetPreview, SnippetSummary},
+    goto_target::GotoTarget,
+    lazy_coder_error::LazyCoderError,
+    position_journal::{Operation, PositionJournal},
+    settings::Settings,
+    snippet_handler::SnippetProvider,
+    state_file::StateLock,
+    template,
+};
+use log::{debug, error, info, warn};
+use serde::Deserialize as _;
 use serde_derive::{Deserialize, Serialize};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Path, PathBuf},
+};
 
 #[cfg(not(test))]
-use aux::{config_dir, get_snippet_provider, path_exists};
+use crate::state_file::write_atomic;
+#[cfg(not(test))]
+use aux::{
+    active_session, config_dir, env_var, file_hash, find_deck, freeze_deck, get_snippet_provider,
+    load_journal, load_settings, lock_state, modified_and_size, path_exists, project_root,
+    remove_frozen_deck, remove_journal, rename_frozen_deck, rename_journal, save_journal,
+    save_settings, session_names, session_path, set_active_session, state_dir, use_config_dir,
+    use_global_state,
+};
 #[cfg(test)]
 use mockall::automock;
 #[cfg(not(test))]
-use std::fs::{canonicalize, create_dir_all, read_to_string, write};
+use std::fs::{canonicalize, copy, create_dir_all, read_to_string, remove_file, rename};
 #[cfg(test)]
 use tests::aux::{
-    canonicalize, config_dir, create_dir_all, get_snippet_provider, path_exists, read_to_string,
-    write,
+    active_session, canonicalize, config_dir, copy, create_dir_all, env_var, file_hash, find_deck,
+    freeze_deck, get_snippet_provider, load_journal, load_settings, lock_state, modified_and_size,
+    path_exists, project_root, read_to_string, remove_file, remove_frozen_deck, remove_journal,
+    rename, rename_frozen_deck, rename_journal, save_journal, save_settings, session_names,
+    session_path, set_active_session, state_dir, use_config_dir, use_global_state, write_atomic,
 };
 
 static FILE_NAME: &str = "lazycoder.toml";
+static SESSIONS_DIR: &str = "sessions";
+static SETTINGS_FILE: &str = "settings.toml";
+static DEFAULT_SESSION: &str = "default";
+static VAR_ENV_PREFIX: &str = "LAZYCODER_VAR_";
+static ESPANSO_CURSOR_HINT: &str = "$|$";
+/// Version of the schema of the configuration file, increased when a change requires migrating older files.
+const SCHEMA_VERSION: u32 = 1;
+/// Upgrades of the configuration file, where the one at index `n` upgrades from version `n` to `n + 1`.
+const MIGRATIONS: [fn(&mut toml::Table); SCHEMA_VERSION as usize] = [
+    // Files from before versioning only lack the version, that is set after migrating.
+    |_| {},
+];
 
 /// `LazyCoder` configuration.
-#[derive(Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Deserialize, Serialize, PartialEq, Default, Debug)]
 pub struct Config {
-    file_path: String,
+    schema_version: SchemaVersion,
+    /// Path of the deck, relative to the root of the project if the state is project-local and the deck is in it.
+    #[serde(with = "crate::state_file::stored_path")]
+    file_path: PathBuf,
     position: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    format: Option<DeckFormat>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    vars: BTreeMap<String, String>,
+    /// Identity of the last provided snippet, used to keep the position when the deck changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    anchor: Option<SnippetAnchor>,
+    /// Whether snippets are provided from the copy of the deck taken by `freeze`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    frozen: bool,
+    /// Hash of the contents of the deck when it was last found, used to find it if it is moved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deck_hash: Option<String>,
+    /// Modification time, in nanoseconds since the epoch, and size of the deck when its hash was computed, so it is only
+    /// computed again when they change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deck_stamp: Option<(u64, u64)>,
+    /// Name of the session, or `None` for the default one.
+    #[serde(skip)]
+    session: Option<String>,
+    /// Lock on the state, held while the configuration is read, modified, and saved.
+    #[serde(skip)]
+    lock: Option<StateLock>,
+    /// Settings of the user, that aren't part of the state.
+    #[serde(skip)]
+    settings: Settings,
+    /// Root of the project if the state is project-local.
+    #[serde(skip)]
+    root: Option<PathBuf>,
+}
+
+/// Version of the schema of a configuration file. Files without it are from before versioning, i.e. version 0.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Copy, Debug)]
+#[serde(transparent)]
+struct SchemaVersion(u32);
+
+impl Default for SchemaVersion {
+    fn default() -> Self {
+        SchemaVersion(SCHEMA_VERSION)
+    }
+}
+
+/// Version of the schema, the only field read before migrating a configuration file.
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default)]
+    schema_version: u32,
+}
+
+/// Identity of a snippet: its id if it has one, or the hash of its text.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+enum SnippetAnchor {
+    Id(String),
+    Hash(String),
+}
+
+impl SnippetAnchor {
+    fn of(snippet: &Snippet) -> SnippetAnchor {
+        match &snippet.metadata.id {
+            Some(id) => SnippetAnchor::Id(id.clone()),
+            None => SnippetAnchor::Hash(deck::content_hash(snippet.text.as_bytes())),
+        }
+    }
+}
+
+impl fmt::Display for SnippetAnchor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnippetAnchor::Id(id) => write!(f, "with id {id:?}"),
+            SnippetAnchor::Hash(hash) => write!(f, "with hash {hash}"),
+        }
+    }
 }
 
 #[cfg_attr(test, automock)]
@@ -32,14 +146,41 @@ impl Config {
     /// # Arguments
     ///
     /// * `path` - path to the file with the snippets that will be stored in the configuration.
-    pub fn new(path: &Path) -> Result<Self, LazyCoderError> {
+    /// * `format` - format of the file with the snippets. If not provided, it is detected from the file extension.
+    /// * `vars` - values of the template variables, that take precedence over the environment and the deck.
+    /// * `session` - name of the session, that becomes the active one. If not provided, the active session is used.
+    pub fn new(
+        path: &Path,
+        format: Option<DeckFormat>,
+        vars: BTreeMap<String, String>,
+        session: Option<String>,
+    ) -> Result<Self, LazyCoderError> {
+        let settings = load_settings()?;
+        let session = resolve_session(session, &settings)?;
+        let lock = lock_state()?;
+        let root = project_root();
         if let Ok(absolute_path) = canonicalize(path) {
             debug!("{} does exist", absolute_path.display());
             let new_config = Config {
-                file_path: absolute_path.to_str().unwrap().to_string(),
+                schema_version: SchemaVersion::default(),
+                file_path: project_relative(&absolute_path, root.as_deref()),
                 position: 0,
+                format,
+                vars,
+                anchor: None,
+                frozen: false,
+                deck_hash: file_hash(&absolute_path),
+                deck_stamp: modified_and_size(&absolute_path),
+                session,
+                lock,
+                settings,
+                root,
             };
             new_config.save(true)?;
+            set_active_session(new_config.session.as_deref())?;
+            let journal = new_config.journal();
+            let from = journal.position().unwrap_or(0);
+            new_config.record(journal, Operation::Start, from);
             Ok(new_config)
         } else {
             error!("{} doesn't exist", path.display());
@@ -49,74 +190,679 @@ impl Config {
 
     /// Creates a configuration from the file if it exists.
     ///
-    /// Configuration is stored in a file following the standards for each operating system.
-    pub fn from_file() -> Result<Self, LazyCoderError> {
-        if let Some(mut config_file) = config_dir() {
-            config_file.push(FILE_NAME);
+    /// The position is stored in the `.lazycoder` directory of the project that contains the working directory, if
+    /// any, or in a file in the state directory following the standards for each operating system. Files with an older
+    /// schema are migrated, keeping a backup of the original. If the deck is no longer in its path, it fails
+    /// suggesting where it may have moved. If the deck has changed and the last provided snippet has moved, the
+    /// position is updated to follow it. If its id is no longer in the deck, it fails, and if its text has been
+    /// edited, the position is kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - name of the session. If not provided, the active session is used.
+    pub fn from_file(session: Option<String>) -> Result<Self, LazyCoderError> {
+        let mut cfg = Config::load(session)?;
+        cfg.check_deck()?;
+        cfg.reanchor()?;
+        Ok(cfg)
+    }
+
+    /// Makes the session use the deck at its new path, keeping the position, and returns that path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - new path of the deck. If not provided, the deck is looked for near its previous path by the hash of
+    ///   its contents, and used if there is only one match.
+    /// * `session` - name of the session. If not provided, the active session is used.
+    pub fn relink(
+        path: Option<PathBuf>,
+        session: Option<String>,
+    ) -> Result<PathBuf, LazyCoderError> {
+        let mut cfg = Config::load(session)?;
+        let new_path = match path {
+            Some(path) => canonicalize(path).map_err(|_| LazyCoderError::SnippetFileNotFound)?,
+            None => match <[PathBuf; 1]>::try_from(cfg.moved_deck_candidates()) {
+                Ok([found]) => found,
+                Err(candidates) => {
+                    return Err(LazyCoderError::DeckMoved {
+                        path: cfg.deck_path(),
+                        candidates,
+                    });
+                }
+            },
+        };
+        info!(
+            "Relinking {} to {}",
+            cfg.deck_path().display(),
+            new_path.display()
+        );
+        let mut journal = cfg.journal();
+        cfg.file_path = project_relative(&new_path, cfg.root.as_deref());
+        cfg.deck_hash = file_hash(&new_path);
+        cfg.deck_stamp = modified_and_size(&new_path);
+        journal.moved_to(&cfg.file_path);
+        if cfg.frozen {
+            freeze_deck(
+                &new_path,
+                cfg.session.as_deref(),
+                cfg.deck_format(),
+                &cfg.settings.deck_defaults(),
+            )?;
+        }
+        match cfg.reanchor() {
+            Err(LazyCoderError::SnippetNotFound(_)) => cfg.anchor = cfg.anchor_at_position(),
+            result => result?,
+        }
+        cfg.save(false)?;
+        cfg.save_journal(&journal);
+        Ok(new_path)
+    }
+
+    /// Reads the configuration of the session from its file, without checking the deck.
+    fn load(session: Option<String>) -> Result<Self, LazyCoderError> {
+        let settings = load_settings()?;
+        let session = resolve_session(session, &settings)?;
+        let lock = lock_state()?;
+        if let Some(config_file) = session_path(session.as_deref()) {
             debug!(
                 "Reading configuration from file {}",
                 config_file.as_path().display()
             );
-            let toml_text = read_to_string(config_file)?;
-            let cfg: Config = toml::from_str(&toml_text)?;
-            // TODO: Check that the file_path is stil valid?
+            let toml_text = read_to_string(&config_file).map_err(|err| match &session {
+                Some(name) if err.kind() == io::ErrorKind::NotFound => {
+                    LazyCoderError::SessionNotFound(name.clone())
+                }
+                _ => LazyCoderError::from(err),
+            })?;
+            let mut cfg = Config::parse(&toml_text, &config_file)?;
+            cfg.session = session;
+            cfg.lock = lock;
+            cfg.settings = settings;
+            cfg.root = project_root();
             Ok(cfg)
         } else {
             Err(LazyCoderError::ConfigDirError)
         }
     }
 
+    /// Names of the sessions, with the default one first, and whether each one is the active one.
+    pub fn sessions() -> Result<Vec<(String, bool)>, LazyCoderError> {
+        let dir = state_dir().ok_or(LazyCoderError::ConfigDirError)?;
+        let active = display_name(resolve_session(None, &load_settings()?)?);
+        let mut names = vec![DEFAULT_SESSION.to_owned()];
+        names.extend(session_names(&dir.join(SESSIONS_DIR))?);
+        Ok(names
+            .into_iter()
+            .map(|name| {
+                let is_active = name == active;
+                (name, is_active)
+            })
+            .collect())
+    }
+
+    /// Makes the session the active one, used when no session is provided.
+    pub fn switch_session(name: String) -> Result<(), LazyCoderError> {
+        let session = session_name(name)?;
+        let _lock = lock_state()?;
+        let path = session_path(session.as_deref()).ok_or(LazyCoderError::ConfigDirError)?;
+        if !path_exists(path) {
+            return Err(LazyCoderError::SessionNotFound(display_name(session)));
+        }
+        Ok(set_active_session(session.as_deref())?)
+    }
+
+    /// Changes the name of a session, keeping it active if it was.
+    pub fn rename_session(from: String, to: String) -> Result<(), LazyCoderError> {
+        let from = session_name(from)?;
+        let to = session_name(to)?;
+        let _lock = lock_state()?;
+        let from_path = session_path(from.as_deref()).ok_or(LazyCoderError::ConfigDirError)?;
+        let to_path = session_path(to.as_deref()).ok_or(LazyCoderError::ConfigDirError)?;
+        if path_exists(&to_path) {
+            return Err(LazyCoderError::SessionExists(display_name(to)));
+        }
+        if let Some(session_dir) = to_path.parent()
+            && !path_exists(session_dir)
+        {
+            create_dir_all(session_dir)?;
+        }
+        rename(from_path, to_path).map_err(|err| session_error(err, from.clone()))?;
+        rename_journal(from.as_deref(), to.as_deref());
+        rename_frozen_deck(from.as_deref(), to.as_deref());
+        if resolve_session(None, &load_settings()?)? == from {
+            set_active_session(to.as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// Removes a session. If it was the active one, the default session becomes active.
+    pub fn delete_session(name: String) -> Result<(), LazyCoderError> {
+        let session = session_name(name)?;
+        let _lock = lock_state()?;
+        let path = session_path(session.as_deref()).ok_or(LazyCoderError::ConfigDirError)?;
+        remove_file(path).map_err(|err| session_error(err, session.clone()))?;
+        remove_journal(session.as_deref());
+        remove_frozen_deck(session.as_deref());
+        if resolve_session(None, &load_settings()?)? == session {
+            set_active_session(None)?;
+        }
+        Ok(())
+    }
+
+    /// Uses the global state even if the working directory is in a project with its own state.
+    pub fn use_global_state() {
+        use_global_state();
+    }
+
+    /// Uses the directory for the settings, and for the state unless a project, `LAZYCODER_STATE_DIR` or
+    /// `LAZYCODER_STATE` provides one.
+    pub fn use_config_dir(dir: PathBuf) {
+        use_config_dir(dir);
+    }
+
+    /// Paths of the files and directories used for the session, with their names.
+    pub fn paths(session: Option<String>) -> Result<Vec<(&'static str, PathBuf)>, LazyCoderError> {
+        let config_dir = config_dir().ok_or(LazyCoderError::ConfigDirError)?;
+        let state_dir = state_dir().ok_or(LazyCoderError::ConfigDirError)?;
+        let session = resolve_session(session, &load_settings()?)?;
+        let session_path =
+            session_path(session.as_deref()).ok_or(LazyCoderError::ConfigDirError)?;
+        let mut paths = vec![
+            ("config_dir", config_dir.clone()),
+            ("settings", config_dir.join(SETTINGS_FILE)),
+            ("state_dir", state_dir),
+            ("session", session_path),
+        ];
+        if let Some(root) = project_root() {
+            paths.push(("project", root));
+        }
+        Ok(paths)
+    }
+
+    /// Settings of the user.
+    pub fn settings() -> Result<Settings, LazyCoderError> {
+        load_settings()
+    }
+
+    /// Changes the value of a setting.
+    pub fn set_setting(key: &str, value: &str) -> Result<(), LazyCoderError> {
+        change_setting(key, Some(value))
+    }
+
+    /// Removes a setting, so its default is used.
+    pub fn unset_setting(key: &str) -> Result<(), LazyCoderError> {
+        change_setting(key, None)
+    }
+
     /// Read snippet from the file in the configuration, increment position, and update config file.
     pub fn next(&mut self) -> Result<String, LazyCoderError> {
-        let path = PathBuf::from(self.file_path.clone());
-        let snippet_prov = get_snippet_provider(&path)?;
-        let snippet = snippet_prov.get_snippet(self.position)?;
+        let snippet_prov = self.snippet_provider()?;
+        let from = self.position;
+        let Some(snippet) = self.fetch_snippet(snippet_prov.as_ref())? else {
+            return self.fallback_text(snippet_prov.as_ref());
+        };
+        let text = self.render(&snippet, ESPANSO_CURSOR_HINT, snippet_prov.as_ref())?;
+        let text = self.settings.transform(text);
         self.position += 1;
+        self.anchor = Some(SnippetAnchor::of(&snippet));
         self.save(false)?;
-        Ok(snippet)
+        self.record(self.journal(), Operation::Next, from);
+        log_metadata(&snippet);
+        Ok(text)
     }
 
     /// Read snippet from the file in the configuration without updating the config file.
     pub fn peek(&mut self) -> Result<String, LazyCoderError> {
-        let path = PathBuf::from(self.file_path.clone());
-        let snippet_prov = get_snippet_provider(&path)?;
-        let snippet = snippet_prov.get_snippet(self.position)?;
-        Ok(snippet)
+        let snippet_prov = self.snippet_provider()?;
+        let Some(snippet) = self.fetch_snippet(snippet_prov.as_ref())? else {
+            return self.fallback_text(snippet_prov.as_ref());
+        };
+        let text = self.render(&snippet, "", snippet_prov.as_ref())?;
+        log_metadata(&snippet);
+        Ok(self.settings.transform(text))
+    }
+
+    /// Position in the deck, with its number of snippets and previews of the next ones.
+    pub fn status(&self) -> Result<DeckStatus, LazyCoderError> {
+        let snippet_prov = self.snippet_provider()?;
+        let total = snippet_prov.snippet_count()?;
+        let deck_settings = snippet_prov.settings()?;
+        let upcoming = (self.position..total.min(self.position.saturating_add(PREVIEW_COUNT)))
+            .map(|position| {
+                let snippet = snippet_prov.get_snippet(position)?;
+                let text = self.preview_text(&snippet, &deck_settings);
+                Ok(SnippetPreview::of(position, &snippet, &text))
+            })
+            .collect::<Result<Vec<_>, LazyCoderError>>()?;
+        Ok(DeckStatus {
+            deck: self.deck_path(),
+            position: self.position,
+            total,
+            next_title: upcoming.first().and_then(|snippet| snippet.title.clone()),
+            upcoming,
+        })
+    }
+
+    /// Summaries of all the snippets of the deck, marking the next one.
+    pub fn list(&self) -> Result<Vec<SnippetSummary>, LazyCoderError> {
+        let snippet_prov = self.snippet_provider()?;
+        let snippets = snippet_prov.snippets()?;
+        let deck_settings = snippet_prov.settings()?;
+        Ok(snippets
+            .iter()
+            .enumerate()
+            .map(|(position, snippet)| {
+                let text = self.preview_text(snippet, &deck_settings);
+                SnippetSummary::of(position, snippet, &text, position == self.position)
+            })
+            .collect())
     }
 
     /// Change the configuration file to point to a snippet that is `count` forward.
+    ///
+    /// The position can't go past the end of the deck unless the end-of-deck policy wraps it to the beginning.
     pub fn forward(&mut self, count: usize) -> Result<(), LazyCoderError> {
-        self.position += count;
-        self.save(false)
+        let from = self.position;
+        self.position = self.forward_position(count)?;
+        self.anchor = self.anchor_at_position();
+        self.save(false)?;
+        self.record(self.journal(), Operation::Forward, from);
+        Ok(())
+    }
+
+    /// Change the configuration file to point to the snippet of the target, that must be in the deck.
+    pub fn goto(&mut self, target: &GotoTarget) -> Result<(), LazyCoderError> {
+        let snippets = self.snippet_provider()?.snippets()?;
+        let from = self.position;
+        self.position = target.resolve(self.position, &snippets)?;
+        self.anchor = self.anchor_at_position();
+        self.save(false)?;
+        self.record(self.journal(), Operation::Goto, from);
+        Ok(())
     }
 
     /// Change the configuration file to point to a snippet that is `count` backward.
     pub fn rewind(&mut self, count: usize) -> Result<(), LazyCoderError> {
         if count <= self.position {
+            let from = self.position;
             self.position -= count;
-            self.save(false)
+            self.anchor = self.anchor_at_position();
+            self.save(false)?;
+            self.record(self.journal(), Operation::Rewind, from);
+            Ok(())
         } else {
             Err(LazyCoderError::OperationOutOfRange)
         }
     }
 
+    /// Restores the position before the last change recorded in the journal.
+    pub fn undo(&mut self) -> Result<(), LazyCoderError> {
+        let mut journal = self.journal();
+        self.position = journal.undo().ok_or(LazyCoderError::NothingToUndo)?.from;
+        self.anchor = self.anchor_at_position();
+        self.save(false)?;
+        self.save_journal(&journal);
+        Ok(())
+    }
+
+    /// Repeats the last change of the position that was undone.
+    pub fn redo(&mut self) -> Result<(), LazyCoderError> {
+        let mut journal = self.journal();
+        self.position = journal.redo().ok_or(LazyCoderError::NothingToRedo)?.to;
+        self.anchor = self.anchor_at_position();
+        self.save(false)?;
+        self.save_journal(&journal);
+        Ok(())
+    }
+
+    /// Number of snippets provided, i.e. the position of the next one.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Journal of the changes of the position in the current deck.
+    pub fn history(&self) -> PositionJournal {
+        self.journal()
+    }
+
+    /// Takes a copy of the deck and provides the snippets from it from now on, so changes to the deck file don't
+    /// affect them.
+    pub fn freeze(&mut self) -> Result<(), LazyCoderError> {
+        freeze_deck(
+            &self.deck_path(),
+            self.session.as_deref(),
+            self.deck_format(),
+            &self.settings.deck_defaults(),
+        )?;
+        self.frozen = true;
+        self.save(false)
+    }
+
+    /// Takes a new copy of the frozen deck and returns the positions of the snippets that have changed.
+    ///
+    /// The position follows the last provided snippet if it has moved, and is kept if it has changed.
+    pub fn refresh(&mut self) -> Result<Vec<usize>, LazyCoderError> {
+        if !self.frozen {
+            return Err(LazyCoderError::DeckNotFrozen);
+        }
+        let changes = freeze_deck(
+            &self.deck_path(),
+            self.session.as_deref(),
+            self.deck_format(),
+            &self.settings.deck_defaults(),
+        )?;
+        match self.reanchor() {
+            Err(LazyCoderError::SnippetNotFound(_)) => self.anchor = self.anchor_at_position(),
+            result => result?,
+        }
+        self.save(false)?;
+        Ok(changes)
+    }
+
+    /// Decodes the configuration file, migrating it to the current schema if it is older.
+    ///
+    /// The original file is kept with a `.v<version>.bak` extension, and replaced with the migrated one.
+    fn parse(toml_text: &str, config_file: &Path) -> Result<Config, LazyCoderError> {
+        let Versioned { schema_version } = toml::from_str(toml_text)?;
+        if schema_version > SCHEMA_VERSION {
+            return Err(LazyCoderError::UnsupportedSchemaVersion {
+                found: schema_version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+        if schema_version == SCHEMA_VERSION {
+            return Ok(toml::from_str(toml_text)?);
+        }
+        let mut table: toml::Table = toml::from_str(toml_text)?;
+        for migration in &MIGRATIONS[schema_version as usize..] {
+            migration(&mut table);
+        }
+        table.insert(
+            String::from("schema_version"),
+            toml::Value::from(SCHEMA_VERSION),
+        );
+        let cfg = Config::deserialize(table)?;
+        let backup = config_file.with_extension(format!("toml.v{schema_version}.bak"));
+        copy(config_file, &backup)?;
+        write_atomic(
+            config_file,
+            toml::to_string(&cfg).expect("Failing to encode TOML"),
+        )?;
+        info!(
+            "Configuration migrated from schema version {schema_version} to {SCHEMA_VERSION}, original kept in {}",
+            backup.display()
+        );
+        Ok(cfg)
+    }
+
+    /// Journal of the session, or an empty one if it was recorded for another deck.
+    fn journal(&self) -> PositionJournal {
+        let journal = load_journal(self.session.as_deref());
+        if journal.file_path() == self.file_path {
+            journal
+        } else {
+            PositionJournal::new(&self.file_path)
+        }
+    }
+
+    /// Records the change from the previous position to the current one in the journal.
+    fn record(&self, mut journal: PositionJournal, operation: Operation, from: usize) {
+        journal.record(operation, from, self.position);
+        self.save_journal(&journal);
+    }
+
+    /// Saves the journal of the session. Failing to do it doesn't affect the command.
+    fn save_journal(&self, journal: &PositionJournal) {
+        if let Err(err) = save_journal(self.session.as_deref(), journal) {
+            warn!("Failed to save position history: {err}");
+        }
+    }
+
+    /// Provider of the snippets of the deck, or of its frozen copy.
+    fn snippet_provider(&self) -> Result<Box<dyn SnippetProvider>, LazyCoderError> {
+        get_snippet_provider(
+            &self.deck_path(),
+            self.session.as_deref(),
+            self.deck_format(),
+            self.frozen,
+            &self.settings.deck_defaults(),
+        )
+    }
+
+    /// Identity of the snippet before the current position, i.e. the last provided one, if it can be obtained.
+    fn anchor_at_position(&self) -> Option<SnippetAnchor> {
+        let previous = self.position.checked_sub(1)?;
+        let snippet_prov = self.snippet_provider().ok()?;
+        let snippet = snippet_prov.get_snippet(previous).ok()?;
+        Some(SnippetAnchor::of(&snippet))
+    }
+
+    /// Moves the position after the last provided snippet if the deck has changed and it isn't where it was.
+    ///
+    /// If the snippet appears several times in the deck, the closest one to the previous position is used. If a snippet
+    /// with an id isn't in the deck anymore, it fails, since the position may point to a different part of the talk. If
+    /// a snippet without one isn't, its text has been edited, so the position is kept and the snippet now before it
+    /// becomes the last provided one. The new position is saved by the next command that changes it.
+    fn reanchor(&mut self) -> Result<(), LazyCoderError> {
+        let Some(anchor) = &self.anchor else {
+            return Ok(());
+        };
+        let Some(previous) = self.position.checked_sub(1) else {
+            return Ok(());
+        };
+        let snippet_prov = self.snippet_provider()?;
+        if let Ok(snippet) = snippet_prov.get_snippet(previous)
+            && SnippetAnchor::of(&snippet) == *anchor
+        {
+            return Ok(());
+        }
+        // The whole deck is read once, instead of getting each snippet from the index.
+        let snippets = snippet_prov.snippets()?;
+        let mut found = None;
+        for (position, snippet) in snippets.iter().enumerate() {
+            if SnippetAnchor::of(snippet) == *anchor
+                && found.is_none_or(|found: usize| {
+                    position.abs_diff(previous) < found.abs_diff(previous)
+                })
+            {
+                found = Some(position);
+            }
+        }
+        let Some(found) = found else {
+            if let SnippetAnchor::Id(_) = anchor {
+                return Err(LazyCoderError::SnippetNotFound(anchor.to_string()));
+            }
+            warn!(
+                "Deck has changed and snippet {anchor} is no longer in it, keeping position {}",
+                self.position
+            );
+            self.anchor = snippets.get(previous).map(SnippetAnchor::of);
+            return Ok(());
+        };
+        info!("Deck has changed, snippet {anchor} moved from position {previous} to {found}");
+        self.position = found + 1;
+        Ok(())
+    }
+
+    /// Fails if the deck is no longer in its path, unless the snippets are provided from a frozen copy. Otherwise,
+    /// records the hash of its contents, to find it if it is moved later. The hash is only computed again if the
+    /// modification time or the size of the deck have changed.
+    fn check_deck(&mut self) -> Result<(), LazyCoderError> {
+        let path = self.deck_path();
+        if self.frozen {
+            Ok(())
+        } else if path_exists(&path) {
+            let stamp = modified_and_size(&path);
+            if (self.deck_hash.is_none() || stamp.is_none() || stamp != self.deck_stamp)
+                && let Some(hash) = file_hash(&path)
+            {
+                self.deck_hash = Some(hash);
+                self.deck_stamp = stamp;
+            }
+            Ok(())
+        } else {
+            error!("{} doesn't exist", path.display());
+            Err(LazyCoderError::DeckMoved {
+                path,
+                candidates: self.moved_deck_candidates(),
+            })
+        }
+    }
+
+    /// Files near the previous path of the deck with the same contents it had when it was last found.
+    fn moved_deck_candidates(&self) -> Vec<PathBuf> {
+        self.deck_hash
+            .as_deref()
+            .map(|hash| find_deck(&self.deck_path(), hash))
+            .unwrap_or_default()
+    }
+
+    /// Format of the deck, either explicitly set or detected from the extension of its file.
+    fn deck_format(&self) -> DeckFormat {
+        self.format
+            .unwrap_or_else(|| DeckFormat::from_path(&self.file_path))
+    }
+
+    /// Path of the deck, resolved from the root of the project if it is relative to it.
+    pub fn deck_path(&self) -> PathBuf {
+        match &self.root {
+            Some(root) => root.join(&self.file_path),
+            None => PathBuf::from(&self.file_path),
+        }
+    }
+
+    /// Obtains the snippet at the current position, applying the end-of-deck policy past the last one.
+    ///
+    /// Returns `None` if the fallback text has to be provided instead of a snippet.
+    fn fetch_snippet(
+        &mut self,
+        snippet_prov: &dyn SnippetProvider,
+    ) -> Result<Option<Snippet>, LazyCoderError> {
+        match snippet_prov.get_snippet(self.position) {
+            Err(LazyCoderError::RunOutOfSnippets) if self.position > 0 => {
+                match snippet_prov.settings()?.end_of_deck {
+                    EndOfDeck::Error => Err(LazyCoderError::RunOutOfSnippets),
+                    EndOfDeck::Wrap => {
+                        debug!("End of deck reached, wrapping to the first snippet");
+                        self.position = 0;
+                        snippet_prov.get_snippet(0).map(Some)
+                    }
+                    EndOfDeck::Stay => {
+                        debug!("End of deck reached, staying on the last snippet");
+                        self.position = snippet_prov
+                            .snippet_count()?
+                            .checked_sub(1)
+                            .ok_or(LazyCoderError::RunOutOfSnippets)?;
+                        snippet_prov.get_snippet(self.position).map(Some)
+                    }
+                    EndOfDeck::Fallback => Ok(None),
+                }
+            }
+            result => result.map(Some),
+        }
+    }
+
+    /// Fallback text of the deck, provided past its last snippet without changing the position.
+    fn fallback_text(&self, snippet_prov: &dyn SnippetProvider) -> Result<String, LazyCoderError> {
+        debug!("End of deck reached, providing the fallback text");
+        let text = snippet_prov.settings()?.fallback_text.unwrap_or_default();
+        Ok(self.settings.transform(text))
+    }
+
+    /// Position `count` snippets forward, limited by the end of the deck unless the end-of-deck policy wraps it.
+    fn forward_position(&self, count: usize) -> Result<usize, LazyCoderError> {
+        let snippet_prov = self.snippet_provider()?;
+        let length = snippet_prov.snippet_count()?;
+        let position = self.position.saturating_add(count);
+        if position <= length {
+            return Ok(position);
+        }
+        match snippet_prov.settings()?.end_of_deck {
+            EndOfDeck::Error => Err(LazyCoderError::OperationOutOfRange),
+            EndOfDeck::Wrap => position
+                .checked_rem(length)
+                .ok_or(LazyCoderError::OperationOutOfRange),
+            EndOfDeck::Stay | EndOfDeck::Fallback => Ok(length),
+        }
+    }
+
+    /// Text of the snippet with its variables expanded and the position of the cursor replaced with `cursor_hint`.
+    fn render(
+        &self,
+        snippet: &Snippet,
+        cursor_hint: &str,
+        snippet_prov: &dyn SnippetProvider,
+    ) -> Result<String, LazyCoderError> {
+        let Some(cursor) = snippet.cursor else {
+            return self.expand_variables(&snippet.text, snippet_prov);
+        };
+        let (before, after) = snippet.text.split_at(cursor);
+        Ok(format!(
+            "{}{cursor_hint}{}",
+            self.expand_variables(before, snippet_prov)?,
+            self.expand_variables(after, snippet_prov)?
+        ))
+    }
+
+    /// Substitutes the template variables of the text of a snippet, if the deck enables them.
+    ///
+    /// Values are looked up in the configuration, then in the `LAZYCODER_VAR_*` environment variables, and finally in
+    /// the deck header.
+    fn expand_variables(
+        &self,
+        text: &str,
+        snippet_prov: &dyn SnippetProvider,
+    ) -> Result<String, LazyCoderError> {
+        if !template::has_placeholders(text) {
+            return Ok(text.to_owned());
+        }
+        self.expand_with(text, &snippet_prov.settings()?)
+    }
+
+    /// Substitutes the template variables of the text with the settings of the deck, if they enable them.
+    fn expand_with(
+        &self,
+        text: &str,
+        deck_settings: &DeckSettings,
+    ) -> Result<String, LazyCoderError> {
+        if !deck_settings.templates {
+            return Ok(text.to_owned());
+        }
+        let deck_vars = &deck_settings.vars;
+        template::expand(text, |name| {
+            self.vars
+                .get(name)
+                .cloned()
+                .or_else(|| env_var(&format!("{VAR_ENV_PREFIX}{name}")))
+                .or_else(|| env_var(&format!("{VAR_ENV_PREFIX}{}", name.to_uppercase())))
+                .or_else(|| deck_vars.get(name).cloned())
+        })
+    }
+
+    /// Text of a snippet as it would be provided, to preview it. Variables without a value are shown as placeholders,
+    /// since previewing a snippet shouldn't fail.
+    fn preview_text(&self, snippet: &Snippet, deck_settings: &DeckSettings) -> String {
+        self.expand_with(&snippet.text, deck_settings)
+            .unwrap_or_else(|_| snippet.text.clone())
+    }
+
     /// Saves this configuration to the standard path and file.
     fn save(&self, create_dir: bool) -> Result<(), LazyCoderError> {
         let toml_text = toml::to_string(&self).expect("Failing to encode TOML");
-        if let Some(mut config_path) = config_dir() {
-            if !path_exists(&config_path) {
+        if let Some(config_path) = session_path(self.session.as_deref()) {
+            let session_dir = config_path.parent().unwrap_or(Path::new("."));
+            if !path_exists(session_dir) {
                 if create_dir {
-                    create_dir_all(config_path.clone())?;
+                    create_dir_all(session_dir)?;
                 } else {
                     return Err(LazyCoderError::ConfigDirError);
                 }
             }
-            config_path.push(FILE_NAME);
             debug!(
                 "Writing configuration to file {}",
                 config_path.as_path().display()
             );
-            write(config_path, toml_text)?;
+            write_atomic(config_path, toml_text)?;
             Ok(())
         } else {
             Err(LazyCoderError::ConfigDirError)
@@ -124,350 +870,2502 @@ impl Config {
     }
 }
 
-#[cfg(not(test))]
-mod aux {
-    use std::path::{Path, PathBuf};
+/// Changes the value of a setting in the settings file, or unsets it if the value is `None`.
+fn change_setting(key: &str, value: Option<&str>) -> Result<(), LazyCoderError> {
+    if key == "default_session"
+        && let Some(name) = value
+    {
+        session_name(name.to_owned())?;
+    }
+    let _lock = lock_state()?;
+    let mut settings = load_settings()?;
+    settings.set(key, value)?;
+    save_settings(&settings)
+}
 
-    use directories::ProjectDirs;
+/// Validates a session name. The default session is `None`.
+fn session_name(name: String) -> Result<Option<String>, LazyCoderError> {
+    if name == DEFAULT_SESSION {
+        Ok(None)
+    } else if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(Some(name))
+    } else {
+        Err(LazyCoderError::InvalidSessionName(name))
+    }
+}
 
-    use crate::{
-        lazy_coder_error::LazyCoderError,
-        snippet_handler::{SnippetHandler, SnippetProvider},
-    };
+/// Session to use: the provided one or, if none, the active one or the default one of the settings.
+fn resolve_session(
+    session: Option<String>,
+    settings: &Settings,
+) -> Result<Option<String>, LazyCoderError> {
+    match session
+        .or_else(active_session)
+        .or_else(|| settings.default_session.clone())
+    {
+        Some(name) => session_name(name),
+        None => Ok(None),
+    }
+}
 
-    pub(crate) fn config_dir() -> Option<PathBuf> {
-        Some(
-            ProjectDirs::from("dev", "jorgeortiz", "lazycoder")?
-                .config_dir()
-                .to_path_buf(),
-        )
+/// Path of a deck to store, relative to the root of the project if it is in it.
+fn project_relative(path: &Path, root: Option<&Path>) -> PathBuf {
+    root.and_then(|root| path.strip_prefix(root).ok())
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// Path of the configuration file of a session, relative to the state directory.
+fn session_file(session: Option<&str>) -> PathBuf {
+    match session {
+        Some(name) => Path::new(SESSIONS_DIR).join(format!("{name}.toml")),
+        None => PathBuf::from(FILE_NAME),
     }
+}
 
-    pub fn get_snippet_provider(path: &Path) -> Result<Box<dyn SnippetProvider>, LazyCoderError> {
-        Ok(Box::new(SnippetHandler::new(path)?))
+/// Name of the file of the frozen copy of the deck of a session, that is different for each session file, so sessions
+/// using the same deck don't share their copies.
+fn frozen_deck_file(session_path: &Path) -> String {
+    format!(
+        "frozen-{}.toml",
+        deck::content_hash(session_path.as_os_str().as_encoded_bytes())
+    )
+}
+
+/// Name of a session as shown to the user.
+fn display_name(session: Option<String>) -> String {
+    session.unwrap_or_else(|| DEFAULT_SESSION.to_owned())
+}
+
+/// Error for a failed operation on the file of a session.
+fn session_error(err: io::Error, session: Option<String>) -> LazyCoderError {
+    if err.kind() == io::ErrorKind::NotFound {
+        LazyCoderError::SessionNotFound(display_name(session))
+    } else {
+        LazyCoderError::ConfigFileError(err)
     }
+}
 
-    pub fn path_exists<P: AsRef<Path>>(path: P) -> bool {
-        path.as_ref().exists()
+/// Logs the metadata of the snippet, so the speaker notes are available when running with a verbose level.
+fn log_metadata(snippet: &Snippet) {
+    let metadata = &snippet.metadata;
+    if let Some(title) = &metadata.title {
+        info!("Snippet: {title}");
+    }
+    if let Some(notes) = &metadata.notes {
+        info!("Notes: {notes}");
     }
+    debug!("Snippet id: {:?}, tags: {:?}", metadata.id, metadata.tags);
 }
 
-#[cfg(test)]
-mod tests {
-    use std::{cell::Cell, str::FromStr};
+#[cfg(not(test))]
+mod aux {
+    use std::{
+        env,
+        ffi::OsStr,
+        fs, io,
+        path::{Path, PathBuf},
+        sync::{
+            OnceLock,
+            atomic::{AtomicBool, Ordering},
+        },
+        time::Duration,
+    };
 
-    use mockall::predicate;
+    use directories::ProjectDirs;
+    use log::warn;
 
-    use crate::snippet_handler::{MockSnippetProvider, SnippetProvider};
+    use crate::{
+        deck::{self, DeckFormat, DeckSettings},
+        frozen_deck::FrozenDeck,
+        lazy_coder_error::LazyCoderError,
+        markdown_handler::MarkdownHandler,
+        position_journal::PositionJournal,
+        settings::Settings,
+        snippet_handler::{DeckReader, ReaderShell, SnippetHandler, SnippetProvider},
+        state_file::{StateLock, migrate_dir, write_atomic},
+    };
 
-    use super::*;
+    use super::{
+        DEFAULT_SESSION, FILE_NAME, SESSIONS_DIR, SETTINGS_FILE, frozen_deck_file, session_file,
+    };
 
-    thread_local! {
-        static CANONICALIZE_ANSWER: Cell<Option<PathBuf>> = const { Cell::new(None) };
-        static CONFIG_DIR_ANSWER: Cell<Option<PathBuf>> = const { Cell::new(None) };
-        static CREATE_DIR_OK_ANSWER: Cell<bool> = const { Cell::new(true) };
-        static CREATE_DIR_ALL_ARG: Cell<Option<PathBuf>> = const { Cell::new(None) };
-        static PATH_EXISTS_ANSWER: Cell<bool> = const { Cell::new(true) };
-        static READ_TO_STRING_ANSWER: Cell<Option<String>> = const { Cell::new(None) };
-        static SNIPPET_PROVIDER_ANSWER: Cell<Option<Box<dyn SnippetProvider>>> = const { Cell::new(None) };
-        static WRITE_OK_ANSWER: Cell<bool> = const { Cell::new(true) };
-        static WRITE_ARG_PATH: Cell<Option<PathBuf>> = const { Cell::new(None) };
-        static WRITE_ARG_CONTENTS: Cell<Option<String>> = const { Cell::new(None) };
+    static ACTIVE_SESSION_FILE: &str = "active_session";
+    static CONFIG_DIR_ENV: &str = "LAZYCODER_CONFIG_DIR";
+    /// Levels of subdirectories searched for a moved deck.
+    const SEARCH_DEPTH: usize = 3;
+    static STATE_DIR_ENV: &str = "LAZYCODER_STATE_DIR";
+    /// State file of the default session.
+    static STATE_FILE_ENV: &str = "LAZYCODER_STATE";
+    /// Configuration directory set with `--config-dir`.
+    static CONFIG_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+    /// Directory with the state of a project, at its root.
+    static PROJECT_DIR: &str = ".lazycoder";
+    /// Whether the global state has to be used even in a project.
+    static GLOBAL_STATE: AtomicBool = AtomicBool::new(false);
+    static HISTORY_DIR: &str = "history";
+    static LOCK_FILE: &str = "lazycoder.lock";
+    /// Time to wait for other processes to release the lock on the state.
+    const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn project_dirs() -> Option<ProjectDirs> {
+        ProjectDirs::from("dev", "jorgeortiz", "lazycoder")
     }
 
-    #[test]
-    fn config_new_from_non_existing_path_fails() {
-        CANONICALIZE_ANSWER.set(None);
+    /// Makes the configuration directory `dir`, taking precedence over the `LAZYCODER_CONFIG_DIR` environment
+    /// variable.
+    pub fn use_config_dir(dir: PathBuf) {
+        let _ = CONFIG_DIR_OVERRIDE.set(dir);
+    }
 
-        let sut = Config::new(Path::new(""));
+    /// Path set in the environment variable, if it isn't empty.
+    fn env_path(name: &str) -> Option<PathBuf> {
+        env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    }
 
-        assert!(matches!(sut, Err(LazyCoderError::SnippetFileNotFound)));
+    /// Configuration directory set with `--config-dir` or the environment, if any.
+    fn config_dir_override() -> Option<PathBuf> {
+        CONFIG_DIR_OVERRIDE
+            .get()
+            .cloned()
+            .or_else(|| env_path(CONFIG_DIR_ENV))
     }
 
-    #[test]
-    fn config_new_from_existing_path_is_created() {
-        let mut path = PathBuf::new();
-        path.push("/tmp");
-        CANONICALIZE_ANSWER.set(Some(path));
-        CONFIG_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+    /// Directory for the settings: the one set with `--config-dir` or the environment, or the standard one.
+    pub fn config_dir() -> Option<PathBuf> {
+        config_dir_override().or_else(|| Some(project_dirs()?.config_dir().to_path_buf()))
+    }
 
-        let sut = Config::new(Path::new("/tmp"));
+    /// Takes the lock on the state, in the state directory. There is no lock if there is no directory.
+    ///
+    /// State left in the configuration directory by older versions is moved to the standard state directory while
+    /// the lock is held.
+    pub fn lock_state() -> Result<Option<StateLock>, LazyCoderError> {
+        let Some(dir) = state_dir() else {
+            return Ok(None);
+        };
+        fs::create_dir_all(&dir)?;
+        let lock = StateLock::acquire(&dir.join(LOCK_FILE), LOCK_TIMEOUT)?;
+        if let Some(dirs) = project_dirs()
+            && Some(&dir) == standard_state_dir().as_ref()
+            && dirs.config_dir() != dir
+        {
+            migrate_legacy_state(dirs.config_dir(), &dir);
+        }
+        Ok(Some(lock))
+    }
 
-        assert!(
-            matches!(sut, Ok(ref cfg) if cfg.file_path == "/tmp" && cfg.position == 0),
-            "Wrong config: {sut:?}"
+    /// Moves the state files from the directory used by older versions, once, keeping those that are already in the
+    /// state directory. Indexes are removed, since they are rebuilt.
+    fn migrate_legacy_state(legacy_dir: &Path, dir: &Path) {
+        let result = migrate_dir(
+            legacy_dir,
+            dir,
+            |name| {
+                [FILE_NAME, SESSIONS_DIR, ACTIVE_SESSION_FILE].contains(&name)
+                    || name.starts_with(&format!("{FILE_NAME}.v"))
+            },
+            |name| {
+                name.starts_with("lazycoder-")
+                    && Path::new(name)
+                        .extension()
+                        .is_some_and(|extension| extension == "index")
+            },
         );
+        if let Err(err) = result {
+            warn!("Failed to migrate {}: {err}", legacy_dir.display());
+        }
     }
 
-    #[test]
-    fn config_from_file_fails_with_non_exiting_path_or_directory() {
-        CONFIG_DIR_ANSWER.set(None);
-
-        let sut = Config::from_file();
-
-        assert!(matches!(sut, Err(LazyCoderError::ConfigDirError)));
+    /// Settings of the user. They are the default ones if there is no settings file.
+    pub fn load_settings() -> Result<Settings, LazyCoderError> {
+        config_dir().map_or_else(
+            || Ok(Settings::default()),
+            |dir| Settings::load(&dir.join(SETTINGS_FILE)),
+        )
     }
 
-    #[test]
-    fn config_from_non_exiting_file_fails() {
-        CONFIG_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
-        READ_TO_STRING_ANSWER.set(None);
+    pub fn save_settings(settings: &Settings) -> Result<(), LazyCoderError> {
+        settings.save(
+            &config_dir()
+                .ok_or(LazyCoderError::ConfigDirError)?
+                .join(SETTINGS_FILE),
+        )
+    }
 
-        let sut = Config::from_file();
+    /// Makes [`project_root`] ignore the projects, so the global state is used.
+    pub fn use_global_state() {
+        GLOBAL_STATE.store(true, Ordering::Relaxed);
+    }
 
-        assert!(matches!(sut, Err(LazyCoderError::ConfigFileError(_))));
+    /// Closest ancestor of the working directory, or itself, with a `.lazycoder` directory, unless the global state
+    /// has to be used.
+    pub fn project_root() -> Option<PathBuf> {
+        if GLOBAL_STATE.load(Ordering::Relaxed) {
+            return None;
+        }
+        env::current_dir()
+            .ok()?
+            .ancestors()
+            .find(|dir| dir.join(PROJECT_DIR).is_dir())
+            .map(Path::to_path_buf)
     }
 
-    #[test]
-    fn config_from_file_returns_valid_configuration() {
-        CONFIG_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
-        READ_TO_STRING_ANSWER.set(Some(String::from(
-            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n",
-        )));
+    /// Directory for the state that isn't configuration, in order of precedence: the one set in
+    /// `LAZYCODER_STATE_DIR`, the one of the state file set in `LAZYCODER_STATE`, the `.lazycoder` directory of the
+    /// project, the configuration directory if it has been set, or the standard one.
+    pub(crate) fn state_dir() -> Option<PathBuf> {
+        env_path(STATE_DIR_ENV)
+            .or_else(|| {
+                env_path(STATE_FILE_ENV).map(|path| match path.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                    _ => PathBuf::from("."),
+                })
+            })
+            .or_else(|| Some(project_root()?.join(PROJECT_DIR)))
+            .or_else(config_dir_override)
+            .or_else(standard_state_dir)
+    }
 
-        let sut = Config::from_file();
+    /// Configuration file of a session: the one set in `LAZYCODER_STATE` for the default session, or else the one in
+    /// the state directory.
+    pub fn session_path(session: Option<&str>) -> Option<PathBuf> {
+        match session {
+            None => env_path(STATE_FILE_ENV),
+            Some(_) => None,
+        }
+        .or_else(|| Some(state_dir()?.join(session_file(session))))
+    }
 
-        assert!(matches!(sut, Ok(config) if config == Config {
-                file_path: String::from("/some/path/file.lazycoder"),
+    /// Standard state directory. Platforms without one use the local data directory.
+    fn standard_state_dir() -> Option<PathBuf> {
+        let dirs = project_dirs()?;
+        Some(
+            dirs.state_dir()
+                .unwrap_or_else(|| dirs.data_local_dir())
+                .to_path_buf(),
+        )
+    }
+
+    /// Directory for the frozen copies of the decks: the standard data directory, unless the directories have been
+    /// set, that keeps them in the state directory.
+    fn data_dir() -> Option<PathBuf> {
+        if env_path(STATE_DIR_ENV).is_some()
+            || env_path(STATE_FILE_ENV).is_some()
+            || config_dir_override().is_some()
+        {
+            state_dir()
+        } else {
+            Some(project_dirs()?.data_dir().to_path_buf())
+        }
+    }
+
+    /// Path of the journal of positions of a session in the state directory.
+    fn journal_path(session: Option<&str>) -> Option<PathBuf> {
+        Some(
+            state_dir()?
+                .join(HISTORY_DIR)
+                .join(format!("{}.toml", session.unwrap_or(DEFAULT_SESSION))),
+        )
+    }
+
+    /// Journal of positions of the session. It is empty if it doesn't exist or can't be read.
+    pub fn load_journal(session: Option<&str>) -> PositionJournal {
+        journal_path(session)
+            .and_then(|path| PositionJournal::load(&path).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save_journal(
+        session: Option<&str>,
+        journal: &PositionJournal,
+    ) -> Result<(), LazyCoderError> {
+        journal.save(&journal_path(session).ok_or(LazyCoderError::ConfigDirError)?)
+    }
+
+    /// Moves the journal of positions with its session. A missing journal is ignored.
+    pub fn rename_journal(from: Option<&str>, to: Option<&str>) {
+        if let (Some(from), Some(to)) = (journal_path(from), journal_path(to)) {
+            let _ = fs::rename(from, to);
+        }
+    }
+
+    /// Removes the journal of positions of a deleted session. A missing journal is ignored.
+    pub fn remove_journal(session: Option<&str>) {
+        if let Some(path) = journal_path(session) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// Path of the frozen copy of the deck of the session in the data directory.
+    fn frozen_deck_path(session: Option<&str>) -> Result<PathBuf, LazyCoderError> {
+        let session_path = session_path(session).ok_or(LazyCoderError::ConfigDirError)?;
+        let dir = data_dir().ok_or(LazyCoderError::ConfigDirError)?;
+        Ok(dir.join(frozen_deck_file(&session_path)))
+    }
+
+    /// Frozen copy of the deck of the session, or else the copy of the deck that was shared by all the sessions before
+    /// each one had its own.
+    fn load_frozen_deck(path: &Path, session: Option<&str>) -> Result<FrozenDeck, LazyCoderError> {
+        FrozenDeck::load(&frozen_deck_path(session)?).or_else(|err| {
+            let legacy_path = data_dir().map(|dir| {
+                dir.join(format!(
+                    "frozen-{}.toml",
+                    deck::content_hash(path.as_os_str().as_encoded_bytes())
+                ))
+            });
+            legacy_path
+                .and_then(|legacy_path| FrozenDeck::load(&legacy_path).ok())
+                .ok_or(err)
+        })
+    }
+
+    /// Stores a copy of the deck for the session and returns the positions of the snippets that differ from its
+    /// previous copy.
+    pub fn freeze_deck(
+        path: &Path,
+        session: Option<&str>,
+        format: DeckFormat,
+        defaults: &DeckSettings,
+    ) -> Result<Vec<usize>, LazyCoderError> {
+        let deck = FrozenDeck::import(
+            get_snippet_provider(path, session, format, false, defaults)?.as_ref(),
+        )?;
+        let changes = match load_frozen_deck(path, session) {
+            Ok(previous) => previous.changes(&deck),
+            Err(_) => (0..deck.len()).collect(),
+        };
+        deck.save(&frozen_deck_path(session)?)?;
+        Ok(changes)
+    }
+
+    /// Moves the frozen copy of the deck with its session. A missing copy is ignored.
+    pub fn rename_frozen_deck(from: Option<&str>, to: Option<&str>) {
+        if let (Ok(from), Ok(to)) = (frozen_deck_path(from), frozen_deck_path(to)) {
+            let _ = fs::rename(from, to);
+        }
+    }
+
+    /// Removes the frozen copy of the deck of a deleted session. A missing copy is ignored.
+    pub fn remove_frozen_deck(session: Option<&str>) {
+        if let Ok(path) = frozen_deck_path(session) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    pub fn env_var(name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+
+    /// Name of the active session, if it isn't the default one.
+    pub fn active_session() -> Option<String> {
+        let text = fs::read_to_string(state_dir()?.join(ACTIVE_SESSION_FILE)).ok()?;
+        Some(text.trim().to_owned()).filter(|name| !name.is_empty())
+    }
+
+    /// Stores the name of the active session, or removes it to use the default one.
+    pub fn set_active_session(session: Option<&str>) -> io::Result<()> {
+        let path = state_dir()
+            .ok_or_else(|| io::Error::other("no state directory"))?
+            .join(ACTIVE_SESSION_FILE);
+        match session {
+            Some(name) => write_atomic(path, name),
+            None => match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// Names of the sessions stored in the directory.
+    pub fn session_names(dir: &Path) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(dir) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            result => result?,
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+                && let Some(name) = path.file_stem().and_then(|stem| stem.to_str())
+            {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn get_snippet_provider(
+        path: &Path,
+        session: Option<&str>,
+        format: DeckFormat,
+        frozen: bool,
+        defaults: &DeckSettings,
+    ) -> Result<Box<dyn SnippetProvider>, LazyCoderError> {
+        if frozen {
+            return Ok(Box::new(load_frozen_deck(path, session)?));
+        }
+        let defaults = defaults.clone();
+        match format {
+            DeckFormat::Lazycoder => match state_dir() {
+                Some(dir) => Ok(Box::new(
+                    SnippetHandler::with_index(
+                        path,
+                        dir.join(format!(
+                            "lazycoder-{}.index",
+                            deck::content_hash(path.as_os_str().as_encoded_bytes())
+                        )),
+                    )?
+                    .with_defaults(defaults),
+                )),
+                None => Ok(Box::new(SnippetHandler::new(path)?.with_defaults(defaults))),
+            },
+            DeckFormat::Markdown => Ok(Box::new(
+                MarkdownHandler::new(path)?.with_defaults(defaults),
+            )),
+        }
+    }
+
+    pub fn path_exists<P: AsRef<Path>>(path: P) -> bool {
+        path.as_ref().exists()
+    }
+
+    /// Hash of the contents of the file, if it can be read.
+    pub fn file_hash(path: &Path) -> Option<String> {
+        fs::read(path)
+            .ok()
+            .map(|contents| deck::content_hash(&contents))
+    }
+
+    /// Modification time and size of the file, the same metadata the index of the snippets keeps, if it can be read.
+    pub fn modified_and_size(path: &Path) -> Option<(u64, u64)> {
+        ReaderShell.modified_and_size(path).ok()
+    }
+
+    /// Files with the extension of the missing deck and the hash of its contents, looked for in the parent of its
+    /// closest existing directory and in the working directory, and in their subdirectories.
+    pub fn find_deck(missing: &Path, hash: &str) -> Vec<PathBuf> {
+        let near = missing
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.is_dir())
+            .map(|dir| dir.parent().unwrap_or(dir).to_path_buf());
+        let mut found = Vec::new();
+        for dir in near.into_iter().chain(env::current_dir().ok()) {
+            search_deck(&dir, missing.extension(), hash, SEARCH_DEPTH, &mut found);
+        }
+        let mut found: Vec<_> = found
+            .into_iter()
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .collect();
+        found.sort();
+        found.dedup();
+        found
+    }
+
+    /// Adds the files in the directory with the extension and the hash to `found`, descending `depth` levels into the
+    /// subdirectories that aren't hidden.
+    fn search_deck(
+        dir: &Path,
+        extension: Option<&OsStr>,
+        hash: &str,
+        depth: usize,
+        found: &mut Vec<PathBuf>,
+    ) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                if depth > 0 && !entry.file_name().as_encoded_bytes().starts_with(b".") {
+                    search_deck(&path, extension, hash, depth - 1, found);
+                }
+            } else if path.extension() == extension && file_hash(&path).as_deref() == Some(hash) {
+                found.push(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        path::PathBuf,
+        str::FromStr,
+    };
+
+    use mockall::predicate;
+
+    use crate::{
+        deck::DeckSettings,
+        settings::OutputTransform,
+        snippet_handler::{MockSnippetProvider, SnippetProvider},
+    };
+
+    use super::*;
+
+    thread_local! {
+        static ACTIVE_SESSION_ANSWER: RefCell<Option<String>> = const { RefCell::new(None) };
+        static ACTIVE_SESSION_ARGS: RefCell<Vec<Option<String>>> = const { RefCell::new(Vec::new()) };
+        static CANONICALIZE_ANSWER: Cell<Option<PathBuf>> = const { Cell::new(None) };
+        static STATE_DIR_ANSWER: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+        static COPY_ARGS: Cell<Option<(PathBuf, PathBuf)>> = const { Cell::new(None) };
+        static CREATE_DIR_OK_ANSWER: Cell<bool> = const { Cell::new(true) };
+        static CONFIG_DIR_ANSWER: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+        static CREATE_DIR_ALL_ARG: Cell<Option<PathBuf>> = const { Cell::new(None) };
+        static ENV_VARS_ANSWER: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+        static FILE_HASH_ANSWER: Cell<Option<String>> = const { Cell::new(None) };
+        static MODIFIED_AND_SIZE_ANSWER: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+        static FIND_DECK_ANSWER: Cell<Option<Vec<PathBuf>>> = const { Cell::new(None) };
+        static FREEZE_DECK_ANSWER: Cell<Option<Vec<usize>>> = const { Cell::new(None) };
+        static FREEZE_DECK_SESSION_ARGS: RefCell<Vec<Option<String>>> = const { RefCell::new(Vec::new()) };
+        static JOURNAL_ANSWER: Cell<Option<PositionJournal>> = const { Cell::new(None) };
+        static JOURNAL_ARG: Cell<Option<PositionJournal>> = const { Cell::new(None) };
+        static LOCK_OK_ANSWER: Cell<bool> = const { Cell::new(true) };
+        static PATH_EXISTS_ANSWER: Cell<bool> = const { Cell::new(true) };
+        static PROJECT_ROOT_ANSWER: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+        static READ_TO_STRING_ANSWER: Cell<Option<String>> = const { Cell::new(None) };
+        static REMOVE_FILE_ARG: Cell<Option<PathBuf>> = const { Cell::new(None) };
+        static RENAME_ARGS: Cell<Option<(PathBuf, PathBuf)>> = const { Cell::new(None) };
+        static SESSION_NAMES_ANSWER: Cell<Option<Vec<String>>> = const { Cell::new(None) };
+        static SETTINGS_ANSWER: Cell<Option<String>> = const { Cell::new(None) };
+        static SETTINGS_ARG: Cell<Option<Settings>> = const { Cell::new(None) };
+        static SNIPPET_PROVIDER_ANSWER: Cell<Option<Box<dyn SnippetProvider>>> = const { Cell::new(None) };
+        static SNIPPET_PROVIDER_FORMAT_ARG: Cell<Option<DeckFormat>> = const { Cell::new(None) };
+        static SNIPPET_PROVIDER_FROZEN_ARG: Cell<Option<bool>> = const { Cell::new(None) };
+        static WRITE_OK_ANSWER: Cell<bool> = const { Cell::new(true) };
+        static WRITE_ARG_PATH: Cell<Option<PathBuf>> = const { Cell::new(None) };
+        static WRITE_ARG_CONTENTS: Cell<Option<String>> = const { Cell::new(None) };
+    }
+
+    #[test]
+    fn config_new_from_non_existing_path_fails() {
+        CANONICALIZE_ANSWER.set(None);
+
+        let sut = Config::new(Path::new(""), None, BTreeMap::new(), None);
+
+        assert!(matches!(sut, Err(LazyCoderError::SnippetFileNotFound)));
+    }
+
+    #[test]
+    fn config_new_stores_deck_path_relative_to_project() {
+        PROJECT_ROOT_ANSWER.set(Some(PathBuf::from("/projects/traits")));
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from("/projects/traits/talk/deck.lazycoder")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/projects/traits/.lazycoder")));
+
+        let sut = Config::new(
+            Path::new("talk/deck.lazycoder"),
+            None,
+            BTreeMap::new(),
+            None,
+        );
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.deck_path() == Path::new("/projects/traits/talk/deck.lazycoder")),
+            "Wrong config: {sut:?}"
+        );
+        assert_eq!(
+            WRITE_ARG_PATH.take(),
+            Some(PathBuf::from("/projects/traits/.lazycoder/lazycoder.toml"))
+        );
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"talk/deck.lazycoder\"\nposition = 0\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn config_new_stores_absolute_path_of_deck_outside_project() {
+        PROJECT_ROOT_ANSWER.set(Some(PathBuf::from("/projects/traits")));
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from("/talks/deck.lazycoder")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/projects/traits/.lazycoder")));
+
+        let sut = Config::new(
+            Path::new("/talks/deck.lazycoder"),
+            None,
+            BTreeMap::new(),
+            None,
+        );
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.file_path == Path::new("/talks/deck.lazycoder") && cfg.deck_path() == Path::new("/talks/deck.lazycoder")),
+            "Wrong config: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_resolves_deck_from_project_root() {
+        PROJECT_ROOT_ANSWER.set(Some(PathBuf::from("/projects/traits")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/projects/traits/.lazycoder")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"talk/deck.lazycoder\"\nposition = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.deck_path() == Path::new("/projects/traits/talk/deck.lazycoder")),
+            "Wrong config: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn global_state_ignores_project() {
+        PROJECT_ROOT_ANSWER.set(Some(PathBuf::from("/projects/traits")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/state/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\n",
+        )));
+
+        Config::use_global_state();
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.root.is_none()),
+            "Wrong config: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn paths_of_session_are_listed() {
+        Config::use_config_dir(PathBuf::from("/usb/lazycoder"));
+        PROJECT_ROOT_ANSWER.set(Some(PathBuf::from("/projects/traits")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/projects/traits/.lazycoder")));
+
+        let result = Config::paths(Some(String::from("meetup")));
+
+        assert!(
+            matches!(result, Ok(ref paths) if *paths == [
+                ("config_dir", PathBuf::from("/usb/lazycoder")),
+                ("settings", PathBuf::from("/usb/lazycoder/settings.toml")),
+                ("state_dir", PathBuf::from("/projects/traits/.lazycoder")),
+                ("session", PathBuf::from("/projects/traits/.lazycoder/sessions/meetup.toml")),
+                ("project", PathBuf::from("/projects/traits")),
+            ]),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn paths_without_config_dir_fail() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/state/path")));
+
+        let result = Config::paths(None);
+
+        assert!(
+            matches!(result, Err(LazyCoderError::ConfigDirError)),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn config_new_from_existing_path_is_created() {
+        let mut path = PathBuf::new();
+        path.push("/tmp");
+        CANONICALIZE_ANSWER.set(Some(path));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+
+        let sut = Config::new(Path::new("/tmp"), None, BTreeMap::new(), None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.file_path == Path::new("/tmp") && cfg.position == 0 && cfg.format.is_none()),
+            "Wrong config: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_new_stores_explicit_format() {
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from("/tmp/talk.txt")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+
+        let sut = Config::new(
+            Path::new("/tmp/talk.txt"),
+            Some(DeckFormat::Markdown),
+            BTreeMap::new(),
+            None,
+        );
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.format == Some(DeckFormat::Markdown)),
+            "Wrong config: {sut:?}"
+        );
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/tmp/talk.txt\"\nposition = 0\nformat = \"markdown\"\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn config_from_file_fails_with_non_exiting_path_or_directory() {
+        STATE_DIR_ANSWER.set(None);
+
+        let sut = Config::from_file(None);
+
+        assert!(matches!(sut, Err(LazyCoderError::ConfigDirError)));
+    }
+
+    #[test]
+    fn config_from_non_exiting_file_fails() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+        READ_TO_STRING_ANSWER.set(None);
+
+        let sut = Config::from_file(None);
+
+        assert!(matches!(sut, Err(LazyCoderError::ConfigFileError(_))));
+    }
+
+    #[test]
+    fn config_from_file_fails_if_state_is_locked() {
+        LOCK_OK_ANSWER.set(false);
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Err(LazyCoderError::StateLocked(_))),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_new_fails_without_saving_if_state_is_locked() {
+        LOCK_OK_ANSWER.set(false);
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from("/tmp/talk.txt")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+
+        let sut = Config::new(Path::new("/tmp/talk.txt"), None, BTreeMap::new(), None);
+
+        assert!(
+            matches!(sut, Err(LazyCoderError::StateLocked(_))),
+            "Unexpected result: {sut:?}"
+        );
+        assert_eq!(WRITE_ARG_PATH.take(), None);
+    }
+
+    #[test]
+    fn config_from_file_returns_valid_configuration() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/some/path/file.lazycoder\"\nposition = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(matches!(sut, Ok(config) if config == Config {
+                file_path: PathBuf::from("/some/path/file.lazycoder"),
                 position: 2,
+                ..Config::default()
             }
         ));
     }
 
     #[test]
-    fn save_returns_error_if_config_dir_fails() {
-        CONFIG_DIR_ANSWER.set(None);
+    fn config_from_file_migrates_unversioned_file_keeping_backup() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.schema_version == SchemaVersion(SCHEMA_VERSION) && cfg.position == 2),
+            "Wrong config: {sut:?}"
+        );
+        assert_eq!(
+            COPY_ARGS.take(),
+            Some((
+                PathBuf::from("/some/config/path/lazycoder.toml"),
+                PathBuf::from("/some/config/path/lazycoder.toml.v0.bak")
+            ))
+        );
+        assert_eq!(
+            WRITE_ARG_PATH.take(),
+            Some(PathBuf::from("/some/config/path/lazycoder.toml"))
+        );
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/path/file.lazycoder\"\nposition = 2\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn config_from_file_keeps_current_file() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/some/path/file.lazycoder\"\nposition = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(sut.is_ok(), "Wrong config: {sut:?}");
+        assert_eq!(COPY_ARGS.take(), None);
+        assert_eq!(WRITE_ARG_PATH.take(), None);
+    }
+
+    #[test]
+    fn config_from_file_fails_with_newer_schema() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 2\nfile_path = \"/some/path/file.lazycoder\"\nplace = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(
+                sut,
+                Err(LazyCoderError::UnsupportedSchemaVersion {
+                    found: 2,
+                    supported: SCHEMA_VERSION
+                })
+            ),
+            "Unexpected result: {sut:?}"
+        );
+        assert_eq!(WRITE_ARG_PATH.take(), None);
+    }
+
+    fn snippet_with_id(id: &str) -> Snippet {
+        let mut snippet = snippet_with_text("Some snippet");
+        snippet.metadata.id = Some(String::from(id));
+        snippet
+    }
+
+    fn snippet_provider_with_ids(ids: &'static [&'static str]) -> MockSnippetProvider {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov.expect_get_snippet().returning(|position| {
+            let id = ids.get(position).ok_or(LazyCoderError::RunOutOfSnippets)?;
+            Ok(snippet_with_id(id))
+        });
+        snippet_prov
+            .expect_snippets()
+            .returning(|| Ok(ids.iter().map(|id| snippet_with_id(id)).collect()));
+        snippet_prov
+    }
+
+    #[test]
+    fn config_from_file_keeps_position_of_anchored_snippet() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n\n[anchor]\nid = \"second\"\n",
+        )));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_ids(&[
+            "first", "second", "third",
+        ]))));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref config) if config.position == 2),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_follows_anchored_snippet_when_deck_changes() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n\n[anchor]\nid = \"second\"\n",
+        )));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_ids(&[
+            "first", "inserted", "second", "third",
+        ]))));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref config) if config.position == 3),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_reads_deck_once_to_follow_anchored_snippet() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n\n[anchor]\nid = \"second\"\n",
+        )));
+        let ids = ["first", "inserted", "other", "second", "third"];
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .times(1)
+            .returning(move |position| Ok(snippet_with_id(ids[position])));
+        snippet_prov
+            .expect_snippets()
+            .times(1)
+            .returning(move || Ok(ids.iter().map(|id| snippet_with_id(id)).collect()));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref config) if config.position == 4),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_fails_if_anchored_id_is_gone() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n\n[anchor]\nid = \"second\"\n",
+        )));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_ids(&[
+            "first", "third",
+        ]))));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Err(LazyCoderError::SnippetNotFound(ref anchor)) if anchor == "with id \"second\""),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    fn snippet_provider_with_texts(texts: &'static [&'static str]) -> MockSnippetProvider {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov.expect_get_snippet().returning(|position| {
+            let text = texts
+                .get(position)
+                .ok_or(LazyCoderError::RunOutOfSnippets)?;
+            Ok(snippet_with_text(text))
+        });
+        snippet_prov
+            .expect_snippets()
+            .returning(|| Ok(texts.iter().map(|text| snippet_with_text(text)).collect()));
+        snippet_prov
+    }
+
+    #[test]
+    fn edited_anchored_snippet_does_not_block_navigation() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from_str("Some path").unwrap()));
+        READ_TO_STRING_ANSWER.set(Some(format!(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n\n[anchor]\nhash = \"{}\"\n",
+            deck::content_hash(b"let y = 2;")
+        )));
+        let edited_deck = || {
+            let mut snippet_prov = snippet_provider_with_texts(&[
+                "let x = 1;",
+                "let y = 3;",
+                "let z = 4;",
+                "let w = 5;",
+            ]);
+            snippet_prov.expect_snippet_count().returning(|| Ok(4));
+            Some(Box::new(snippet_prov) as Box<dyn SnippetProvider>)
+        };
+        SNIPPET_PROVIDER_ANSWER.set(edited_deck());
+        let mut sut = Config::from_file(None).expect("Failing to read configuration");
+
+        SNIPPET_PROVIDER_ANSWER.set(edited_deck());
+        let result = sut.next();
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+        assert_eq!(sut.position, 3);
+
+        SNIPPET_PROVIDER_ANSWER.set(edited_deck());
+        let result = sut.forward(1);
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+        assert_eq!(sut.position, 4);
+    }
+
+    #[test]
+    fn save_returns_error_if_config_dir_fails() {
+        STATE_DIR_ANSWER.set(None);
+        let sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            sut.save(true),
+            Err(LazyCoderError::ConfigDirError)
+        ));
+    }
+
+    #[test]
+    fn save_creates_dir_if_doesnt_exist_and_rnquested() {
+        let path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(false);
+        let sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        assert!(matches!(sut.save(true), Ok(())));
+
+        assert_eq!(CREATE_DIR_ALL_ARG.take(), Some(path_buf));
+    }
+
+    #[test]
+    fn save_returns_error_if_doesnt_config_dir_doesnt_exist_and_create_dir_is_false() {
+        let path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(false);
+        let sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        assert!(matches!(
+            sut.save(false),
+            Err(LazyCoderError::ConfigDirError)
+        ));
+    }
+
+    #[test]
+    fn save_stores_configuration_in_stanard_file() {
+        let mut path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        let sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        // TODO: path.exists() should also be mocked. and then create_dir_all should be a spy.
+        assert!(matches!(sut.save(true), Ok(())));
+
+        path_buf.push(FILE_NAME);
+        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 3\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn config_new_makes_session_active() {
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from("/tmp/talk.txt")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+
+        let sut = Config::new(
+            Path::new("/tmp/talk.txt"),
+            None,
+            BTreeMap::new(),
+            Some(String::from("meetup")),
+        );
+
+        assert!(sut.is_ok(), "Wrong config: {sut:?}");
+        assert_eq!(
+            WRITE_ARG_PATH.take(),
+            Some(PathBuf::from("/some/config/path/sessions/meetup.toml"))
+        );
+        assert_eq!(
+            CREATE_DIR_ALL_ARG.take(),
+            None,
+            "Directory shouldn't be created if it exists"
+        );
+        assert_eq!(ACTIVE_SESSION_ARGS.take(), [Some(String::from("meetup"))]);
+    }
+
+    #[test]
+    fn config_new_fails_with_invalid_session_name() {
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from("/tmp/talk.txt")));
+
+        let sut = Config::new(
+            Path::new("/tmp/talk.txt"),
+            None,
+            BTreeMap::new(),
+            Some(String::from("../talk")),
+        );
+
+        assert!(
+            matches!(sut, Err(LazyCoderError::InvalidSessionName(ref name)) if name == "../talk")
+        );
+    }
+
+    #[test]
+    fn config_from_file_uses_active_session() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        ACTIVE_SESSION_ANSWER.set(Some(String::from("meetup")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.session.as_deref() == Some("meetup") && cfg.position == 2),
+            "Wrong config: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_uses_default_session_of_settings() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        SETTINGS_ANSWER.set(Some(String::from("default_session = \"meetup\"\n")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "file_path = \"/some/path/file.lazycoder\"\nposition = 2\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.session.as_deref() == Some("meetup") && cfg.settings.default_session.is_some()),
+            "Wrong config: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_fails_with_invalid_settings() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        SETTINGS_ANSWER.set(Some(String::from("end_of_deck = \"loop\"\n")));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Err(LazyCoderError::ConfigEncoding(_))),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn change_setting_saves_settings() {
+        SETTINGS_ANSWER.set(Some(String::from("separator = \"%%%\"\n")));
+
+        let result = Config::set_setting("end_of_deck", "wrap");
+
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+        assert_eq!(
+            SETTINGS_ARG.take(),
+            Some(Settings {
+                separator: Some(String::from("%%%")),
+                end_of_deck: Some(EndOfDeck::Wrap),
+                ..Settings::default()
+            })
+        );
+    }
+
+    #[test]
+    fn change_setting_rejects_invalid_default_session() {
+        let result = Config::set_setting("default_session", "my talk");
+
+        assert!(
+            matches!(result, Err(LazyCoderError::InvalidSessionName(ref name)) if name == "my talk"),
+            "Unexpected result: {result:?}"
+        );
+        assert_eq!(SETTINGS_ARG.take(), None);
+    }
+
+    #[test]
+    fn unset_setting_removes_it() {
+        SETTINGS_ANSWER.set(Some(String::from("separator = \"%%%\"\n")));
+
+        let result = Config::unset_setting("separator");
+
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+        assert_eq!(SETTINGS_ARG.take(), Some(Settings::default()));
+    }
+
+    #[test]
+    fn settings_are_loaded() {
+        SETTINGS_ANSWER.set(Some(String::from("log_file = \"/tmp/lazycoder.log\"\n")));
+
+        let result = Config::settings();
+
+        assert!(
+            matches!(result, Ok(ref settings) if settings.log_file == Some(PathBuf::from("/tmp/lazycoder.log"))),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_of_missing_session_fails() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(None);
+
+        let sut = Config::from_file(Some(String::from("meetup")));
+
+        assert!(
+            matches!(sut, Err(LazyCoderError::SessionNotFound(ref name)) if name == "meetup"),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_with_moved_deck_suggests_candidates() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(false);
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\ndeck_hash = \"0123456789abcdef\"\n",
+        )));
+        FIND_DECK_ANSWER.set(Some(vec![PathBuf::from("/old/talks/deck.lazycoder")]));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Err(LazyCoderError::DeckMoved { ref path, ref candidates })
+                if *path == Path::new("/talks/deck.lazycoder") && *candidates == [PathBuf::from("/old/talks/deck.lazycoder")]),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_with_missing_frozen_deck_is_read() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(false);
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\nfrozen = true\n",
+        )));
+
+        let sut = Config::from_file(None);
+
+        assert!(sut.is_ok(), "Unexpected result: {sut:?}");
+    }
+
+    #[test]
+    fn config_from_file_records_hash_of_deck() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\n",
+        )));
+        FILE_HASH_ANSWER.set(Some(String::from("0123456789abcdef")));
+
+        let sut = Config::from_file(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.deck_hash.as_deref() == Some("0123456789abcdef")),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn config_from_file_keeps_hash_of_deck_if_it_is_unchanged() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\ndeck_hash = \"0123456789abcdef\"\ndeck_stamp = [1700000000000000000, 42]\n",
+        )));
+        MODIFIED_AND_SIZE_ANSWER.set(Some((1_700_000_000_000_000_000, 42)));
+        FILE_HASH_ANSWER.set(Some(String::from("fedcba9876543210")));
+
+        let sut = Config::from_file(None);
+        let hash_computed = FILE_HASH_ANSWER.take().is_none();
+        MODIFIED_AND_SIZE_ANSWER.set(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.deck_hash.as_deref() == Some("0123456789abcdef")),
+            "Unexpected result: {sut:?}"
+        );
+        assert!(!hash_computed, "Hash of an unchanged deck computed again");
+    }
+
+    #[test]
+    fn config_from_file_records_hash_of_deck_if_it_has_changed() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\ndeck_hash = \"0123456789abcdef\"\ndeck_stamp = [1700000000000000000, 42]\n",
+        )));
+        MODIFIED_AND_SIZE_ANSWER.set(Some((1_700_000_000_000_000_001, 42)));
+        FILE_HASH_ANSWER.set(Some(String::from("fedcba9876543210")));
+
+        let sut = Config::from_file(None);
+        MODIFIED_AND_SIZE_ANSWER.set(None);
+
+        assert!(
+            matches!(sut, Ok(ref cfg) if cfg.deck_hash.as_deref() == Some("fedcba9876543210")
+                && cfg.deck_stamp == Some((1_700_000_000_000_000_001, 42))),
+            "Unexpected result: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn relink_uses_the_only_candidate_and_keeps_history() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\ndeck_hash = \"0123456789abcdef\"\n",
+        )));
+        FIND_DECK_ANSWER.set(Some(vec![PathBuf::from("/old/talks/deck.lazycoder")]));
+        FILE_HASH_ANSWER.set(Some(String::from("0123456789abcdef")));
+        let mut journal = PositionJournal::new(Path::new("/talks/deck.lazycoder"));
+        journal.record(Operation::Next, 1, 2);
+        JOURNAL_ANSWER.set(Some(journal));
+
+        let result = Config::relink(None, None);
+
+        assert!(
+            matches!(result, Ok(ref path) if *path == Path::new("/old/talks/deck.lazycoder")),
+            "Unexpected result: {result:?}"
+        );
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/old/talks/deck.lazycoder\"\nposition = 2\ndeck_hash = \"0123456789abcdef\"\n"
+            ))
+        );
+        let journal = JOURNAL_ARG.take().expect("Journal not saved");
+        assert_eq!(journal.file_path(), Path::new("/old/talks/deck.lazycoder"));
+        assert_eq!(journal.position(), Some(2));
+    }
+
+    #[test]
+    fn relink_with_several_candidates_fails() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"/talks/deck.lazycoder\"\nposition = 2\ndeck_hash = \"0123456789abcdef\"\n",
+        )));
+        FIND_DECK_ANSWER.set(Some(vec![
+            PathBuf::from("/old/talks/deck.lazycoder"),
+            PathBuf::from("/backup/deck.lazycoder"),
+        ]));
+
+        let result = Config::relink(None, None);
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckMoved { ref candidates, .. }) if candidates.len() == 2),
+            "Unexpected result: {result:?}"
+        );
+        assert_eq!(WRITE_ARG_CONTENTS.take(), None);
+    }
+
+    #[test]
+    fn relink_to_path_in_project_stores_it_relative_to_root() {
+        PROJECT_ROOT_ANSWER.set(Some(PathBuf::from("/projects/traits")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/projects/traits/.lazycoder")));
+        READ_TO_STRING_ANSWER.set(Some(String::from(
+            "schema_version = 1\nfile_path = \"talk/deck.lazycoder\"\nposition = 2\n",
+        )));
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from(
+            "/projects/traits/slides/deck.lazycoder",
+        )));
+
+        let result = Config::relink(Some(PathBuf::from("slides/deck.lazycoder")), None);
+
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"slides/deck.lazycoder\"\nposition = 2\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn save_stores_session_in_sessions_dir() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(false);
+        let sut = Config {
+            file_path: PathBuf::from("/some/talk.lazycoder"),
+            session: Some(String::from("meetup")),
+            ..Config::default()
+        };
+
+        assert!(matches!(sut.save(true), Ok(())));
+
+        assert_eq!(
+            CREATE_DIR_ALL_ARG.take(),
+            Some(PathBuf::from("/some/config/path/sessions"))
+        );
+        assert_eq!(
+            WRITE_ARG_PATH.take(),
+            Some(PathBuf::from("/some/config/path/sessions/meetup.toml"))
+        );
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/talk.lazycoder\"\nposition = 0\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn sessions_are_listed_with_default_first_and_active_marked() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        ACTIVE_SESSION_ANSWER.set(Some(String::from("meetup")));
+        SESSION_NAMES_ANSWER.set(Some(vec![
+            String::from("conference"),
+            String::from("meetup"),
+        ]));
+
+        let sut = Config::sessions();
+
+        assert!(
+            matches!(sut, Ok(ref sessions) if *sessions == [
+                (String::from("default"), false),
+                (String::from("conference"), false),
+                (String::from("meetup"), true),
+            ]),
+            "Unexpected sessions: {sut:?}"
+        );
+    }
+
+    #[test]
+    fn switch_to_missing_session_fails() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(false);
+
+        let sut = Config::switch_session(String::from("meetup"));
+
+        assert!(matches!(sut, Err(LazyCoderError::SessionNotFound(ref name)) if name == "meetup"));
+        assert!(ACTIVE_SESSION_ARGS.take().is_empty());
+    }
+
+    #[test]
+    fn switch_to_default_session_removes_active_one() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+
+        let sut = Config::switch_session(String::from("default"));
+
+        assert!(matches!(sut, Ok(())));
+        assert_eq!(ACTIVE_SESSION_ARGS.take(), [None]);
+    }
+
+    #[test]
+    fn rename_of_active_session_keeps_it_active() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(false);
+        ACTIVE_SESSION_ANSWER.set(Some(String::from("meetup")));
+
+        let sut = Config::rename_session(String::from("meetup"), String::from("conference"));
+
+        assert!(matches!(sut, Ok(())));
+        assert_eq!(
+            RENAME_ARGS.take(),
+            Some((
+                PathBuf::from("/some/config/path/sessions/meetup.toml"),
+                PathBuf::from("/some/config/path/sessions/conference.toml")
+            ))
+        );
+        assert_eq!(
+            ACTIVE_SESSION_ARGS.take(),
+            [Some(String::from("conference"))]
+        );
+    }
+
+    #[test]
+    fn rename_to_existing_session_fails() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+
+        let sut = Config::rename_session(String::from("meetup"), String::from("default"));
+
+        assert!(matches!(sut, Err(LazyCoderError::SessionExists(ref name)) if name == "default"));
+        assert_eq!(RENAME_ARGS.take(), None);
+    }
+
+    #[test]
+    fn rename_fails_without_renaming_if_state_is_locked() {
+        LOCK_OK_ANSWER.set(false);
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(false);
+
+        let sut = Config::rename_session(String::from("meetup"), String::from("conference"));
+
+        assert!(matches!(sut, Err(LazyCoderError::StateLocked(_))));
+        assert_eq!(RENAME_ARGS.take(), None);
+    }
+
+    #[test]
+    fn delete_of_active_session_makes_default_active() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        ACTIVE_SESSION_ANSWER.set(Some(String::from("meetup")));
+
+        let sut = Config::delete_session(String::from("meetup"));
+
+        assert!(matches!(sut, Ok(())));
+        assert_eq!(
+            REMOVE_FILE_ARG.take(),
+            Some(PathBuf::from("/some/config/path/sessions/meetup.toml"))
+        );
+        assert_eq!(ACTIVE_SESSION_ARGS.take(), [None]);
+    }
+
+    #[test]
+    fn next_snippet_increases_position_saves_and_returns_text() {
+        let mut path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Ok(snippet_with_text("Some snippet")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "Some snippet"),
+            "Snippet: {snippet:?}"
+        );
+        path_buf.push(FILE_NAME);
+        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 4\n\n[anchor]\nhash = \"61f6fc91894bd778\"\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn snippet_provider_format_is_detected_from_extension() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Ok(snippet_with_text("Some snippet")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/talk.md"),
+            position: 0,
+            ..Config::default()
+        };
+
+        assert!(sut.peek().is_ok(), "Unexpected error peeking");
+        assert_eq!(
+            SNIPPET_PROVIDER_FORMAT_ARG.take(),
+            Some(DeckFormat::Markdown)
+        );
+    }
+
+    #[test]
+    fn snippet_provider_format_uses_explicit_format() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Ok(snippet_with_text("Some snippet")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/talk.md"),
+            position: 0,
+            format: Some(DeckFormat::Lazycoder),
+            ..Config::default()
+        };
+
+        assert!(sut.peek().is_ok(), "Unexpected error peeking");
+        assert_eq!(
+            SNIPPET_PROVIDER_FORMAT_ARG.take(),
+            Some(DeckFormat::Lazycoder)
+        );
+    }
+
+    #[test]
+    fn next_snippet_fails_if_snippet_provider_fails() {
+        let path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
+        snippet_prov
+            .expect_settings()
+            .returning(|| Ok(DeckSettings::default()));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Err(LazyCoderError::RunOutOfSnippets)),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(WRITE_ARG_PATH.take(), None);
+        assert_eq!(WRITE_ARG_CONTENTS.take(), None);
+    }
+
+    #[test]
+    fn next_snippet_fails_if_save_fails() {
+        STATE_DIR_ANSWER.set(None);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Ok(snippet_with_text("Some snippet")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Err(LazyCoderError::ConfigDirError)),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(WRITE_ARG_PATH.take(), None);
+        assert_eq!(WRITE_ARG_CONTENTS.take(), None);
+    }
+
+    #[test]
+    fn peek_snippet_only_returns_text() {
+        let path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Ok(snippet_with_text("Some snippet")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.peek();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "Some snippet"),
+            "Snippet: {snippet:?}"
+        );
+    }
+
+    #[test]
+    fn peek_snippet_applies_output_transforms() {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Ok(snippet_with_text("fn main() {\n\tlet x = 1;\n}\n")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            settings: Settings {
+                output: vec![
+                    OutputTransform::TrimTrailingNewline,
+                    OutputTransform::ExpandTabs,
+                ],
+                ..Settings::default()
+            },
+            ..Config::default()
+        };
+
+        let snippet = sut.peek();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "fn main() {\n    let x = 1;\n}"),
+            "Snippet: {snippet:?}"
+        );
+    }
+
+    #[test]
+    fn peek_snippet_fails_if_snippet_provider_fails() {
+        let path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
+        snippet_prov
+            .expect_settings()
+            .returning(|| Ok(DeckSettings::default()));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.peek();
+
+        assert!(
+            matches!(snippet, Err(LazyCoderError::RunOutOfSnippets)),
+            "Snippet: {snippet:?}"
+        );
+    }
+
+    #[test]
+    fn next_snippet_wraps_to_first_if_deck_allows_it() {
+        let mut path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(0))
+            .once()
+            .returning(|_| Ok(snippet_with_text("First snippet")));
+        snippet_prov.expect_settings().returning(|| {
+            Ok(DeckSettings {
+                end_of_deck: EndOfDeck::Wrap,
+                ..DeckSettings::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "First snippet"),
+            "Snippet: {snippet:?}"
+        );
+        path_buf.push(FILE_NAME);
+        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 1\n\n[anchor]\nhash = \"7e10aae7d8ada968\"\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn next_snippet_stays_on_last_if_deck_allows_it() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = snippet_provider_with_count(3, EndOfDeck::Stay);
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(2))
+            .once()
+            .returning(|_| Ok(snippet_with_text("Last snippet")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "Last snippet"),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(sut.position, 3);
+    }
+
+    #[test]
+    fn next_snippet_provides_fallback_text_without_advancing() {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
+        snippet_prov.expect_settings().returning(|| {
+            Ok(DeckSettings {
+                end_of_deck: EndOfDeck::Fallback,
+                fallback_text: Some(String::from("// That's all folks!")),
+                ..DeckSettings::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "// That's all folks!"),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(sut.position, 3);
+        assert_eq!(WRITE_ARG_PATH.take(), None);
+    }
+
+    #[test]
+    fn peek_snippet_wraps_to_first_if_deck_allows_it() {
+        let path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(3))
+            .once()
+            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
+        snippet_prov
+            .expect_get_snippet()
+            .with(predicate::eq(0))
+            .once()
+            .returning(|_| Ok(snippet_with_text("First snippet")));
+        snippet_prov.expect_settings().returning(|| {
+            Ok(DeckSettings {
+                end_of_deck: EndOfDeck::Wrap,
+                ..DeckSettings::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.peek();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "First snippet"),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(WRITE_ARG_CONTENTS.take(), None);
+    }
+
+    #[test]
+    fn next_snippet_expands_variables_from_config_environment_and_deck() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        ENV_VARS_ANSWER.set(BTreeMap::from([
+            (String::from("LAZYCODER_VAR_CUSTOMER"), String::from("ACME")),
+            (
+                String::from("LAZYCODER_VAR_project_name"),
+                String::from("env"),
+            ),
+        ]));
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Ok(snippet_with_text("{{project_name}} {{customer}} {{year}}")));
+        snippet_prov.expect_settings().returning(|| {
+            Ok(DeckSettings {
+                vars: BTreeMap::from([
+                    (String::from("customer"), String::from("deck")),
+                    (String::from("year"), String::from("2025")),
+                ]),
+                templates: true,
+                ..DeckSettings::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            vars: BTreeMap::from([(String::from("project_name"), String::from("shapes"))]),
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "shapes ACME 2025"),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 4\n\n[vars]\nproject_name = \"shapes\"\n\n[anchor]\nhash = \"a5b0141fd9933175\"\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn next_snippet_with_unknown_variable_fails_without_advancing() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Ok(snippet_with_text("cargo new {{project_name}}")));
+        snippet_prov.expect_settings().returning(|| {
+            Ok(DeckSettings {
+                templates: true,
+                ..DeckSettings::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Err(LazyCoderError::UnknownVariable(ref name)) if name == "project_name"),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(sut.position, 3);
+        assert_eq!(WRITE_ARG_CONTENTS.take(), None);
+    }
+
+    #[test]
+    fn next_snippet_keeps_placeholders_if_deck_does_not_enable_templates() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Ok(snippet_with_text("format!(\"{{name}}\")")));
+        snippet_prov
+            .expect_settings()
+            .returning(|| Ok(DeckSettings::default()));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "format!(\"{{name}}\")"),
+            "Snippet: {snippet:?}"
+        );
+    }
+
+    #[test]
+    fn next_snippet_marks_cursor_position_for_espanso() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov.expect_get_snippet().returning(|_| {
+            Ok(Snippet {
+                text: String::from("fn {{name}}() {}"),
+                cursor: Some(15),
+                ..Snippet::default()
+            })
+        });
+        snippet_prov.expect_settings().returning(|| {
+            Ok(DeckSettings {
+                vars: BTreeMap::from([(String::from("name"), String::from("main"))]),
+                templates: true,
+                ..DeckSettings::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.next();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "fn main() {$|$}"),
+            "Snippet: {snippet:?}"
+        );
+    }
+
+    #[test]
+    fn peek_snippet_strips_cursor_position() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov.expect_get_snippet().returning(|_| {
+            Ok(Snippet {
+                text: String::from("fn main() {}"),
+                cursor: Some(11),
+                ..Snippet::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            position: 3,
+            ..Config::default()
+        };
+
+        let snippet = sut.peek();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "fn main() {}"),
+            "Snippet: {snippet:?}"
+        );
+    }
+
+    #[test]
+    fn freeze_stores_copy_of_deck_and_saves() {
+        let mut path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        FREEZE_DECK_ANSWER.set(Some(vec![0, 1]));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/talk.md"),
+            position: 0,
+            ..Config::default()
+        };
+
+        let result = sut.freeze();
+
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+        assert_eq!(
+            SNIPPET_PROVIDER_FORMAT_ARG.take(),
+            Some(DeckFormat::Markdown)
+        );
+        path_buf.push(FILE_NAME);
+        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/talk.md\"\nposition = 0\nfrozen = true\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn sessions_on_same_deck_freeze_their_own_copies() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        let mut sessions = [None, Some(String::from("meetup"))].map(|session| Config {
+            file_path: PathBuf::from("/some/talk.lazycoder"),
+            session,
+            ..Config::default()
+        });
+
+        for sut in &mut sessions {
+            FREEZE_DECK_ANSWER.set(Some(vec![0]));
+            let result = sut.freeze();
+
+            assert!(result.is_ok(), "Unexpected result: {result:?}");
+        }
+        assert_eq!(
+            FREEZE_DECK_SESSION_ARGS.take(),
+            [None, Some(String::from("meetup"))]
+        );
+        let state_dir = Path::new("/some/config/path");
+        assert_ne!(
+            frozen_deck_file(&state_dir.join(session_file(None))),
+            frozen_deck_file(&state_dir.join(session_file(Some("meetup"))))
+        );
+        assert_ne!(
+            frozen_deck_file(&state_dir.join(session_file(Some("meetup")))),
+            frozen_deck_file(&Path::new("/project/.lazycoder").join(session_file(Some("meetup"))))
+        );
+    }
+
+    #[test]
+    fn frozen_deck_provides_snippets() {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_get_snippet()
+            .returning(|_| Ok(snippet_with_text("Frozen snippet")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            frozen: true,
+            ..Config::default()
+        };
+
+        let snippet = sut.peek();
+
+        assert!(
+            matches!(snippet, Ok(ref text) if text == "Frozen snippet"),
+            "Snippet: {snippet:?}"
+        );
+        assert_eq!(SNIPPET_PROVIDER_FROZEN_ARG.take(), Some(true));
+    }
+
+    #[test]
+    fn refresh_returns_changed_snippets_and_saves() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        PATH_EXISTS_ANSWER.set(true);
+        FREEZE_DECK_ANSWER.set(Some(vec![2, 5]));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            frozen: true,
+            ..Config::default()
+        };
+
+        let result = sut.refresh();
+
+        assert!(
+            matches!(result, Ok(ref changes) if *changes == [2, 5]),
+            "Unexpected result: {result:?}"
+        );
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 0\nfrozen = true\n"
+            ))
+        );
+    }
+
+    #[test]
+    fn refresh_fails_if_deck_is_not_frozen() {
+        FREEZE_DECK_ANSWER.set(Some(vec![2, 5]));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/config/path"),
+            ..Config::default()
+        };
+
+        let result = sut.refresh();
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckNotFrozen)),
+            "Unexpected result: {result:?}"
+        );
+        FREEZE_DECK_ANSWER.take();
+    }
+
+    fn snippet_provider_with_count(count: usize, end_of_deck: EndOfDeck) -> MockSnippetProvider {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov
+            .expect_snippet_count()
+            .returning(move || Ok(count));
+        snippet_prov.expect_settings().returning(move || {
+            Ok(DeckSettings {
+                end_of_deck,
+                ..DeckSettings::default()
+            })
+        });
+        snippet_prov
+    }
+
+    #[test]
+    fn status_previews_next_snippets() {
+        let mut snippet_prov = snippet_provider_with_count(10, EndOfDeck::Error);
+        snippet_prov.expect_get_snippet().returning(|position| {
+            let mut snippet = snippet_with_text(&format!("\n    let x = {position};\n"));
+            if position == 3 {
+                snippet.metadata.title = Some(String::from("Bindings"));
+            }
+            Ok(snippet)
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
         let sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/talk.lazycoder"),
             position: 3,
+            ..Config::default()
         };
 
-        assert!(matches!(
-            sut.save(true),
-            Err(LazyCoderError::ConfigDirError)
-        ));
+        let result = sut.status();
+
+        assert!(
+            matches!(result, Ok(ref status) if *status == DeckStatus {
+                deck: PathBuf::from("/some/talk.lazycoder"),
+                position: 3,
+                total: 10,
+                next_title: Some(String::from("Bindings")),
+                upcoming: vec![
+                    SnippetPreview {
+                        position: 3,
+                        title: Some(String::from("Bindings")),
+                        preview: String::from("let x = 3;"),
+                    },
+                    SnippetPreview {
+                        position: 4,
+                        title: None,
+                        preview: String::from("let x = 4;"),
+                    },
+                ],
+            }),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn status_at_end_of_deck_has_no_previews() {
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_count(
+            5,
+            EndOfDeck::Error,
+        ))));
+        let sut = Config {
+            file_path: PathBuf::from("/some/talk.lazycoder"),
+            position: 5,
+            ..Config::default()
+        };
+
+        let result = sut.status();
+
+        assert!(
+            matches!(result, Ok(ref status) if status.total == 5 && status.next_title.is_none() && status.upcoming.is_empty()),
+            "Unexpected result: {result:?}"
+        );
     }
 
     #[test]
-    fn save_creates_dir_if_doesnt_exist_and_rnquested() {
-        let path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(false);
+    fn status_previews_snippets_as_provided() {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov.expect_snippet_count().returning(|| Ok(2));
+        snippet_prov.expect_settings().once().returning(|| {
+            Ok(DeckSettings {
+                templates: true,
+                vars: BTreeMap::from([(String::from("project_name"), String::from("shapes"))]),
+                ..DeckSettings::default()
+            })
+        });
+        snippet_prov.expect_get_snippet().returning(|position| {
+            Ok(snippet_with_text(if position == 0 {
+                "cargo new {{project_name}}\n"
+            } else {
+                "cd {{missing}}\n"
+            }))
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
         let sut = Config {
-            file_path: String::from("/some/config/path"),
-            position: 3,
+            file_path: PathBuf::from("/some/talk.lazycoder"),
+            ..Config::default()
         };
 
-        assert!(matches!(sut.save(true), Ok(())));
+        let result = sut.status();
 
-        assert_eq!(CREATE_DIR_ALL_ARG.take(), Some(path_buf));
+        assert!(
+            matches!(result, Ok(ref status) if status.upcoming[0].preview == "cargo new shapes"
+                && status.upcoming[1].preview == "cd {{missing}}"),
+            "Unexpected result: {result:?}"
+        );
     }
 
     #[test]
-    fn save_returns_error_if_doesnt_config_dir_doesnt_exist_and_create_dir_is_false() {
-        let path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(false);
+    fn position_and_deck_of_project_are_reported() {
         let sut = Config {
-            file_path: String::from("/some/config/path"),
-            position: 3,
+            file_path: PathBuf::from("talks/traits.lazycoder"),
+            root: Some(PathBuf::from("/projects/shapes")),
+            position: 4,
+            ..Config::default()
         };
 
-        assert!(matches!(
-            sut.save(false),
-            Err(LazyCoderError::ConfigDirError)
-        ));
+        assert_eq!(sut.position(), 4);
+        assert_eq!(
+            sut.deck_path(),
+            PathBuf::from("/projects/shapes/talks/traits.lazycoder")
+        );
     }
 
     #[test]
-    fn save_stores_configuration_in_stanard_file() {
-        let mut path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(true);
+    fn list_summarizes_snippets_and_marks_the_next_one() {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov.expect_snippets().returning(|| {
+            Ok(vec![
+                snippet_with_text("trait {{trait_name}} {}\n"),
+                snippet_with_text("fn main() {\n}\n"),
+            ])
+        });
+        snippet_prov.expect_settings().once().returning(|| {
+            Ok(DeckSettings {
+                templates: true,
+                vars: BTreeMap::from([(String::from("trait_name"), String::from("Shape"))]),
+                ..DeckSettings::default()
+            })
+        });
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
         let sut = Config {
-            file_path: String::from("/some/config/path"),
-            position: 3,
+            file_path: PathBuf::from("/some/talk.lazycoder"),
+            position: 1,
+            ..Config::default()
         };
 
-        // TODO: path.exists() should also be mocked. and then create_dir_all should be a spy.
-        assert!(matches!(sut.save(true), Ok(())));
+        let result = sut.list();
 
-        path_buf.push(FILE_NAME);
-        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
-        assert_eq!(
-            WRITE_ARG_CONTENTS.take(),
-            Some(String::from(
-                "file_path = \"/some/config/path\"\nposition = 3\n"
-            ))
+        assert!(
+            matches!(result, Ok(ref snippets) if *snippets == [
+                SnippetSummary {
+                    position: 0,
+                    id: None,
+                    title: None,
+                    lines: 1,
+                    preview: String::from("trait Shape {}"),
+                    current: false,
+                },
+                SnippetSummary {
+                    position: 1,
+                    id: None,
+                    title: None,
+                    lines: 2,
+                    preview: String::from("fn main() {"),
+                    current: true,
+                },
+            ]),
+            "Unexpected result: {result:?}"
         );
     }
 
     #[test]
-    fn next_snippet_increases_position_saves_and_returns_text() {
-        let mut path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(true);
-        let mut snippet_prov = MockSnippetProvider::new();
-        snippet_prov
-            .expect_get_snippet()
-            .with(predicate::eq(3))
-            .once()
-            .returning(|_| Ok(String::from("Some snippet")));
-        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+    fn goto_moves_to_snippet_and_saves() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_ids(&[
+            "intro", "trait", "impl",
+        ]))));
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/config/path"),
             position: 3,
+            ..Config::default()
         };
 
-        let snippet = sut.next();
+        let result = sut.goto(&GotoTarget::Label(String::from("trait")));
 
-        assert!(
-            matches!(snippet, Ok(ref text) if text == "Some snippet"),
-            "Snippet: {snippet:?}"
-        );
-        path_buf.push(FILE_NAME);
-        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
         assert_eq!(
             WRITE_ARG_CONTENTS.take(),
             Some(String::from(
-                "file_path = \"/some/config/path\"\nposition = 4\n"
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 1\n"
             ))
         );
+        assert_eq!(
+            JOURNAL_ARG
+                .take()
+                .and_then(|journal| journal.entries().last().map(|(entry, _)| entry.operation)),
+            Some(Operation::Goto)
+        );
     }
 
     #[test]
-    fn next_snippet_fails_if_snippet_provider_fails() {
-        let path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(true);
-        let mut snippet_prov = MockSnippetProvider::new();
-        snippet_prov
-            .expect_get_snippet()
-            .with(predicate::eq(3))
-            .once()
-            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
-        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+    fn goto_missing_target_fails_without_saving() {
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_ids(&[
+            "intro", "trait",
+        ]))));
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
-            position: 3,
+            file_path: PathBuf::from("/some/config/path"),
+            position: 1,
+            ..Config::default()
         };
 
-        let snippet = sut.next();
+        let result = sut.goto(&GotoTarget::Position(2));
 
         assert!(
-            matches!(snippet, Err(LazyCoderError::RunOutOfSnippets)),
-            "Snippet: {snippet:?}"
+            matches!(result, Err(LazyCoderError::TargetNotFound { ref nearest, .. }) if *nearest == ["1 (trait)"]),
+            "Unexpected result: {result:?}"
         );
-        assert_eq!(WRITE_ARG_PATH.take(), None);
+        assert_eq!(sut.position, 1);
         assert_eq!(WRITE_ARG_CONTENTS.take(), None);
     }
 
     #[test]
-    fn next_snippet_fails_if_save_fails() {
-        CONFIG_DIR_ANSWER.set(None);
-        let mut snippet_prov = MockSnippetProvider::new();
-        snippet_prov
-            .expect_get_snippet()
-            .with(predicate::eq(3))
-            .once()
-            .returning(|_| Ok(String::from("Some snippet")));
-        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+    fn forward_increases_position_and_saves() {
+        let mut path_buf = PathBuf::from("/some/config/path");
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
+        PATH_EXISTS_ANSWER.set(true);
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_count(
+            10,
+            EndOfDeck::Error,
+        ))));
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/config/path"),
             position: 3,
+            ..Config::default()
         };
 
-        let snippet = sut.next();
+        let result = sut.forward(4);
 
-        assert!(
-            matches!(snippet, Err(LazyCoderError::ConfigDirError)),
-            "Snippet: {snippet:?}"
+        assert!(result.is_ok(), "Unexpected error moving forward");
+        path_buf.push(FILE_NAME);
+        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 7\n"
+            ))
         );
-        assert_eq!(WRITE_ARG_PATH.take(), None);
-        assert_eq!(WRITE_ARG_CONTENTS.take(), None);
     }
 
     #[test]
-    fn peek_snippet_only_returns_text() {
-        let path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(true);
-        let mut snippet_prov = MockSnippetProvider::new();
-        snippet_prov
-            .expect_get_snippet()
-            .with(predicate::eq(3))
-            .once()
-            .returning(|_| Ok(String::from("Some snippet")));
-        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+    fn forward_past_end_of_deck_fails_without_saving() {
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_count(
+            5,
+            EndOfDeck::Error,
+        ))));
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/config/path"),
             position: 3,
+            ..Config::default()
         };
 
-        let snippet = sut.peek();
+        let result = sut.forward(4);
 
         assert!(
-            matches!(snippet, Ok(ref text) if text == "Some snippet"),
-            "Snippet: {snippet:?}"
+            matches!(result, Err(LazyCoderError::OperationOutOfRange)),
+            "Unexpected result: {result:?}"
         );
+        assert_eq!(sut.position, 3);
+        assert_eq!(WRITE_ARG_PATH.take(), None);
     }
 
     #[test]
-    fn peek_snippet_fails_if_snippet_provider_fails() {
-        let path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(true);
-        let mut snippet_prov = MockSnippetProvider::new();
-        snippet_prov
-            .expect_get_snippet()
-            .with(predicate::eq(3))
-            .once()
-            .returning(|_| Err(LazyCoderError::RunOutOfSnippets));
-        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_prov)));
+    fn forward_past_end_of_deck_stops_at_end_if_deck_stays() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_count(
+            5,
+            EndOfDeck::Stay,
+        ))));
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/config/path"),
             position: 3,
+            ..Config::default()
         };
 
-        let snippet = sut.peek();
+        assert!(sut.forward(4).is_ok(), "Unexpected error moving forward");
 
-        assert!(
-            matches!(snippet, Err(LazyCoderError::RunOutOfSnippets)),
-            "Snippet: {snippet:?}"
-        );
+        assert_eq!(sut.position, 5);
     }
 
     #[test]
-    fn forward_increases_position_and_saves() {
-        let mut path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
-        PATH_EXISTS_ANSWER.set(true);
+    fn forward_past_end_of_deck_wraps_if_deck_allows_it() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_count(
+            5,
+            EndOfDeck::Wrap,
+        ))));
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/config/path"),
             position: 3,
+            ..Config::default()
         };
 
-        let result = sut.forward(4);
+        assert!(sut.forward(4).is_ok(), "Unexpected error moving forward");
 
-        assert!(result.is_ok(), "Unexpected error moving forward");
-        path_buf.push(FILE_NAME);
-        assert_eq!(WRITE_ARG_PATH.take(), Some(path_buf));
-        assert_eq!(
-            WRITE_ARG_CONTENTS.take(),
-            Some(String::from(
-                "file_path = \"/some/config/path\"\nposition = 7\n"
-            ))
-        );
+        assert_eq!(sut.position, 2);
     }
 
     #[test]
     fn rewind_decreases_position_and_saves() {
         let mut path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
         PATH_EXISTS_ANSWER.set(true);
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/config/path"),
             position: 3,
+            ..Config::default()
         };
 
         let result = sut.rewind(2);
@@ -478,7 +3376,7 @@ mod tests {
         assert_eq!(
             WRITE_ARG_CONTENTS.take(),
             Some(String::from(
-                "file_path = \"/some/config/path\"\nposition = 1\n"
+                "schema_version = 1\nfile_path = \"/some/config/path\"\nposition = 1\n"
             ))
         );
     }
@@ -486,11 +3384,12 @@ mod tests {
     #[test]
     fn rewind_fails_when_decrement_moves_below_zero() {
         let path_buf = PathBuf::from("/some/config/path");
-        CONFIG_DIR_ANSWER.set(Some(path_buf.clone()));
+        STATE_DIR_ANSWER.set(Some(path_buf.clone()));
         PATH_EXISTS_ANSWER.set(true);
         let mut sut = Config {
-            file_path: String::from("/some/config/path"),
+            file_path: PathBuf::from("/some/config/path"),
             position: 3,
+            ..Config::default()
         };
 
         let result = sut.rewind(4);
@@ -501,20 +3400,181 @@ mod tests {
         );
     }
 
+    fn journal_operations(journal: &PositionJournal) -> Vec<(Operation, usize, usize, bool)> {
+        journal
+            .entries()
+            .map(|(entry, undone)| (entry.operation, entry.from, entry.to, undone))
+            .collect()
+    }
+
+    #[test]
+    fn config_new_records_start_from_last_position_of_deck() {
+        CANONICALIZE_ANSWER.set(Some(PathBuf::from("/tmp/talk.txt")));
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        let mut journal = PositionJournal::new(Path::new("/tmp/talk.txt"));
+        journal.record(Operation::Forward, 0, 8);
+        JOURNAL_ANSWER.set(Some(journal));
+
+        let sut = Config::new(Path::new("/tmp/talk.txt"), None, BTreeMap::new(), None);
+
+        assert!(sut.is_ok(), "Wrong config: {sut:?}");
+        let journal = JOURNAL_ARG.take().expect("Journal not saved");
+        assert_eq!(
+            journal_operations(&journal),
+            [
+                (Operation::Forward, 0, 8, false),
+                (Operation::Start, 8, 0, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_records_change_in_new_journal_if_deck_changed() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        let mut journal = PositionJournal::new(Path::new("/some/other/talk.txt"));
+        journal.record(Operation::Forward, 0, 8);
+        JOURNAL_ANSWER.set(Some(journal));
+        SNIPPET_PROVIDER_ANSWER.set(Some(Box::new(snippet_provider_with_count(
+            10,
+            EndOfDeck::Error,
+        ))));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/talk.txt"),
+            position: 3,
+            ..Config::default()
+        };
+
+        assert!(sut.forward(4).is_ok(), "Unexpected error moving forward");
+
+        let journal = JOURNAL_ARG.take().expect("Journal not saved");
+        assert_eq!(journal.file_path(), "/some/talk.txt");
+        assert_eq!(
+            journal_operations(&journal),
+            [(Operation::Forward, 3, 7, false)]
+        );
+    }
+
+    #[test]
+    fn history_ignores_journal_of_other_deck() {
+        let mut journal = PositionJournal::new(Path::new("/some/other/talk.txt"));
+        journal.record(Operation::Forward, 0, 8);
+        JOURNAL_ANSWER.set(Some(journal));
+        let sut = Config {
+            file_path: PathBuf::from("/some/talk.txt"),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            sut.history(),
+            PositionJournal::new(Path::new("/some/talk.txt"))
+        );
+    }
+
+    #[test]
+    fn undo_restores_previous_position_and_saves() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        let mut journal = PositionJournal::new(Path::new("/some/talk.txt"));
+        journal.record(Operation::Next, 2, 3);
+        journal.record(Operation::Forward, 3, 13);
+        JOURNAL_ANSWER.set(Some(journal));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/talk.txt"),
+            position: 13,
+            ..Config::default()
+        };
+
+        assert!(sut.undo().is_ok(), "Unexpected error undoing");
+
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/talk.txt\"\nposition = 3\n"
+            ))
+        );
+        let journal = JOURNAL_ARG.take().expect("Journal not saved");
+        assert_eq!(
+            journal_operations(&journal),
+            [
+                (Operation::Next, 2, 3, false),
+                (Operation::Forward, 3, 13, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_fails_without_changes() {
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/talk.txt"),
+            position: 13,
+            ..Config::default()
+        };
+
+        assert!(matches!(sut.undo(), Err(LazyCoderError::NothingToUndo)));
+        assert_eq!(sut.position, 13);
+    }
+
+    #[test]
+    fn redo_repeats_undone_change_and_saves() {
+        STATE_DIR_ANSWER.set(Some(PathBuf::from("/some/config/path")));
+        let mut journal = PositionJournal::new(Path::new("/some/talk.txt"));
+        journal.record(Operation::Rewind, 5, 0);
+        journal.undo();
+        JOURNAL_ANSWER.set(Some(journal));
+        let mut sut = Config {
+            file_path: PathBuf::from("/some/talk.txt"),
+            position: 5,
+            ..Config::default()
+        };
+
+        assert!(sut.redo().is_ok(), "Unexpected error redoing");
+
+        assert_eq!(
+            WRITE_ARG_CONTENTS.take(),
+            Some(String::from(
+                "schema_version = 1\nfile_path = \"/some/talk.txt\"\nposition = 0\n"
+            ))
+        );
+        assert!(matches!(sut.redo(), Err(LazyCoderError::NothingToRedo)));
+    }
+
+    fn snippet_with_text(text: &str) -> Snippet {
+        Snippet {
+            text: String::from(text),
+            ..Snippet::default()
+        }
+    }
+
     pub mod aux {
         use std::{
             io,
             path::{Path, PathBuf},
         };
 
-        use crate::{lazy_coder_error::LazyCoderError, snippet_handler::SnippetProvider};
+        use crate::{
+            config::session_file,
+            deck::{DeckFormat, DeckSettings},
+            lazy_coder_error::LazyCoderError,
+            position_journal::PositionJournal,
+            settings::Settings,
+            snippet_handler::SnippetProvider,
+            state_file::StateLock,
+        };
 
         use super::{
-            CANONICALIZE_ANSWER, CONFIG_DIR_ANSWER, CREATE_DIR_ALL_ARG, CREATE_DIR_OK_ANSWER,
-            PATH_EXISTS_ANSWER, READ_TO_STRING_ANSWER, SNIPPET_PROVIDER_ANSWER, WRITE_ARG_CONTENTS,
-            WRITE_ARG_PATH, WRITE_OK_ANSWER,
+            ACTIVE_SESSION_ANSWER, ACTIVE_SESSION_ARGS, CANONICALIZE_ANSWER, CONFIG_DIR_ANSWER,
+            COPY_ARGS, CREATE_DIR_ALL_ARG, CREATE_DIR_OK_ANSWER, ENV_VARS_ANSWER, FILE_HASH_ANSWER,
+            FIND_DECK_ANSWER, FREEZE_DECK_ANSWER, FREEZE_DECK_SESSION_ARGS, JOURNAL_ANSWER,
+            JOURNAL_ARG, LOCK_OK_ANSWER, MODIFIED_AND_SIZE_ANSWER, PATH_EXISTS_ANSWER,
+            PROJECT_ROOT_ANSWER, READ_TO_STRING_ANSWER, REMOVE_FILE_ARG, RENAME_ARGS,
+            SESSION_NAMES_ANSWER, SETTINGS_ANSWER, SETTINGS_ARG, SNIPPET_PROVIDER_ANSWER,
+            SNIPPET_PROVIDER_FORMAT_ARG, SNIPPET_PROVIDER_FROZEN_ARG, STATE_DIR_ANSWER,
+            WRITE_ARG_CONTENTS, WRITE_ARG_PATH, WRITE_OK_ANSWER,
         };
 
+        pub fn active_session() -> Option<String> {
+            ACTIVE_SESSION_ANSWER.with_borrow(Clone::clone)
+        }
+
         pub fn canonicalize<P: AsRef<Path>>(_path: P) -> io::Result<PathBuf> {
             match CANONICALIZE_ANSWER.take() {
                 Some(path_buf) => Ok(path_buf),
@@ -522,8 +3582,20 @@ mod tests {
             }
         }
 
-        pub(crate) fn config_dir() -> Option<PathBuf> {
-            CONFIG_DIR_ANSWER.take()
+        pub(crate) fn state_dir() -> Option<PathBuf> {
+            STATE_DIR_ANSWER.with_borrow(Clone::clone)
+        }
+
+        pub fn session_path(session: Option<&str>) -> Option<PathBuf> {
+            Some(state_dir()?.join(session_file(session)))
+        }
+
+        pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<u64> {
+            COPY_ARGS.set(Some((
+                from.as_ref().to_path_buf(),
+                to.as_ref().to_path_buf(),
+            )));
+            write_result().map(|()| 0)
         }
 
         pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
@@ -535,27 +3607,147 @@ mod tests {
             }
         }
 
+        pub fn env_var(name: &str) -> Option<String> {
+            ENV_VARS_ANSWER.with_borrow(|vars| vars.get(name).cloned())
+        }
+
+        pub fn freeze_deck(
+            _path: &Path,
+            session: Option<&str>,
+            format: DeckFormat,
+            _defaults: &DeckSettings,
+        ) -> Result<Vec<usize>, LazyCoderError> {
+            FREEZE_DECK_SESSION_ARGS.with_borrow_mut(|args| args.push(session.map(String::from)));
+            SNIPPET_PROVIDER_FORMAT_ARG.set(Some(format));
+            FREEZE_DECK_ANSWER
+                .take()
+                .ok_or(LazyCoderError::SnippetFileNotFound)
+        }
+
         pub fn get_snippet_provider(
             _path: &Path,
+            _session: Option<&str>,
+            format: DeckFormat,
+            frozen: bool,
+            _defaults: &DeckSettings,
         ) -> Result<Box<dyn SnippetProvider>, LazyCoderError> {
+            SNIPPET_PROVIDER_FORMAT_ARG.set(Some(format));
+            SNIPPET_PROVIDER_FROZEN_ARG.set(Some(frozen));
             match SNIPPET_PROVIDER_ANSWER.take() {
                 Some(snippet_prov) => Ok(snippet_prov),
                 None => Err(LazyCoderError::RunOutOfSnippets),
             }
         }
 
+        pub fn load_journal(_session: Option<&str>) -> PositionJournal {
+            JOURNAL_ANSWER.take().unwrap_or_default()
+        }
+
+        pub fn save_journal(
+            _session: Option<&str>,
+            journal: &PositionJournal,
+        ) -> Result<(), LazyCoderError> {
+            JOURNAL_ARG.set(Some(journal.clone()));
+            write_result().map_err(LazyCoderError::from)
+        }
+
+        pub fn rename_journal(_from: Option<&str>, _to: Option<&str>) {}
+
+        pub fn rename_frozen_deck(_from: Option<&str>, _to: Option<&str>) {}
+
+        pub fn load_settings() -> Result<Settings, LazyCoderError> {
+            Ok(toml::from_str(&SETTINGS_ANSWER.take().unwrap_or_default())?)
+        }
+
+        pub fn save_settings(settings: &Settings) -> Result<(), LazyCoderError> {
+            SETTINGS_ARG.set(Some(settings.clone()));
+            write_result().map_err(LazyCoderError::from)
+        }
+
+        pub fn remove_journal(_session: Option<&str>) {}
+
+        pub fn remove_frozen_deck(_session: Option<&str>) {}
+
+        pub fn lock_state() -> Result<Option<StateLock>, LazyCoderError> {
+            if LOCK_OK_ANSWER.get() {
+                Ok(None)
+            } else {
+                Err(LazyCoderError::StateLocked(PathBuf::from("lazycoder.lock")))
+            }
+        }
+
         pub fn path_exists<P: AsRef<Path>>(_path: P) -> bool {
             PATH_EXISTS_ANSWER.get()
         }
 
+        pub fn file_hash(_path: &Path) -> Option<String> {
+            FILE_HASH_ANSWER.take()
+        }
+
+        pub fn modified_and_size(_path: &Path) -> Option<(u64, u64)> {
+            MODIFIED_AND_SIZE_ANSWER.get()
+        }
+
+        pub fn find_deck(_missing: &Path, _hash: &str) -> Vec<PathBuf> {
+            FIND_DECK_ANSWER.take().unwrap_or_default()
+        }
+
+        pub fn project_root() -> Option<PathBuf> {
+            PROJECT_ROOT_ANSWER.with_borrow(Clone::clone)
+        }
+
+        pub fn use_global_state() {
+            PROJECT_ROOT_ANSWER.set(None);
+        }
+
+        pub fn config_dir() -> Option<PathBuf> {
+            CONFIG_DIR_ANSWER.with_borrow(Clone::clone)
+        }
+
+        pub fn use_config_dir(dir: PathBuf) {
+            CONFIG_DIR_ANSWER.set(Some(dir));
+        }
+
+        pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+            REMOVE_FILE_ARG.set(Some(path.as_ref().to_path_buf()));
+            write_result()
+        }
+
+        pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+            RENAME_ARGS.set(Some((
+                from.as_ref().to_path_buf(),
+                to.as_ref().to_path_buf(),
+            )));
+            write_result()
+        }
+
+        pub fn session_names(_dir: &Path) -> io::Result<Vec<String>> {
+            SESSION_NAMES_ANSWER
+                .take()
+                .ok_or_else(|| io::Error::other("Some error"))
+        }
+
+        pub fn set_active_session(session: Option<&str>) -> io::Result<()> {
+            ACTIVE_SESSION_ARGS.with_borrow_mut(|args| args.push(session.map(str::to_owned)));
+            write_result()
+        }
+
+        fn write_result() -> io::Result<()> {
+            if WRITE_OK_ANSWER.get() {
+                Ok(())
+            } else {
+                Err(io::Error::other("Some error"))
+            }
+        }
+
         pub(crate) fn read_to_string<P: AsRef<Path>>(_path: P) -> std::io::Result<String> {
             match READ_TO_STRING_ANSWER.take() {
                 Some(lines) => Ok(lines),
-                None => Err(io::Error::other("Some error")),
+                None => Err(io::Error::from(io::ErrorKind::NotFound)),
             }
         }
 
-        pub(crate) fn write<P: AsRef<Path>, C: AsRef<[u8]>>(
+        pub(crate) fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(
             path: P,
             contents: C,
         ) -> io::Result<()> {
@@ -0,0 +1,905 @@
+//! Deck format
+//!
+//! A deck is a plain text file with snippets separated by "\n---\n\n". Each snippet may start with an optional header
+//! delimited by two `+++` lines that contains its metadata in TOML:
+//!
+//! ```text
+//! +++
+//! id = "add-trait"
+//! title = "Add the trait"
+//! tags = ["traits"]
+//! notes = "Explain why we need a trait here."
+//! +++
+//! trait Shape {}
+//! ```
+//!
+//! The header is never part of the text provided for the snippet.
+//!
+//! The deck itself may start with an optional header delimited by two `===` lines with its settings in TOML:
+//!
+//! ```text
+//! ===
+//! title = "Traits in depth"
+//! author = "Jorge"
+//! separator = "%%%"
+//! language = "rust"
+//! end_of_deck = "wrap"
+//! templates = true
+//!
+//! [vars]
+//! project_name = "shapes"
+//! ===
+//! ```
+//!
+//! The separator is the text of the line that separates snippets, which must be followed by an empty line.
+//!
+//! The end-of-deck policy (`error`, `wrap`, `stay`, or `fallback`) sets what is provided past the last snippet. The
+//! `fallback` one provides the `fallback_text` of the header.
+//!
+//! Placeholders like `{{project_name}}` are only substituted with template variables in decks with `templates = true`,
+//! so the snippets of other decks are provided as they are.
+//!
+//! The cursor marker (`⟨cursor⟩` by default) sets the position of the cursor after the snippet is typed.
+//!
+//! A line that starts with a backslash followed by the separator, a header delimiter, or an include directive, like
+//! `\---`, is kept in the snippet as a literal line without the backslash.
+//!
+//! Decks with CRLF line endings or a leading byte order mark are supported. Snippets are provided with LF line endings
+//! unless `line_endings = "keep"` is set in the deck header.
+//!
+//! A snippet made only of include directives, such as `!include part2.lazycoder`, is replaced by the snippets of the
+//! included files. Their paths are relative to the file that includes them.
+use clap::ValueEnum;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::lazy_coder_error::LazyCoderError;
+
+static HEADER_DELIMITER: &str = "+++";
+static DECK_HEADER_DELIMITER: &str = "===";
+static DEFAULT_SEPARATOR: &str = "---";
+static INCLUDE_DIRECTIVE: &str = "!include";
+static DEFAULT_CURSOR_MARKER: &str = "⟨cursor⟩";
+static BYTE_ORDER_MARK: char = '\u{feff}';
+static ESCAPE: char = '\\';
+
+/// Format of the file that contains the deck.
+#[derive(ValueEnum, Deserialize, Serialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DeckFormat {
+    /// Snippets separated by a separator line
+    Lazycoder,
+    /// Fenced code blocks of a Markdown document
+    Markdown,
+}
+
+impl DeckFormat {
+    /// Detects the format of the deck from the extension of its path.
+    pub fn from_path(path: &Path) -> DeckFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown") => {
+                DeckFormat::Markdown
+            }
+            _ => DeckFormat::Lazycoder,
+        }
+    }
+}
+
+/// Behavior when the position goes beyond the last snippet of the deck.
+#[derive(Deserialize, Serialize, Default, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EndOfDeck {
+    /// Report that there are no more snippets.
+    #[default]
+    Error,
+    /// Start again from the first snippet.
+    Wrap,
+    /// Provide the last snippet again.
+    Stay,
+    /// Provide the fallback text of the deck.
+    Fallback,
+}
+
+/// Line endings of the text provided for the snippets.
+#[derive(Deserialize, Serialize, Default, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEndings {
+    /// Always use LF line endings.
+    #[default]
+    Normalize,
+    /// Use the line endings of the deck file.
+    Keep,
+}
+
+/// Deck-wide settings, as defined in the deck header.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeckSettings {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub separator: String,
+    pub language: Option<String>,
+    pub end_of_deck: EndOfDeck,
+    /// Text provided past the last snippet when the end-of-deck policy is [`EndOfDeck::Fallback`].
+    pub fallback_text: Option<String>,
+    pub cursor_marker: Option<String>,
+    pub line_endings: Option<LineEndings>,
+    /// Whether placeholders like `{{project_name}}` are substituted with template variables.
+    pub templates: bool,
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Default for DeckSettings {
+    fn default() -> Self {
+        DeckSettings {
+            title: None,
+            author: None,
+            separator: String::from(DEFAULT_SEPARATOR),
+            language: None,
+            end_of_deck: EndOfDeck::default(),
+            fallback_text: None,
+            cursor_marker: None,
+            line_endings: None,
+            templates: false,
+            vars: BTreeMap::new(),
+        }
+    }
+}
+
+impl DeckSettings {
+    /// Parses the deck header, if present, and returns the settings, the rest of the deck, and the line where that
+    /// rest starts (1-based). Settings that aren't defined in the header are taken from `defaults`.
+    pub fn parse<'a>(
+        deck: &'a str,
+        file: &Path,
+        defaults: &DeckSettings,
+    ) -> Result<(DeckSettings, &'a str, usize), LazyCoderError> {
+        let Some(rest) = strip_delimiter_line(deck, DECK_HEADER_DELIMITER) else {
+            return Ok((defaults.clone(), deck, 1));
+        };
+        let (header, body) = split_header(rest, DECK_HEADER_DELIMITER).ok_or_else(|| {
+            LazyCoderError::DeckParseError {
+                file: file.to_path_buf(),
+                line: 1,
+                message: String::from("deck header is not closed"),
+            }
+        })?;
+        // Parsed on its own first, so errors point to the line of the header where they are.
+        toml::from_str::<DeckSettings>(header).map_err(|err| LazyCoderError::DeckParseError {
+            file: file.to_path_buf(),
+            line: 2 + err.span().map_or(0, |span| line_of(header, span.start)),
+            message: err.message().to_owned(),
+        })?;
+        let settings = defaults.overridden_by(header);
+        if !is_valid_separator(&settings.separator) {
+            return Err(LazyCoderError::DeckParseError {
+                file: file.to_path_buf(),
+                line: 2,
+                message: String::from("separator must be a non-empty single line"),
+            });
+        }
+        Ok((settings, body, 3 + line_of(header, header.len())))
+    }
+
+    /// These settings with those defined in a valid header replacing them.
+    fn overridden_by(&self, header: &str) -> DeckSettings {
+        let mut merged = toml::Table::try_from(self).expect("Failing to encode settings");
+        merged
+            .extend(toml::from_str::<toml::Table>(header).expect("Failing to decode valid header"));
+        merged.try_into().expect("Failing to decode valid settings")
+    }
+
+    /// Uses the settings of the including deck for those that aren't defined in this one.
+    pub fn inherit(&mut self, parent: &DeckSettings) {
+        if self.language.is_none() {
+            self.language.clone_from(&parent.language);
+        }
+        if self.cursor_marker.is_none() {
+            self.cursor_marker.clone_from(&parent.cursor_marker);
+        }
+        if self.line_endings.is_none() {
+            self.line_endings = parent.line_endings;
+        }
+    }
+
+    /// Text that marks the position of the cursor in a snippet. Empty if disabled.
+    pub fn cursor_marker(&self) -> &str {
+        self.cursor_marker
+            .as_deref()
+            .unwrap_or(DEFAULT_CURSOR_MARKER)
+    }
+
+    /// Text that separates two snippets in the deck.
+    pub fn snippet_separator(&self) -> String {
+        format!("\n{}\n\n", self.separator)
+    }
+}
+
+/// Metadata of a snippet, as defined in its header.
+#[derive(Deserialize, Serialize, Default, PartialEq, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SnippetMetadata {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub notes: Option<String>,
+    pub language: Option<String>,
+}
+
+/// A snippet of the deck: the text that will be provided and its metadata.
+#[derive(Deserialize, Serialize, Default, PartialEq, Clone, Debug)]
+pub struct Snippet {
+    pub metadata: SnippetMetadata,
+    pub text: String,
+    /// Position of the cursor after the snippet is typed, as a byte offset of the text.
+    pub cursor: Option<usize>,
+}
+
+impl Snippet {
+    /// Parses a snippet, extracting its header if present.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - text of the snippet as found in the deck.
+    /// * `file` - path of the deck file that contains the snippet, used to report errors.
+    /// * `line` - line of the deck where the snippet starts (1-based), used to report errors.
+    pub fn parse(chunk: &str, file: &Path, line: usize) -> Result<Snippet, LazyCoderError> {
+        let Some(rest) = strip_delimiter_line(chunk, HEADER_DELIMITER) else {
+            return Ok(Snippet {
+                metadata: SnippetMetadata::default(),
+                text: chunk.to_owned(),
+                cursor: None,
+            });
+        };
+        let (header, text) =
+            split_header(rest, HEADER_DELIMITER).ok_or_else(|| LazyCoderError::DeckParseError {
+                file: file.to_path_buf(),
+                line,
+                message: String::from("snippet header is not closed"),
+            })?;
+        let metadata = toml::from_str(header).map_err(|err| LazyCoderError::DeckParseError {
+            file: file.to_path_buf(),
+            line: line + 1 + err.span().map_or(0, |span| line_of(header, span.start)),
+            message: err.message().to_owned(),
+        })?;
+        Ok(Snippet {
+            metadata,
+            text: text.to_owned(),
+            cursor: None,
+        })
+    }
+
+    /// Turns escaped lines, like `\---`, into literal ones, like `---`, by removing their first backslash.
+    ///
+    /// Lines are escaped if they start with one or more backslashes followed by the separator line, a header delimiter
+    /// or an include directive.
+    pub fn unescape(&mut self, settings: &DeckSettings) {
+        if !self.text.contains(ESCAPE) {
+            return;
+        }
+        let markers = [
+            settings.separator.as_str(),
+            HEADER_DELIMITER,
+            DECK_HEADER_DELIMITER,
+            INCLUDE_DIRECTIVE,
+        ];
+        self.text = self
+            .text
+            .split_inclusive('\n')
+            .map(|line| match line.strip_prefix(ESCAPE) {
+                Some(rest)
+                    if markers
+                        .iter()
+                        .any(|marker| rest.trim_start_matches(ESCAPE).starts_with(marker)) =>
+                {
+                    rest
+                }
+                _ => line,
+            })
+            .collect();
+    }
+
+    /// Completes the snippet with the deck settings: default language, cursor position, and line endings.
+    ///
+    /// Only the first cursor marker sets the position of the cursor. Any other marker is removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - settings of the deck file that contains the snippet.
+    /// * `crlf` - whether the deck file uses CRLF line endings.
+    pub fn apply_settings(&mut self, settings: &DeckSettings, crlf: bool) {
+        if self.metadata.language.is_none() {
+            self.metadata.language.clone_from(&settings.language);
+        }
+        let marker = settings.cursor_marker();
+        if !marker.is_empty()
+            && let Some(cursor) = self.text.find(marker)
+        {
+            self.text = self.text.replace(marker, "");
+            self.cursor = Some(cursor);
+        }
+        if crlf && settings.line_endings == Some(LineEndings::Keep) {
+            self.cursor = self
+                .cursor
+                .map(|cursor| cursor + self.text[..cursor].matches('\n').count());
+            self.text = self.text.replace('\n', "\r\n");
+        }
+    }
+}
+
+/// Whether the text can be a separator line, i.e. it is a single line that isn't blank.
+pub fn is_valid_separator(separator: &str) -> bool {
+    !separator.trim().is_empty() && !separator.contains('\n')
+}
+
+/// Removes the byte order mark and converts CRLF line endings to LF.
+///
+/// Returns the normalized text and whether the original one had CRLF line endings.
+pub fn normalize_line_endings(text: &str) -> (String, bool) {
+    let text = text.strip_prefix(BYTE_ORDER_MARK).unwrap_or(text);
+    if text.contains("\r\n") {
+        (text.replace("\r\n", "\n"), true)
+    } else {
+        (text.to_owned(), false)
+    }
+}
+
+/// Maps offsets of the normalized text of a deck file to offsets of its original contents.
+pub struct RawOffsets {
+    byte_order_mark: usize,
+    /// Offsets of the normalized text where a carriage return was removed.
+    removed: Vec<usize>,
+}
+
+impl RawOffsets {
+    pub fn new(text: &str) -> RawOffsets {
+        let byte_order_mark = if text.starts_with(BYTE_ORDER_MARK) {
+            BYTE_ORDER_MARK.len_utf8()
+        } else {
+            0
+        };
+        let removed = text[byte_order_mark..]
+            .match_indices("\r\n")
+            .enumerate()
+            .map(|(count, (offset, _))| offset - count)
+            .collect();
+        RawOffsets {
+            byte_order_mark,
+            removed,
+        }
+    }
+
+    /// Offset of the original contents that corresponds to the offset of the normalized text.
+    pub fn raw(&self, offset: usize) -> usize {
+        self.byte_order_mark + offset + self.removed.partition_point(|removed| *removed < offset)
+    }
+}
+
+/// Hash of the contents of a file, used to identify them.
+///
+/// It uses 64-bit FNV-1a, that is stable across platforms and versions.
+pub fn content_hash(contents: &[u8]) -> String {
+    let hash = contents
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{hash:016x}")
+}
+
+/// Extracts the include directives of a snippet as pairs of line and included path.
+///
+/// Returns `None` if the snippet has no include directives, and an error if it mixes them with other content.
+pub fn include_directives<'a>(
+    chunk: &'a str,
+    file: &Path,
+    line: usize,
+) -> Result<Option<Vec<(usize, &'a str)>>, LazyCoderError> {
+    let mut directives = Vec::new();
+    let mut other_content = false;
+    for (index, text) in chunk.lines().enumerate() {
+        match text.strip_prefix(INCLUDE_DIRECTIVE) {
+            Some(target) if target.is_empty() || target.starts_with(char::is_whitespace) => {
+                let target = target.trim();
+                if target.is_empty() {
+                    return Err(LazyCoderError::DeckParseError {
+                        file: file.to_path_buf(),
+                        line: line + index,
+                        message: String::from("include directive without a path"),
+                    });
+                }
+                directives.push((line + index, target));
+            }
+            _ => other_content |= !text.trim().is_empty(),
+        }
+    }
+    match directives.first() {
+        None => Ok(None),
+        Some((directive_line, _)) if other_content => Err(LazyCoderError::DeckParseError {
+            file: file.to_path_buf(),
+            line: *directive_line,
+            message: String::from("include directives must be in a snippet of their own"),
+        }),
+        Some(_) => Ok(Some(directives)),
+    }
+}
+
+/// Returns the text after the first line if that line is the given header delimiter.
+fn strip_delimiter_line<'a>(text: &'a str, delimiter: &str) -> Option<&'a str> {
+    let rest = text.strip_prefix(delimiter)?;
+    if rest.is_empty() {
+        Some(rest)
+    } else {
+        rest.strip_prefix('\n')
+    }
+}
+
+/// Splits the text after the opening delimiter into the header and the text after the closing delimiter.
+fn split_header<'a>(text: &'a str, delimiter: &str) -> Option<(&'a str, &'a str)> {
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim_end_matches('\n') == delimiter {
+            return Some((&text[..offset], &text[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Number of line breaks before the given byte offset.
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_is_detected_from_extension() {
+        assert_eq!(
+            DeckFormat::from_path(Path::new("/talks/traits.md")),
+            DeckFormat::Markdown
+        );
+        assert_eq!(
+            DeckFormat::from_path(Path::new("/talks/traits.Markdown")),
+            DeckFormat::Markdown
+        );
+        assert_eq!(
+            DeckFormat::from_path(Path::new("/talks/traits.lazycoder")),
+            DeckFormat::Lazycoder
+        );
+        assert_eq!(
+            DeckFormat::from_path(Path::new("/talks/traits")),
+            DeckFormat::Lazycoder
+        );
+    }
+
+    #[test]
+    fn snippet_without_header_is_kept_verbatim() {
+        let result = Snippet::parse("fn main() {}\n", Path::new("deck.lazycoder"), 1);
+
+        assert!(
+            matches!(result, Ok(ref snippet) if *snippet == Snippet {
+                metadata: SnippetMetadata::default(),
+                text: String::from("fn main() {}\n"),
+                cursor: None,
+            }),
+            "Unexpected snippet: {result:?}"
+        );
+    }
+
+    #[test]
+    fn snippet_header_is_parsed_and_removed_from_text() {
+        let result = Snippet::parse(
+            "+++\nid = \"add-trait\"\ntitle = \"Add trait\"\ntags = [\"traits\", \"intro\"]\nnotes = \"Explain it\"\n+++\ntrait Shape {}\n",
+            Path::new("deck.lazycoder"),
+            1,
+        );
+
+        assert!(
+            matches!(result, Ok(ref snippet) if *snippet == Snippet {
+                metadata: SnippetMetadata {
+                    id: Some(String::from("add-trait")),
+                    title: Some(String::from("Add trait")),
+                    tags: vec![String::from("traits"), String::from("intro")],
+                    notes: Some(String::from("Explain it")),
+                    language: None,
+                },
+                text: String::from("trait Shape {}\n"),
+                cursor: None,
+            }),
+            "Unexpected snippet: {result:?}"
+        );
+    }
+
+    #[test]
+    fn snippet_with_only_header_has_empty_text() {
+        let result = Snippet::parse("+++\nid = \"empty\"\n+++", Path::new("deck.lazycoder"), 1);
+
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text.is_empty()
+                && snippet.metadata.id.as_deref() == Some("empty")),
+            "Unexpected snippet: {result:?}"
+        );
+    }
+
+    #[test]
+    fn settings_provide_default_language_and_cursor_position() {
+        let mut snippet = Snippet {
+            text: String::from("fn main() {\n    ⟨cursor⟩\n}\n⟨cursor⟩"),
+            ..Snippet::default()
+        };
+
+        snippet.apply_settings(
+            &DeckSettings {
+                language: Some(String::from("rust")),
+                ..DeckSettings::default()
+            },
+            false,
+        );
+
+        assert_eq!(snippet.text, "fn main() {\n    \n}\n");
+        assert_eq!(snippet.cursor, Some(16));
+        assert_eq!(snippet.metadata.language.as_deref(), Some("rust"));
+    }
+
+    #[test]
+    fn snippet_language_is_not_overridden_by_settings() {
+        let mut snippet = Snippet {
+            metadata: SnippetMetadata {
+                language: Some(String::from("toml")),
+                ..SnippetMetadata::default()
+            },
+            text: String::from("[package]\n"),
+            cursor: None,
+        };
+
+        snippet.apply_settings(
+            &DeckSettings {
+                language: Some(String::from("rust")),
+                ..DeckSettings::default()
+            },
+            false,
+        );
+
+        assert_eq!(snippet.metadata.language.as_deref(), Some("toml"));
+        assert_eq!(snippet.cursor, None);
+    }
+
+    #[test]
+    fn empty_cursor_marker_disables_it() {
+        let mut snippet = Snippet {
+            text: String::from("a ⟨cursor⟩ b"),
+            ..Snippet::default()
+        };
+
+        snippet.apply_settings(
+            &DeckSettings {
+                cursor_marker: Some(String::new()),
+                ..DeckSettings::default()
+            },
+            false,
+        );
+
+        assert_eq!(snippet.text, "a ⟨cursor⟩ b");
+        assert_eq!(snippet.cursor, None);
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept_if_requested() {
+        let mut snippet = Snippet {
+            text: String::from("fn main() {\n⟨cursor⟩\n}\n"),
+            ..Snippet::default()
+        };
+
+        snippet.apply_settings(
+            &DeckSettings {
+                line_endings: Some(LineEndings::Keep),
+                ..DeckSettings::default()
+            },
+            true,
+        );
+
+        assert_eq!(snippet.text, "fn main() {\r\n\r\n}\r\n");
+        assert_eq!(snippet.cursor, Some(13));
+    }
+
+    #[test]
+    fn crlf_line_endings_are_normalized_by_default() {
+        let mut snippet = Snippet {
+            text: String::from("fn main() {\n}\n"),
+            ..Snippet::default()
+        };
+
+        snippet.apply_settings(&DeckSettings::default(), true);
+
+        assert_eq!(snippet.text, "fn main() {\n}\n");
+    }
+
+    #[test]
+    fn byte_order_mark_and_crlf_are_normalized() {
+        assert_eq!(
+            normalize_line_endings("\u{feff}First\r\n\r\n---\r\n\r\nSecond\r\n"),
+            (String::from("First\n\n---\n\nSecond\n"), true)
+        );
+        assert_eq!(
+            normalize_line_endings("First\n\n---\n\nSecond\n"),
+            (String::from("First\n\n---\n\nSecond\n"), false)
+        );
+    }
+
+    #[test]
+    fn escaped_lines_are_unescaped_once() {
+        let mut snippet = Snippet {
+            text: String::from(
+                "title: Demo\n\\---\n\n\\\\---\n\\+++\n\\===\n\\!include other\n\\n---\n\\%%%\n",
+            ),
+            ..Snippet::default()
+        };
+
+        snippet.unescape(&DeckSettings::default());
+
+        assert_eq!(
+            snippet.text,
+            "title: Demo\n---\n\n\\---\n+++\n===\n!include other\n\\n---\n\\%%%\n"
+        );
+    }
+
+    #[test]
+    fn escaped_custom_separator_is_unescaped() {
+        let mut snippet = Snippet {
+            text: String::from("\\%%%\n"),
+            ..Snippet::default()
+        };
+
+        snippet.unescape(&DeckSettings {
+            separator: String::from("%%%"),
+            ..DeckSettings::default()
+        });
+
+        assert_eq!(snippet.text, "%%%\n");
+    }
+
+    #[test]
+    fn raw_offsets_skip_byte_order_mark_and_carriage_returns() {
+        let raw = "\u{feff}ab\r\ncd\r\n\r\nef";
+        let (normalized, _) = normalize_line_endings(raw);
+        let sut = RawOffsets::new(raw);
+
+        for (offset, _) in normalized.char_indices() {
+            let raw_offset = sut.raw(offset);
+            assert_eq!(
+                raw[raw_offset..].replace('\r', ""),
+                normalized[offset..],
+                "Wrong raw offset for {offset}"
+            );
+        }
+        assert_eq!(sut.raw(normalized.len()), raw.len());
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c");
+        assert_ne!(content_hash(b"First"), content_hash(b"first"));
+    }
+
+    #[test]
+    fn unclosed_snippet_header_is_an_error() {
+        let result = Snippet::parse(
+            "+++\nid = \"add-trait\"\ntrait Shape {}\n",
+            Path::new("deck.lazycoder"),
+            7,
+        );
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 7, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn invalid_snippet_header_reports_its_line() {
+        let result = Snippet::parse(
+            "+++\nid = \"add-trait\"\nunknown = 3\n+++\ntext\n",
+            Path::new("deck.lazycoder"),
+            7,
+        );
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 9, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn snippet_without_include_directives_has_none() {
+        let result = include_directives(
+            "fn main() {\n    // !include nothing\n}\n",
+            Path::new("deck.lazycoder"),
+            1,
+        );
+
+        assert!(matches!(result, Ok(None)), "Unexpected result: {result:?}");
+    }
+
+    #[test]
+    fn include_directives_are_extracted_with_their_lines() {
+        let result = include_directives(
+            "!include part1.lazycoder\n\n!include  dir/part 2.lazycoder \n",
+            Path::new("deck.lazycoder"),
+            10,
+        );
+
+        assert!(
+            matches!(result, Ok(Some(ref directives)) if *directives == vec![
+                (10, "part1.lazycoder"),
+                (12, "dir/part 2.lazycoder"),
+            ]),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn include_directive_mixed_with_content_is_an_error() {
+        let result = include_directives(
+            "fn main() {}\n!include part1.lazycoder\n",
+            Path::new("deck.lazycoder"),
+            10,
+        );
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 11, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn include_directive_without_path_is_an_error() {
+        let result = include_directives("!include \n", Path::new("deck.lazycoder"), 3);
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 3, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn deck_without_header_uses_default_settings() {
+        let result = DeckSettings::parse(
+            "First snippet\n\n---\n\nSecond snippet\n",
+            Path::new("deck.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert!(
+            matches!(result, Ok((ref settings, body, 1)) if *settings == DeckSettings::default()
+                && body == "First snippet\n\n---\n\nSecond snippet\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn deck_header_is_parsed_and_removed_from_body() {
+        let result = DeckSettings::parse(
+            "===\ntitle = \"Traits\"\nauthor = \"Jorge\"\nseparator = \"%%%\"\nlanguage = \"rust\"\nend_of_deck = \"wrap\"\ntemplates = true\n[vars]\nproject_name = \"shapes\"\n===\nFirst snippet\n",
+            Path::new("deck.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert!(
+            matches!(result, Ok((ref settings, body, 11)) if *settings == DeckSettings {
+                title: Some(String::from("Traits")),
+                author: Some(String::from("Jorge")),
+                separator: String::from("%%%"),
+                language: Some(String::from("rust")),
+                end_of_deck: EndOfDeck::Wrap,
+                fallback_text: None,
+                cursor_marker: None,
+                line_endings: None,
+                templates: true,
+                vars: BTreeMap::from([(String::from("project_name"), String::from("shapes"))]),
+            } && body == "First snippet\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn fallback_end_of_deck_is_parsed_with_its_text() {
+        let result = DeckSettings::parse(
+            "===\nend_of_deck = \"fallback\"\nfallback_text = \"// The end\"\n===\nFirst snippet\n",
+            Path::new("deck.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert!(
+            matches!(result, Ok((ref settings, _, _)) if settings.end_of_deck == EndOfDeck::Fallback
+                && settings.fallback_text.as_deref() == Some("// The end")),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn deck_settings_take_missing_values_from_defaults() {
+        let defaults = DeckSettings {
+            separator: String::from("%%%"),
+            end_of_deck: EndOfDeck::Wrap,
+            ..DeckSettings::default()
+        };
+
+        let result = DeckSettings::parse(
+            "===\ntitle = \"Traits\"\nend_of_deck = \"error\"\n===\nFirst snippet\n",
+            Path::new("deck.lazycoder"),
+            &defaults,
+        );
+
+        assert!(
+            matches!(result, Ok((ref settings, _, 5)) if *settings == DeckSettings {
+                title: Some(String::from("Traits")),
+                separator: String::from("%%%"),
+                end_of_deck: EndOfDeck::Error,
+                ..DeckSettings::default()
+            }),
+            "Unexpected result: {result:?}"
+        );
+        assert!(
+            matches!(DeckSettings::parse("First snippet\n", Path::new("deck.lazycoder"), &defaults),
+                Ok((ref settings, _, 1)) if *settings == defaults),
+            "Defaults are not used without header"
+        );
+    }
+
+    #[test]
+    fn unclosed_deck_header_is_an_error() {
+        let result = DeckSettings::parse(
+            "===\ntitle = \"Traits\"\nFirst snippet\n",
+            Path::new("deck.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 1, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn invalid_deck_header_reports_its_line() {
+        let result = DeckSettings::parse(
+            "===\ntitle = \"Traits\"\nend_of_deck = \"loop\"\n===\n",
+            Path::new("deck.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 3, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn empty_separator_is_an_error() {
+        let result = DeckSettings::parse(
+            "===\nseparator = \" \"\n===\n",
+            Path::new("deck.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn snippet_separator_is_built_from_separator_line() {
+        let settings = DeckSettings {
+            separator: String::from("%%%"),
+            ..DeckSettings::default()
+        };
+
+        assert_eq!(settings.snippet_separator(), "\n%%%\n\n");
+    }
+}
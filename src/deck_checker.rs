@@ -0,0 +1,695 @@
+//! Deck checker
+//!
+//! Finds the mistakes of a deck that make it provide unexpected snippets, like a separator with trailing spaces that
+//! isn't recognized, and reports them with their file, line and column. Errors change how the deck is split or make
+//! a snippet invalid, while warnings point to snippets that are probably not what was intended.
+//!
+//! Decks can be rewritten in a canonical form that fixes the separators and drops the empty snippets, without changing
+//! the text of the other snippets.
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde_derive::Serialize;
+
+use crate::{
+    deck::{self, DeckFormat, DeckSettings, Snippet},
+    lazy_coder_error::LazyCoderError,
+    markdown_handler::MarkdownHandler,
+    snippet_handler::{self, SnippetProvider},
+    state_file::write_atomic,
+};
+
+static BYTE_ORDER_MARK: &str = "\u{feff}";
+
+/// Maximum nesting of included files that are checked.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+#[derive(Serialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a deck. Lines and columns are 1-based.
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file.display(),
+            self.line,
+            self.column,
+            self.severity,
+            self.message
+        )
+    }
+}
+
+/// Problems found in a deck and the files that have been rewritten to fix them.
+#[derive(Serialize, Default, Debug)]
+pub struct Report {
+    pub diagnostics: Vec<Diagnostic>,
+    pub fixed: Vec<PathBuf>,
+}
+
+impl Report {
+    /// Number of errors found.
+    pub fn errors(&self) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .count()
+    }
+}
+
+/// Checks the deck and the files it includes.
+///
+/// # Arguments
+///
+/// * `path` - path to the deck file.
+/// * `format` - format of the deck file.
+/// * `fix` - whether the files are rewritten in canonical form before checking them.
+/// * `defaults` - settings used for the files that don't define them.
+pub fn check_deck(
+    path: &Path,
+    format: DeckFormat,
+    fix: bool,
+    defaults: &DeckSettings,
+) -> Result<Report, LazyCoderError> {
+    let mut report = Report::default();
+    match format {
+        DeckFormat::Lazycoder => {
+            check_file(path, defaults, None, &mut Vec::new(), fix, &mut report)?;
+        }
+        DeckFormat::Markdown => {
+            if fix {
+                warn!("Markdown decks are not rewritten");
+            }
+            if let Err(err) = MarkdownHandler::new(path)?
+                .with_defaults(defaults.clone())
+                .settings()
+            {
+                report.diagnostics.push(diagnostic_from(err)?);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Checks a deck file, and recursively the files it includes, adding the problems found to the report.
+fn check_file(
+    path: &Path,
+    defaults: &DeckSettings,
+    parent: Option<&DeckSettings>,
+    including: &mut Vec<PathBuf>,
+    fix: bool,
+    report: &mut Report,
+) -> Result<(), LazyCoderError> {
+    let raw = fs::read_to_string(path).map_err(LazyCoderError::SnippetFileError)?;
+    let (mut text, crlf) = deck::normalize_line_endings(&raw);
+    if fix && let Some(fixed) = canonical_form(&text, path, defaults) {
+        let mut contents = if raw.starts_with(BYTE_ORDER_MARK) {
+            String::from(BYTE_ORDER_MARK)
+        } else {
+            String::new()
+        };
+        contents.push_str(&if crlf {
+            fixed.replace('\n', "\r\n")
+        } else {
+            fixed.clone()
+        });
+        rewrite(path, &contents).map_err(LazyCoderError::SnippetFileError)?;
+        report.fixed.push(path.to_path_buf());
+        text = fixed;
+    }
+    let (settings, includes) = check_text(&text, path, defaults, parent, &mut report.diagnostics);
+    let Some(settings) = settings else {
+        return Ok(());
+    };
+    including.push(path.to_path_buf());
+    for (line, target) in includes {
+        let included_path = snippet_handler::resolve_include(path, &target);
+        let mut error = |message: String| {
+            report.diagnostics.push(Diagnostic {
+                file: path.to_path_buf(),
+                line,
+                column: 1,
+                severity: Severity::Error,
+                message,
+            });
+        };
+        if including.contains(&included_path) {
+            error(format!(
+                "include cycle: {} is already being included",
+                included_path.display()
+            ));
+        } else if including.len() >= MAX_INCLUDE_DEPTH {
+            error(String::from("includes are nested too deeply"));
+        } else if let Err(err) = check_file(
+            &included_path,
+            defaults,
+            Some(&settings),
+            including,
+            fix,
+            report,
+        ) {
+            report.diagnostics.push(Diagnostic {
+                file: path.to_path_buf(),
+                line,
+                column: 1,
+                severity: Severity::Error,
+                message: format!(
+                    "cannot read included file {}: {err}",
+                    included_path.display()
+                ),
+            });
+        }
+    }
+    including.pop();
+    Ok(())
+}
+
+/// Replaces the contents of a deck file at once, so it isn't lost if the rewrite is interrupted, keeping its
+/// permissions. If it is a symbolic link, the file it points to is replaced.
+fn rewrite(path: &Path, contents: &str) -> io::Result<()> {
+    let target = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&target)?.permissions();
+    write_atomic(&target, contents)?;
+    fs::set_permissions(&target, permissions)
+}
+
+/// Checks the normalized text of a deck file.
+///
+/// Returns the settings of the file, unless its header can't be parsed, and its include directives.
+fn check_text(
+    text: &str,
+    file: &Path,
+    defaults: &DeckSettings,
+    parent: Option<&DeckSettings>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (Option<DeckSettings>, Vec<(usize, String)>) {
+    let diagnostic = |line: usize, column: usize, severity: Severity, message: String| Diagnostic {
+        file: file.to_path_buf(),
+        line,
+        column,
+        severity,
+        message,
+    };
+    let (mut settings, body, first_line) = match DeckSettings::parse(text, file, defaults) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            diagnostics.extend(diagnostic_from(err).ok());
+            return (None, Vec::new());
+        }
+    };
+    if let Some(parent) = parent {
+        settings.inherit(parent);
+    }
+
+    check_separators(
+        body,
+        first_line,
+        &settings.separator,
+        diagnostic,
+        diagnostics,
+    );
+
+    let snippet_separator = settings.snippet_separator();
+    let marker = settings.cursor_marker();
+    let mut includes = Vec::new();
+    let mut first_occurrences = HashMap::new();
+    let mut endings = Vec::new();
+    let mut line = first_line;
+    for chunk in body.split(&snippet_separator) {
+        let chunk_line = line;
+        line += chunk.matches('\n').count() + snippet_separator.matches('\n').count();
+        match deck::include_directives(chunk, file, chunk_line) {
+            Ok(Some(directives)) => {
+                includes.extend(
+                    directives
+                        .into_iter()
+                        .map(|(line, target)| (line, target.to_owned())),
+                );
+                continue;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                diagnostics.extend(diagnostic_from(err).ok());
+                continue;
+            }
+        }
+        let mut snippet = match Snippet::parse(chunk, file, chunk_line) {
+            Ok(snippet) => snippet,
+            Err(err) => {
+                diagnostics.extend(diagnostic_from(err).ok());
+                continue;
+            }
+        };
+        // The text of the snippet is the end of the chunk, after its header.
+        let text_start = chunk.len() - snippet.text.len();
+        snippet.unescape(&settings);
+        if snippet.text.trim().is_empty() {
+            diagnostics.push(diagnostic(
+                chunk_line,
+                1,
+                Severity::Warning,
+                String::from("snippet is empty"),
+            ));
+            continue;
+        }
+        match first_occurrences.entry(snippet.text) {
+            Entry::Occupied(first) => diagnostics.push(diagnostic(
+                chunk_line,
+                1,
+                Severity::Warning,
+                format!("snippet is a duplicate of the one at line {}", first.get()),
+            )),
+            Entry::Vacant(entry) => {
+                entry.insert(chunk_line);
+            }
+        }
+        if !marker.is_empty() {
+            for (offset, _) in chunk[text_start..].match_indices(marker).skip(1) {
+                let (line, column) = position_of(chunk, text_start + offset);
+                diagnostics.push(diagnostic(
+                    chunk_line + line,
+                    column,
+                    Severity::Error,
+                    String::from("snippet has more than one cursor marker"),
+                ));
+            }
+        }
+        let last_line = chunk_line + chunk.trim_end_matches('\n').matches('\n').count();
+        endings.push((last_line, chunk.ends_with('\n')));
+    }
+
+    check_endings(&endings, diagnostic, diagnostics);
+    (Some(settings), includes)
+}
+
+/// Checks that the separator lines don't have trailing whitespace, and warns about the lines like the separator that
+/// aren't followed by an empty line, since they are part of a snippet and may be a separator missing it.
+fn check_separators<F>(
+    body: &str,
+    first_line: usize,
+    separator: &str,
+    diagnostic: F,
+    diagnostics: &mut Vec<Diagnostic>,
+) where
+    F: Fn(usize, usize, Severity, String) -> Diagnostic,
+{
+    let lines: Vec<&str> = body.lines().collect();
+    let content_warning = |index: usize| {
+        diagnostic(
+            first_line + index,
+            1,
+            Severity::Warning,
+            format!(
+                "line like the separator isn't followed by an empty line, so it is part of the snippet; escape it as \
+                 `\\{separator}` to keep it, or add an empty line after it to split the snippet"
+            ),
+        )
+    };
+    for (index, line) in lines.iter().enumerate() {
+        let followed_by_empty = lines.get(index + 1).is_some_and(|next| next.is_empty());
+        if *line == separator {
+            if index > 0 && !followed_by_empty {
+                diagnostics.push(content_warning(index));
+            }
+        } else if line.trim_end() == separator && !followed_by_empty {
+            diagnostics.push(content_warning(index));
+        } else if line.trim_end() == separator {
+            diagnostics.push(diagnostic(
+                first_line + index,
+                separator.chars().count() + 1,
+                Severity::Error,
+                String::from("separator has trailing whitespace"),
+            ));
+        }
+    }
+}
+
+/// Checks that all the snippets end like most of them, either with or without a newline.
+///
+/// # Arguments
+///
+/// * `endings` - last line of each snippet and whether it ends with a newline.
+fn check_endings<F>(endings: &[(usize, bool)], diagnostic: F, diagnostics: &mut Vec<Diagnostic>)
+where
+    F: Fn(usize, usize, Severity, String) -> Diagnostic,
+{
+    let with_newline = endings.iter().filter(|(_, newline)| *newline).count();
+    if with_newline > 0 && with_newline < endings.len() {
+        let majority = with_newline * 2 > endings.len();
+        for (line, _) in endings.iter().filter(|(_, newline)| *newline != majority) {
+            diagnostics.push(diagnostic(
+                *line,
+                1,
+                Severity::Warning,
+                String::from(if majority {
+                    "snippet ends without a newline, unlike most snippets of the file"
+                } else {
+                    "snippet ends with a newline, unlike most snippets of the file"
+                }),
+            ));
+        }
+    }
+}
+
+/// Rewrites the normalized text of a deck file in canonical form.
+///
+/// Separators followed by an empty line lose their trailing whitespace and empty snippets are dropped. The text of the
+/// other snippets is kept as it is, including lines like the separator that aren't followed by an empty line, e.g. a
+/// YAML document marker, and their trailing newlines, that are typed with them. Returns `None` if the text doesn't
+/// change or its header can't be parsed.
+fn canonical_form(text: &str, file: &Path, defaults: &DeckSettings) -> Option<String> {
+    let (settings, body, _) = DeckSettings::parse(text, file, defaults).ok()?;
+    let separator = settings.separator.as_str();
+    let mut fixed_body = String::with_capacity(body.len());
+    let mut lines = body.split_inclusive('\n').peekable();
+    let mut first = true;
+    while let Some(line) = lines.next() {
+        let content = line.trim_end_matches('\n');
+        if !first
+            && content != separator
+            && content.trim_end() == separator
+            && lines.peek() == Some(&"\n")
+        {
+            fixed_body.push_str(separator);
+            fixed_body.push('\n');
+        } else {
+            fixed_body.push_str(line);
+        }
+        first = false;
+    }
+
+    let snippet_separator = settings.snippet_separator();
+    let chunks: Vec<&str> = fixed_body
+        .split(&snippet_separator)
+        .filter(|chunk| !chunk.trim().is_empty())
+        .collect();
+
+    let fixed = format!(
+        "{}{}",
+        &text[..text.len() - body.len()],
+        chunks.join(&snippet_separator)
+    );
+    (fixed != text).then_some(fixed)
+}
+
+/// Line (0-based) and column (1-based) of a byte offset of the text.
+fn position_of(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (
+        before.matches('\n').count(),
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+/// Turns a parse error into an error diagnostic. Any other error is returned as is.
+fn diagnostic_from(err: LazyCoderError) -> Result<Diagnostic, LazyCoderError> {
+    match err {
+        LazyCoderError::DeckParseError {
+            file,
+            line,
+            message,
+        } => Ok(Diagnostic {
+            file,
+            line,
+            column: 1,
+            severity: Severity::Error,
+            message,
+        }),
+        err => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn check(text: &str) -> Vec<(usize, usize, Severity, String)> {
+        let mut diagnostics = Vec::new();
+        check_text(
+            text,
+            Path::new("/talks/main.lazycoder"),
+            &DeckSettings::default(),
+            None,
+            &mut diagnostics,
+        );
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.line,
+                    diagnostic.column,
+                    diagnostic.severity,
+                    diagnostic.message,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_deck_has_no_diagnostics() {
+        assert_eq!(
+            check(
+                "===\ntitle = \"Traits\"\n===\nFirst\n\n---\n\n+++\nid = \"second\"\n+++\nSecond\n"
+            ),
+            []
+        );
+    }
+
+    #[test]
+    fn separator_with_trailing_whitespace_is_error() {
+        let diagnostics = check("First\n\n--- \n\nSecond\n");
+
+        assert_eq!(
+            diagnostics,
+            [(
+                3,
+                4,
+                Severity::Error,
+                String::from("separator has trailing whitespace")
+            )]
+        );
+    }
+
+    #[test]
+    fn lines_like_separator_in_snippets_are_warnings() {
+        let diagnostics = check("First\n\n---\n\nSecond\n---\nThird\n--- \nFourth\n\\---\nFifth\n");
+
+        let warning = String::from(
+            "line like the separator isn't followed by an empty line, so it is part of the snippet; escape it as \
+             `\\---` to keep it, or add an empty line after it to split the snippet",
+        );
+        assert_eq!(
+            diagnostics,
+            [
+                (6, 1, Severity::Warning, warning.clone()),
+                (8, 1, Severity::Warning, warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn suspicious_snippets_are_warnings() {
+        let diagnostics = check(
+            "===\nseparator = \"%%%\"\n===\nfn main() {}\n\n%%%\n\n\n\n%%%\n\nfn main() {}\n\n%%%\n\nlet x = ⟨cursor⟩;\nlet y = 2;\n\n%%%\n\nNo newline\n%%%\n\nLast\n",
+        );
+
+        assert_eq!(
+            diagnostics,
+            [
+                (8, 1, Severity::Warning, String::from("snippet is empty")),
+                (
+                    12,
+                    1,
+                    Severity::Warning,
+                    String::from("snippet is a duplicate of the one at line 4")
+                ),
+                (
+                    21,
+                    1,
+                    Severity::Warning,
+                    String::from(
+                        "snippet ends without a newline, unlike most snippets of the file"
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_cursor_markers_are_errors() {
+        let diagnostics = check(
+            "+++\nnotes = \"Type ⟨cursor⟩ to place the cursor\"\n+++\nlet x = ⟨cursor⟩;\nlet y = ⟨cursor⟩;\n",
+        );
+
+        assert_eq!(
+            diagnostics,
+            [(
+                5,
+                9,
+                Severity::Error,
+                String::from("snippet has more than one cursor marker")
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_errors_are_reported() {
+        let diagnostics = check("First\n\n---\n\n+++\nid = 1\n+++\nSecond\n");
+
+        assert!(
+            matches!(diagnostics.as_slice(), [(6, 1, Severity::Error, _)]),
+            "Unexpected diagnostics: {diagnostics:?}"
+        );
+    }
+
+    #[test]
+    fn canonical_form_fixes_separators_and_snippets() {
+        let fixed = canonical_form(
+            "===\ntitle = \"Traits\"\n===\nFirst\n\n---  \n\nSecond\n\n---\n\n\n\n---\n\nThird\n---\n\nLast\n",
+            Path::new("/talks/main.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert_eq!(
+            fixed.as_deref(),
+            Some(
+                "===\ntitle = \"Traits\"\n===\nFirst\n\n---\n\nSecond\n\n---\n\nThird\n---\n\nLast\n"
+            )
+        );
+    }
+
+    #[test]
+    fn canonical_form_keeps_lines_like_separator_in_snippets() {
+        let fixed = canonical_form(
+            "---\ntitle: Demo\n---\nBody\n\n---\n\nA\n---  \nB\n\n---\n\n\n\n---\n\nLast",
+            Path::new("/talks/main.lazycoder"),
+            &DeckSettings::default(),
+        );
+
+        assert_eq!(
+            fixed.as_deref(),
+            Some("---\ntitle: Demo\n---\nBody\n\n---\n\nA\n---  \nB\n\n---\n\nLast")
+        );
+    }
+
+    #[test]
+    fn canonical_deck_is_not_rewritten() {
+        assert_eq!(
+            canonical_form(
+                "First\n\n---\n\nSecond\n",
+                Path::new("/talks/main.lazycoder"),
+                &DeckSettings::default()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn included_files_are_checked_and_fixed() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("main.lazycoder");
+        let included = dir.path().join("part2.lazycoder");
+        fs::write(
+            &path,
+            "First\n\n---\n\n!include part2.lazycoder\n!include missing.lazycoder\n",
+        )
+        .expect("Unable to write deck");
+        fs::write(&included, "\u{feff}Second\r\n\r\n--- \r\n\r\nThird\r\n")
+            .expect("Unable to write included deck");
+
+        let report = check_deck(
+            &path,
+            DeckFormat::Lazycoder,
+            false,
+            &DeckSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(report.errors(), 2);
+        assert_eq!(report.diagnostics[0].file, included);
+        assert_eq!(report.diagnostics[1].line, 6);
+
+        let report =
+            check_deck(&path, DeckFormat::Lazycoder, true, &DeckSettings::default()).unwrap();
+        assert_eq!(report.fixed, std::slice::from_ref(&included));
+        assert_eq!(report.errors(), 1);
+        assert_eq!(
+            fs::read_to_string(&included).unwrap(),
+            "\u{feff}Second\r\n\r\n---\r\n\r\nThird\r\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fixed_file_keeps_its_permissions_and_links() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let target = dir.path().join("talk.lazycoder");
+        let link = dir.path().join("link.lazycoder");
+        fs::write(&target, "First\n\n--- \n\nSecond\n").expect("Unable to write deck");
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640))
+            .expect("Unable to set permissions");
+        symlink(&target, &link).expect("Unable to link deck");
+
+        let report =
+            check_deck(&link, DeckFormat::Lazycoder, true, &DeckSettings::default()).unwrap();
+
+        assert_eq!(report.fixed, std::slice::from_ref(&link));
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "First\n\n---\n\nSecond\n"
+        );
+        assert_eq!(
+            fs::metadata(&target).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+    }
+
+    #[test]
+    fn diagnostic_is_displayed_with_its_location() {
+        let diagnostic = Diagnostic {
+            file: PathBuf::from("/talks/main.lazycoder"),
+            line: 3,
+            column: 4,
+            severity: Severity::Error,
+            message: String::from("separator has trailing whitespace"),
+        };
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "/talks/main.lazycoder:3:4: error: separator has trailing whitespace"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! Status of a session
+//!
+//! Where the session is in its deck, and what comes next, without providing any snippet. It is printed as text or as
+//! JSON, so it can be shown while presenting without typing anything through espanso. The whole deck can also be
+//! listed, to plan a rehearsal.
+use std::{
+    fmt::{self, Write as _},
+    path::PathBuf,
+};
+
+use serde_derive::Serialize;
+
+use crate::{deck::Snippet, state_file::stored_path};
+
+/// Number of upcoming snippets previewed.
+pub const PREVIEW_COUNT: usize = 2;
+/// Maximum number of characters of a preview.
+const PREVIEW_WIDTH: usize = 60;
+
+/// Position of a session in its deck and the snippets that follow it.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct DeckStatus {
+    /// Path of the deck, as an array of its bytes if it isn't valid UTF-8.
+    #[serde(serialize_with = "stored_path::serialize")]
+    pub deck: PathBuf,
+    /// Number of snippets provided, i.e. the position of the next one.
+    pub position: usize,
+    /// Number of snippets of the deck.
+    pub total: usize,
+    /// Title of the next snippet, if there is one and it has a title.
+    pub next_title: Option<String>,
+    /// Previews of the next snippets, starting with the next one.
+    pub upcoming: Vec<SnippetPreview>,
+}
+
+/// Short version of a snippet, to recognize it.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct SnippetPreview {
+    pub position: usize,
+    pub title: Option<String>,
+    /// First line of the text that isn't blank, truncated.
+    pub preview: String,
+}
+
+impl SnippetPreview {
+    /// Preview of the snippet at the position, with its text as it would be provided.
+    pub fn of(position: usize, snippet: &Snippet, text: &str) -> SnippetPreview {
+        SnippetPreview {
+            position,
+            title: snippet.metadata.title.clone(),
+            preview: preview(text),
+        }
+    }
+}
+
+/// Summary of a snippet of the deck, as listed.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct SnippetSummary {
+    pub position: usize,
+    pub id: Option<String>,
+    pub title: Option<String>,
+    /// Number of lines of the text.
+    pub lines: usize,
+    /// First line of the text that isn't blank, truncated.
+    pub preview: String,
+    /// Whether it is the next snippet of the session.
+    pub current: bool,
+}
+
+impl SnippetSummary {
+    /// Summary of the snippet at the position of the deck, with its text as it would be provided.
+    pub fn of(position: usize, snippet: &Snippet, text: &str, current: bool) -> SnippetSummary {
+        SnippetSummary {
+            position,
+            id: snippet.metadata.id.clone(),
+            title: snippet.metadata.title.clone(),
+            lines: text.lines().count(),
+            preview: preview(text),
+            current,
+        }
+    }
+}
+
+/// Lines listing the snippets, with their position, id or title, number of lines, and preview, marking the next
+/// one with `>`.
+pub fn listing(snippets: &[SnippetSummary]) -> String {
+    let label = |snippet: &SnippetSummary| {
+        snippet
+            .id
+            .clone()
+            .or_else(|| snippet.title.clone())
+            .unwrap_or_else(|| String::from("-"))
+    };
+    let position_width = snippets.len().saturating_sub(1).to_string().len();
+    let label_width = snippets
+        .iter()
+        .map(|snippet| label(snippet).chars().count())
+        .max()
+        .unwrap_or_default();
+    let mut text = String::new();
+    for snippet in snippets {
+        writeln!(
+            text,
+            "{} {:>position_width$}  {:<label_width$}  {:>3} {}  {}",
+            if snippet.current { '>' } else { ' ' },
+            snippet.position,
+            label(snippet),
+            snippet.lines,
+            if snippet.lines == 1 { "line " } else { "lines" },
+            snippet.preview
+        )
+        .expect("Failing to write to a string");
+    }
+    text
+}
+
+/// First line of the text that isn't blank, without its indentation, and truncated with an ellipsis if it is longer
+/// than the preview width.
+pub fn preview(text: &str) -> String {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    if line.chars().count() > PREVIEW_WIDTH {
+        let mut truncated: String = line.chars().take(PREVIEW_WIDTH - 1).collect();
+        truncated.push('…');
+        truncated
+    } else {
+        line.to_owned()
+    }
+}
+
+impl fmt::Display for DeckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Deck: {}", self.deck.display())?;
+        writeln!(f, "Position: {}/{}", self.position, self.total)?;
+        match (&self.next_title, self.upcoming.is_empty()) {
+            (Some(title), _) => writeln!(f, "Next: {title}")?,
+            (None, false) => writeln!(f, "Next: untitled snippet")?,
+            (None, true) => writeln!(f, "Next: end of deck")?,
+        }
+        for snippet in &self.upcoming {
+            write!(f, "  {}: ", snippet.position)?;
+            if let Some(title) = &snippet.title {
+                write!(f, "[{title}] ")?;
+            }
+            writeln!(f, "{}", snippet.preview)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_is_first_line_that_is_not_blank() {
+        assert_eq!(preview("\n   \n    fn main() {\n}\n"), "fn main() {");
+        assert_eq!(preview(""), "");
+    }
+
+    #[test]
+    fn long_preview_is_truncated() {
+        let text = "é".repeat(PREVIEW_WIDTH + 1);
+
+        let result = preview(&text);
+
+        assert_eq!(result.chars().count(), PREVIEW_WIDTH);
+        assert!(result.ends_with("é…"), "Unexpected preview: {result}");
+    }
+
+    #[test]
+    fn listing_aligns_snippets_and_marks_the_next_one() {
+        let snippets = [
+            SnippetSummary {
+                position: 0,
+                id: Some(String::from("add-trait")),
+                title: Some(String::from("Add the trait")),
+                lines: 1,
+                preview: String::from("trait Shape {}"),
+                current: false,
+            },
+            SnippetSummary {
+                position: 1,
+                id: None,
+                title: Some(String::from("Impl")),
+                lines: 3,
+                preview: String::from("impl Shape for Circle {"),
+                current: true,
+            },
+            SnippetSummary {
+                position: 2,
+                id: None,
+                title: None,
+                lines: 12,
+                preview: String::from("fn main() {"),
+                current: false,
+            },
+        ];
+
+        assert_eq!(
+            listing(&snippets),
+            "  0  add-trait    1 line   trait Shape {}\n> 1  Impl         3 lines  impl Shape for Circle {\n  2  -           12 lines  fn main() {\n"
+        );
+    }
+
+    #[test]
+    fn status_is_displayed() {
+        let status = DeckStatus {
+            deck: PathBuf::from("/talks/traits.lazycoder"),
+            position: 3,
+            total: 10,
+            next_title: Some(String::from("Trait objects")),
+            upcoming: vec![
+                SnippetPreview {
+                    position: 3,
+                    title: Some(String::from("Trait objects")),
+                    preview: String::from("let shapes: Vec<Box<dyn Shape>> = vec![];"),
+                },
+                SnippetPreview {
+                    position: 4,
+                    title: None,
+                    preview: String::from("for shape in &shapes {"),
+                },
+            ],
+        };
+
+        assert_eq!(
+            status.to_string(),
+            "Deck: /talks/traits.lazycoder\nPosition: 3/10\nNext: Trait objects\n  3: [Trait objects] let shapes: Vec<Box<dyn Shape>> = vec![];\n  4: for shape in &shapes {\n"
+        );
+    }
+
+    #[test]
+    fn status_at_end_of_deck_is_displayed() {
+        let status = DeckStatus {
+            deck: PathBuf::from("/talks/traits.lazycoder"),
+            position: 10,
+            total: 10,
+            next_title: None,
+            upcoming: Vec::new(),
+        };
+
+        assert_eq!(
+            status.to_string(),
+            "Deck: /talks/traits.lazycoder\nPosition: 10/10\nNext: end of deck\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn status_with_non_utf8_deck_is_encoded() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let status = DeckStatus {
+            deck: PathBuf::from(OsStr::from_bytes(b"/talks/caf\xe9")),
+            position: 10,
+            total: 10,
+            next_title: None,
+            upcoming: Vec::new(),
+        };
+
+        let json = serde_json::to_value(&status).expect("Unable to encode status");
+
+        assert_eq!(json["deck"], serde_json::json!(b"/talks/caf\xe9"));
+    }
+}
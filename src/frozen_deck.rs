@@ -0,0 +1,168 @@
+//! Frozen decks
+//!
+//! A copy of the parsed snippets of a deck, taken when the talk starts, so editing the deck during the talk doesn't
+//! change the snippets that are provided. The copy is only updated when it is explicitly refreshed.
+use std::{fs, path::Path};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    deck::{DeckSettings, Snippet},
+    lazy_coder_error::LazyCoderError,
+    snippet_handler::SnippetProvider,
+    state_file::write_atomic,
+};
+
+#[derive(Deserialize, Serialize, PartialEq, Default, Debug)]
+pub struct FrozenDeck {
+    settings: DeckSettings,
+    snippets: Vec<Snippet>,
+}
+
+impl FrozenDeck {
+    /// Takes the settings and all the snippets provided for a deck.
+    pub fn import(snippet_prov: &dyn SnippetProvider) -> Result<FrozenDeck, LazyCoderError> {
+        Ok(FrozenDeck {
+            settings: snippet_prov.settings()?,
+            snippets: snippet_prov.snippets()?,
+        })
+    }
+
+    /// Reads a frozen deck from the file.
+    pub fn load(path: &Path) -> Result<FrozenDeck, LazyCoderError> {
+        let text = fs::read_to_string(path).map_err(LazyCoderError::SnippetFileError)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    /// Writes the frozen deck to the file, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), LazyCoderError> {
+        let text = toml::to_string(self).expect("Failing to encode TOML");
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(LazyCoderError::SnippetFileError)?;
+        }
+        write_atomic(path, text).map_err(LazyCoderError::SnippetFileError)
+    }
+
+    /// Number of snippets of the deck.
+    pub fn len(&self) -> usize {
+        self.snippets.len()
+    }
+
+    /// Positions of the snippets that are different in the other deck, including those that only exist in one of them.
+    pub fn changes(&self, other: &FrozenDeck) -> Vec<usize> {
+        (0..self.len().max(other.len()))
+            .filter(|position| self.snippets.get(*position) != other.snippets.get(*position))
+            .collect()
+    }
+}
+
+impl SnippetProvider for FrozenDeck {
+    fn get_snippet(&self, position: usize) -> Result<Snippet, LazyCoderError> {
+        self.snippets
+            .get(position)
+            .cloned()
+            .ok_or(LazyCoderError::RunOutOfSnippets)
+    }
+
+    fn snippet_count(&self) -> Result<usize, LazyCoderError> {
+        Ok(self.len())
+    }
+
+    fn snippets(&self) -> Result<Vec<Snippet>, LazyCoderError> {
+        Ok(self.snippets.clone())
+    }
+
+    fn settings(&self) -> Result<DeckSettings, LazyCoderError> {
+        Ok(self.settings.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::{deck::SnippetMetadata, snippet_handler::MockSnippetProvider};
+
+    use super::*;
+
+    fn deck_with_texts(texts: &[&str]) -> FrozenDeck {
+        FrozenDeck {
+            settings: DeckSettings::default(),
+            snippets: texts
+                .iter()
+                .map(|text| Snippet {
+                    text: String::from(*text),
+                    ..Snippet::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn all_snippets_are_imported() {
+        let mut snippet_prov = MockSnippetProvider::new();
+        snippet_prov.expect_snippets().returning(|| {
+            Ok((0..2)
+                .map(|position| Snippet {
+                    text: format!("Snippet {position}"),
+                    ..Snippet::default()
+                })
+                .collect())
+        });
+        snippet_prov.expect_settings().returning(|| {
+            Ok(DeckSettings {
+                title: Some(String::from("Traits")),
+                ..DeckSettings::default()
+            })
+        });
+
+        let result = FrozenDeck::import(&snippet_prov);
+
+        assert!(
+            matches!(result, Ok(ref deck) if *deck == FrozenDeck {
+                settings: DeckSettings {
+                    title: Some(String::from("Traits")),
+                    ..DeckSettings::default()
+                },
+                ..deck_with_texts(&["Snippet 0", "Snippet 1"])
+            }),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn saved_deck_is_loaded() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("frozen").join("deck.toml");
+        let mut deck = deck_with_texts(&["fn main() {\n    \"\"\"\n}\n", "Last"]);
+        deck.snippets[0].cursor = Some(12);
+        deck.snippets[0].metadata = SnippetMetadata {
+            id: Some(String::from("main")),
+            tags: vec![String::from("intro")],
+            ..SnippetMetadata::default()
+        };
+
+        deck.save(&path).expect("Unable to save deck");
+
+        let result = FrozenDeck::load(&path);
+        assert!(
+            matches!(result, Ok(ref loaded) if *loaded == deck),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn changed_positions_are_reported() {
+        let deck = deck_with_texts(&["First", "Second", "Third"]);
+
+        assert_eq!(
+            deck.changes(&deck_with_texts(&["First", "Changed", "Third", "Fourth"])),
+            [1, 3]
+        );
+        assert_eq!(deck.changes(&deck_with_texts(&["First"])), [1, 2]);
+        assert!(
+            deck.changes(&deck_with_texts(&["First", "Second", "Third"]))
+                .is_empty()
+        );
+    }
+}
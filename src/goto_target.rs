@@ -0,0 +1,339 @@
+//! Targets of goto
+//!
+//! A snippet to go to, given by its position (`12`), relative to the current one (`+3`, `-2`), relative to the last
+//! one (`$`, `$-1`), or by its id or title. Targets are resolved against the snippets of the deck, so those that don't
+//! exist are rejected before the position changes.
+use std::{fmt, str::FromStr};
+
+use crate::{deck::Snippet, lazy_coder_error::LazyCoderError};
+
+/// Maximum number of snippets suggested when a target doesn't match any.
+const MAX_SUGGESTIONS: usize = 3;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum GotoTarget {
+    /// Position in the deck, starting at 0.
+    Position(usize),
+    /// Number of snippets after the current position.
+    Forward(usize),
+    /// Number of snippets before the current position.
+    Backward(usize),
+    /// Number of snippets before the last one.
+    FromEnd(usize),
+    /// Id or title of a snippet, or the beginning of one of them.
+    Label(String),
+}
+
+impl FromStr for GotoTarget {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let number = |digits: &str| {
+            digits
+                .parse::<usize>()
+                .ok()
+                .filter(|_| digits.bytes().all(|byte| byte.is_ascii_digit()))
+        };
+        if text.is_empty() {
+            return Err(String::from("empty target"));
+        }
+        let target = if text == "$" {
+            GotoTarget::FromEnd(0)
+        } else if let Some(count) = text.strip_prefix("$-").and_then(number) {
+            GotoTarget::FromEnd(count)
+        } else if let Some(count) = text.strip_prefix('+').and_then(number) {
+            GotoTarget::Forward(count)
+        } else if let Some(count) = text.strip_prefix('-').and_then(number) {
+            GotoTarget::Backward(count)
+        } else if let Some(position) = number(text) {
+            GotoTarget::Position(position)
+        } else {
+            GotoTarget::Label(text.to_owned())
+        };
+        Ok(target)
+    }
+}
+
+impl fmt::Display for GotoTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GotoTarget::Position(position) => write!(f, "{position}"),
+            GotoTarget::Forward(count) => write!(f, "+{count}"),
+            GotoTarget::Backward(count) => write!(f, "-{count}"),
+            GotoTarget::FromEnd(0) => write!(f, "$"),
+            GotoTarget::FromEnd(count) => write!(f, "$-{count}"),
+            GotoTarget::Label(label) => write!(f, "{label}"),
+        }
+    }
+}
+
+impl GotoTarget {
+    /// Position of the snippet of the target in the deck.
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - current position, that relative targets start from.
+    /// * `snippets` - snippets of the deck.
+    pub fn resolve(&self, position: usize, snippets: &[Snippet]) -> Result<usize, LazyCoderError> {
+        let (found, before_start) = match self {
+            GotoTarget::Position(target) => (Some(*target), false),
+            GotoTarget::Forward(count) => (position.checked_add(*count), false),
+            GotoTarget::Backward(count) => (position.checked_sub(*count), true),
+            GotoTarget::FromEnd(count) => {
+                (snippets.len().checked_sub(count.saturating_add(1)), true)
+            }
+            GotoTarget::Label(label) => return resolve_label(label, snippets),
+        };
+        found
+            .filter(|target| *target < snippets.len())
+            .ok_or_else(|| {
+                let nearest = if found.is_none() && before_start {
+                    0
+                } else {
+                    snippets.len().saturating_sub(1)
+                };
+                LazyCoderError::TargetNotFound {
+                    target: self.to_string(),
+                    nearest: snippets
+                        .get(nearest)
+                        .map(|snippet| describe(nearest, snippet))
+                        .into_iter()
+                        .collect(),
+                }
+            })
+    }
+}
+
+/// Position of the snippet with the id, or else with the title, or else whose id or title starts with the label,
+/// ignoring case for the titles and the beginnings.
+fn resolve_label(label: &str, snippets: &[Snippet]) -> Result<usize, LazyCoderError> {
+    let lowercase_label = label.to_lowercase();
+    let labels = |snippet: &Snippet| {
+        [&snippet.metadata.id, &snippet.metadata.title]
+            .into_iter()
+            .flatten()
+            .map(|text| text.to_lowercase())
+            .collect::<Vec<_>>()
+    };
+    let matching = |matches: &dyn Fn(&Snippet) -> bool| {
+        snippets
+            .iter()
+            .enumerate()
+            .filter(|(_, snippet)| matches(snippet))
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>()
+    };
+    let mut found = matching(&|snippet| snippet.metadata.id.as_deref() == Some(label));
+    if found.is_empty() {
+        found = matching(&|snippet| {
+            snippet
+                .metadata
+                .title
+                .as_ref()
+                .is_some_and(|title| title.to_lowercase() == lowercase_label)
+        });
+    }
+    if found.is_empty() {
+        found = matching(&|snippet| {
+            labels(snippet)
+                .iter()
+                .any(|text| text.starts_with(&lowercase_label))
+        });
+    }
+    match found[..] {
+        [position] => Ok(position),
+        [] => {
+            let mut distances: Vec<_> = snippets
+                .iter()
+                .enumerate()
+                .filter_map(|(position, snippet)| {
+                    let distance = labels(snippet)
+                        .iter()
+                        .map(|text| edit_distance(text, &lowercase_label))
+                        .min()?;
+                    Some((distance, position))
+                })
+                .collect();
+            distances.sort_unstable();
+            Err(LazyCoderError::TargetNotFound {
+                target: label.to_owned(),
+                nearest: distances
+                    .into_iter()
+                    .take(MAX_SUGGESTIONS)
+                    .map(|(_, position)| describe(position, &snippets[position]))
+                    .collect(),
+            })
+        }
+        _ => Err(LazyCoderError::AmbiguousTarget {
+            target: label.to_owned(),
+            matches: found
+                .into_iter()
+                .map(|position| describe(position, &snippets[position]))
+                .collect(),
+        }),
+    }
+}
+
+/// Position of a snippet with its id or title, as shown in the errors.
+fn describe(position: usize, snippet: &Snippet) -> String {
+    match snippet
+        .metadata
+        .id
+        .as_ref()
+        .or(snippet.metadata.title.as_ref())
+    {
+        Some(label) => format!("{position} ({label})"),
+        None => position.to_string(),
+    }
+}
+
+/// Number of characters that have to be inserted, removed, or replaced to turn one text into the other.
+fn edit_distance(text: &str, other: &str) -> usize {
+    let other: Vec<char> = other.chars().collect();
+    let mut previous: Vec<usize> = (0..=other.len()).collect();
+    for (i, character) in text.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, other_character) in other.iter().enumerate() {
+            let replacement = previous[j] + usize::from(character != *other_character);
+            current.push(replacement.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[other.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deck::SnippetMetadata;
+
+    use super::*;
+
+    fn snippets() -> Vec<Snippet> {
+        [
+            (Some("intro"), Some("Introduction")),
+            (Some("add-trait"), Some("Add the trait")),
+            (None, Some("Implement the trait")),
+            (None, None),
+            (Some("impl-circle"), Some("Implement the trait")),
+        ]
+        .into_iter()
+        .map(|(id, title)| Snippet {
+            metadata: SnippetMetadata {
+                id: id.map(String::from),
+                title: title.map(String::from),
+                ..SnippetMetadata::default()
+            },
+            ..Snippet::default()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn targets_are_parsed() {
+        assert_eq!("12".parse(), Ok(GotoTarget::Position(12)));
+        assert_eq!("+3".parse(), Ok(GotoTarget::Forward(3)));
+        assert_eq!("-2".parse(), Ok(GotoTarget::Backward(2)));
+        assert_eq!("$".parse(), Ok(GotoTarget::FromEnd(0)));
+        assert_eq!("$-1".parse(), Ok(GotoTarget::FromEnd(1)));
+        assert_eq!(
+            "add-trait".parse(),
+            Ok(GotoTarget::Label(String::from("add-trait")))
+        );
+        assert_eq!("+x".parse(), Ok(GotoTarget::Label(String::from("+x"))));
+        assert!("".parse::<GotoTarget>().is_err());
+    }
+
+    #[test]
+    fn targets_are_displayed_as_parsed() {
+        for text in ["12", "+3", "-2", "$", "$-1", "add-trait"] {
+            assert_eq!(text.parse::<GotoTarget>().unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn numeric_targets_are_resolved() {
+        let snippets = snippets();
+
+        assert_eq!(GotoTarget::Position(3).resolve(1, &snippets).ok(), Some(3));
+        assert_eq!(GotoTarget::Forward(3).resolve(1, &snippets).ok(), Some(4));
+        assert_eq!(GotoTarget::Backward(1).resolve(1, &snippets).ok(), Some(0));
+        assert_eq!(GotoTarget::FromEnd(0).resolve(1, &snippets).ok(), Some(4));
+        assert_eq!(GotoTarget::FromEnd(1).resolve(1, &snippets).ok(), Some(3));
+    }
+
+    #[test]
+    fn targets_outside_deck_suggest_nearest_end() {
+        let snippets = snippets();
+
+        let result = GotoTarget::Forward(4).resolve(1, &snippets);
+        assert!(
+            matches!(result, Err(LazyCoderError::TargetNotFound { ref target, ref nearest })
+                if target == "+4" && *nearest == ["4 (impl-circle)"]),
+            "Unexpected result: {result:?}"
+        );
+        let result = GotoTarget::Backward(2).resolve(1, &snippets);
+        assert!(
+            matches!(result, Err(LazyCoderError::TargetNotFound { ref nearest, .. }) if *nearest == ["0 (intro)"]),
+            "Unexpected result: {result:?}"
+        );
+        let result = GotoTarget::FromEnd(0).resolve(0, &[]);
+        assert!(
+            matches!(result, Err(LazyCoderError::TargetNotFound { ref nearest, .. }) if nearest.is_empty()),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn labels_are_resolved_by_id_title_or_beginning() {
+        let snippets = snippets();
+
+        assert_eq!(
+            GotoTarget::Label(String::from("add-trait"))
+                .resolve(0, &snippets)
+                .ok(),
+            Some(1)
+        );
+        assert_eq!(
+            GotoTarget::Label(String::from("introduction"))
+                .resolve(0, &snippets)
+                .ok(),
+            Some(0)
+        );
+        assert_eq!(
+            GotoTarget::Label(String::from("Add the"))
+                .resolve(0, &snippets)
+                .ok(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn ambiguous_label_lists_matches() {
+        let result = GotoTarget::Label(String::from("implement the trait")).resolve(0, &snippets());
+
+        assert!(
+            matches!(result, Err(LazyCoderError::AmbiguousTarget { ref target, ref matches })
+                if target == "implement the trait" && *matches == ["2 (Implement the trait)", "4 (impl-circle)"]),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn unknown_label_suggests_closest_snippets() {
+        let result = GotoTarget::Label(String::from("add-triat")).resolve(0, &snippets());
+
+        assert!(
+            matches!(result, Err(LazyCoderError::TargetNotFound { ref nearest, .. })
+                if nearest.first().map(String::as_str) == Some("1 (add-trait)") && nearest.len() == MAX_SUGGESTIONS),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn edit_distance_counts_changes() {
+        assert_eq!(edit_distance("trait", "trait"), 0);
+        assert_eq!(edit_distance("trait", "triat"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("intro", "outro"), 2);
+    }
+}
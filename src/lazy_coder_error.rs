@@ -1,4 +1,4 @@
-use std::{error, fmt, io};
+use std::{error, fmt, io, path::PathBuf};
 
 #[derive(Debug)]
 pub enum LazyCoderError {
@@ -9,6 +9,72 @@ pub enum LazyCoderError {
     ConfigFileError(io::Error),
     ConfigEncoding(toml::de::Error),
     OperationOutOfRange,
+    DeckParseError {
+        file: PathBuf,
+        line: usize,
+        message: String,
+    },
+    UnknownVariable(String),
+    SnippetNotFound(String),
+    DeckNotFrozen,
+    InvalidSessionName(String),
+    SessionNotFound(String),
+    SessionExists(String),
+    NothingToUndo,
+    NothingToRedo,
+    StateLocked(PathBuf),
+    UnsupportedSchemaVersion {
+        found: u32,
+        supported: u32,
+    },
+    UnknownSetting(String),
+    InvalidSettingValue {
+        key: String,
+        value: String,
+    },
+    DeckMoved {
+        path: PathBuf,
+        candidates: Vec<PathBuf>,
+    },
+    TargetNotFound {
+        target: String,
+        nearest: Vec<String>,
+    },
+    AmbiguousTarget {
+        target: String,
+        matches: Vec<String>,
+    },
+}
+
+impl LazyCoderError {
+    /// Name of the variant in snake case, that identifies the error in the JSON output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LazyCoderError::SnippetFileNotFound => "snippet_file_not_found",
+            LazyCoderError::SnippetFileError(_) => "snippet_file_error",
+            LazyCoderError::RunOutOfSnippets => "run_out_of_snippets",
+            LazyCoderError::ConfigDirError => "config_dir_error",
+            LazyCoderError::ConfigFileError(_) => "config_file_error",
+            LazyCoderError::ConfigEncoding(_) => "config_encoding",
+            LazyCoderError::OperationOutOfRange => "operation_out_of_range",
+            LazyCoderError::DeckParseError { .. } => "deck_parse_error",
+            LazyCoderError::UnknownVariable(_) => "unknown_variable",
+            LazyCoderError::SnippetNotFound(_) => "snippet_not_found",
+            LazyCoderError::DeckNotFrozen => "deck_not_frozen",
+            LazyCoderError::InvalidSessionName(_) => "invalid_session_name",
+            LazyCoderError::SessionNotFound(_) => "session_not_found",
+            LazyCoderError::SessionExists(_) => "session_exists",
+            LazyCoderError::NothingToUndo => "nothing_to_undo",
+            LazyCoderError::NothingToRedo => "nothing_to_redo",
+            LazyCoderError::StateLocked(_) => "state_locked",
+            LazyCoderError::UnsupportedSchemaVersion { .. } => "unsupported_schema_version",
+            LazyCoderError::UnknownSetting(_) => "unknown_setting",
+            LazyCoderError::InvalidSettingValue { .. } => "invalid_setting_value",
+            LazyCoderError::DeckMoved { .. } => "deck_moved",
+            LazyCoderError::TargetNotFound { .. } => "target_not_found",
+            LazyCoderError::AmbiguousTarget { .. } => "ambiguous_target",
+        }
+    }
 }
 
 impl error::Error for LazyCoderError {}
@@ -37,10 +103,96 @@ impl fmt::Display for LazyCoderError {
             LazyCoderError::OperationOutOfRange => {
                 write!(f, "operation out of range")
             }
+            LazyCoderError::DeckParseError {
+                file,
+                line,
+                message,
+            } => {
+                write!(
+                    f,
+                    "deck parse error at {}:{line}: {message}",
+                    file.display()
+                )
+            }
+            LazyCoderError::UnknownVariable(name) => {
+                write!(f, "unknown template variable: {name} (escape with \\{{{{)")
+            }
+            LazyCoderError::SnippetNotFound(anchor) => {
+                write!(f, "snippet {anchor} is no longer in the deck")
+            }
+            LazyCoderError::DeckNotFrozen => {
+                write!(f, "deck is not frozen")
+            }
+            LazyCoderError::InvalidSessionName(name) => {
+                write!(f, "invalid session name: {name}")
+            }
+            LazyCoderError::SessionNotFound(name) => {
+                write!(f, "session not found: {name}")
+            }
+            LazyCoderError::SessionExists(name) => {
+                write!(f, "session already exists: {name}")
+            }
+            LazyCoderError::NothingToUndo => {
+                write!(f, "nothing to undo")
+            }
+            LazyCoderError::NothingToRedo => {
+                write!(f, "nothing to redo")
+            }
+            LazyCoderError::StateLocked(path) => {
+                write!(f, "timed out waiting for the lock on {}", path.display())
+            }
+            LazyCoderError::UnsupportedSchemaVersion { found, supported } => {
+                write!(
+                    f,
+                    "configuration schema version {found} is newer than the supported {supported}, upgrade lazycoder"
+                )
+            }
+            LazyCoderError::UnknownSetting(key) => {
+                write!(f, "unknown setting: {key}")
+            }
+            LazyCoderError::InvalidSettingValue { key, value } => {
+                write!(f, "invalid value for setting {key}: {value:?}")
+            }
+            LazyCoderError::DeckMoved { path, candidates } => {
+                write!(f, "deck not found at {}; ", path.display())?;
+                match &candidates[..] {
+                    [] => write!(f, "run `lazycoder relink <path>` with its new path"),
+                    [found] => write!(f, "run `lazycoder relink` to use {}", found.display()),
+                    _ => write!(
+                        f,
+                        "run `lazycoder relink <path>` with one of {}",
+                        join_paths(candidates)
+                    ),
+                }
+            }
+            LazyCoderError::TargetNotFound { target, nearest } => {
+                write!(f, "no snippet matches {target}")?;
+                if nearest.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, "; nearest: {}", nearest.join(", "))
+                }
+            }
+            LazyCoderError::AmbiguousTarget { target, matches } => {
+                write!(
+                    f,
+                    "{target} matches several snippets: {}",
+                    matches.join(", ")
+                )
+            }
         }
     }
 }
 
+/// Paths separated by commas.
+fn join_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl From<std::io::Error> for LazyCoderError {
     fn from(err: std::io::Error) -> Self {
         LazyCoderError::ConfigFileError(err)
@@ -117,6 +269,198 @@ mod tests {
         );
     }
 
+    #[test]
+    fn display_deck_parse_error() {
+        assert_eq!(
+            LazyCoderError::DeckParseError {
+                file: PathBuf::from("/talks/traits.lazycoder"),
+                line: 12,
+                message: String::from("snippet header is not closed")
+            }
+            .to_string(),
+            "deck parse error at /talks/traits.lazycoder:12: snippet header is not closed"
+        );
+    }
+
+    #[test]
+    fn display_unknown_variable_error() {
+        assert_eq!(
+            LazyCoderError::UnknownVariable(String::from("project_name")).to_string(),
+            "unknown template variable: project_name (escape with \\{{)"
+        );
+    }
+
+    #[test]
+    fn display_snippet_not_found_error() {
+        assert_eq!(
+            LazyCoderError::SnippetNotFound(String::from("with id \"intro\"")).to_string(),
+            "snippet with id \"intro\" is no longer in the deck"
+        );
+    }
+
+    #[test]
+    fn display_deck_not_frozen_error() {
+        assert_eq!(
+            LazyCoderError::DeckNotFrozen.to_string(),
+            "deck is not frozen"
+        );
+    }
+
+    #[test]
+    fn display_invalid_session_name_error() {
+        assert_eq!(
+            LazyCoderError::InvalidSessionName(String::from("my talk")).to_string(),
+            "invalid session name: my talk"
+        );
+    }
+
+    #[test]
+    fn display_session_not_found_error() {
+        assert_eq!(
+            LazyCoderError::SessionNotFound(String::from("meetup")).to_string(),
+            "session not found: meetup"
+        );
+    }
+
+    #[test]
+    fn display_session_exists_error() {
+        assert_eq!(
+            LazyCoderError::SessionExists(String::from("meetup")).to_string(),
+            "session already exists: meetup"
+        );
+    }
+
+    #[test]
+    fn display_nothing_to_undo_error() {
+        assert_eq!(LazyCoderError::NothingToUndo.to_string(), "nothing to undo");
+    }
+
+    #[test]
+    fn display_nothing_to_redo_error() {
+        assert_eq!(LazyCoderError::NothingToRedo.to_string(), "nothing to redo");
+    }
+
+    #[test]
+    fn display_state_locked_error() {
+        assert_eq!(
+            LazyCoderError::StateLocked(PathBuf::from("/config/lazycoder.lock")).to_string(),
+            "timed out waiting for the lock on /config/lazycoder.lock"
+        );
+    }
+
+    #[test]
+    fn display_unsupported_schema_version_error() {
+        assert_eq!(
+            LazyCoderError::UnsupportedSchemaVersion {
+                found: 3,
+                supported: 1
+            }
+            .to_string(),
+            "configuration schema version 3 is newer than the supported 1, upgrade lazycoder"
+        );
+    }
+
+    #[test]
+    fn display_unknown_setting_error() {
+        assert_eq!(
+            LazyCoderError::UnknownSetting(String::from("sparator")).to_string(),
+            "unknown setting: sparator"
+        );
+    }
+
+    #[test]
+    fn display_invalid_setting_value_error() {
+        assert_eq!(
+            LazyCoderError::InvalidSettingValue {
+                key: String::from("end_of_deck"),
+                value: String::from("loop")
+            }
+            .to_string(),
+            "invalid value for setting end_of_deck: \"loop\""
+        );
+    }
+
+    #[test]
+    fn display_deck_moved_error() {
+        let path = PathBuf::from("/talks/traits.lazycoder");
+        assert_eq!(
+            LazyCoderError::DeckMoved {
+                path: path.clone(),
+                candidates: Vec::new()
+            }
+            .to_string(),
+            "deck not found at /talks/traits.lazycoder; run `lazycoder relink <path>` with its new path"
+        );
+        assert_eq!(
+            LazyCoderError::DeckMoved {
+                path: path.clone(),
+                candidates: vec![PathBuf::from("/old/traits.lazycoder")]
+            }
+            .to_string(),
+            "deck not found at /talks/traits.lazycoder; run `lazycoder relink` to use /old/traits.lazycoder"
+        );
+        assert_eq!(
+            LazyCoderError::DeckMoved {
+                path,
+                candidates: vec![PathBuf::from("/a.lazycoder"), PathBuf::from("/b.lazycoder")]
+            }
+            .to_string(),
+            "deck not found at /talks/traits.lazycoder; run `lazycoder relink <path>` with one of /a.lazycoder, /b.lazycoder"
+        );
+    }
+
+    #[test]
+    fn display_target_not_found_error() {
+        assert_eq!(
+            LazyCoderError::TargetNotFound {
+                target: String::from("add-triat"),
+                nearest: vec![String::from("1 (add-trait)"), String::from("0 (intro)")]
+            }
+            .to_string(),
+            "no snippet matches add-triat; nearest: 1 (add-trait), 0 (intro)"
+        );
+        assert_eq!(
+            LazyCoderError::TargetNotFound {
+                target: String::from("$"),
+                nearest: Vec::new()
+            }
+            .to_string(),
+            "no snippet matches $"
+        );
+    }
+
+    #[test]
+    fn display_ambiguous_target_error() {
+        assert_eq!(
+            LazyCoderError::AmbiguousTarget {
+                target: String::from("impl"),
+                matches: vec![String::from("2"), String::from("4 (impl-circle)")]
+            }
+            .to_string(),
+            "impl matches several snippets: 2, 4 (impl-circle)"
+        );
+    }
+
+    #[test]
+    fn kind_is_name_of_variant() {
+        assert_eq!(
+            LazyCoderError::RunOutOfSnippets.kind(),
+            "run_out_of_snippets"
+        );
+        assert_eq!(
+            LazyCoderError::ConfigFileError(std::io::Error::other("some file error")).kind(),
+            "config_file_error"
+        );
+        assert_eq!(
+            LazyCoderError::TargetNotFound {
+                target: String::from("intro"),
+                nearest: Vec::new()
+            }
+            .kind(),
+            "target_not_found"
+        );
+    }
+
     #[test]
     fn toml_error_conversion() {
         let toml_error = toml::de::Error::custom("some file error");
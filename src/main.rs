@@ -1,10 +1,15 @@
 //! Lazycoder - A simple snippet generator for expanso
 //!
-//! `lazycoder start </filepath/demo.lazycoder>`
+//! `lazycoder start </filepath/demo.lazycoder> [--format lazycoder|markdown] [--freeze]`
 //! - works with only one demo at a time
+//! - Markdown files (`.md`) provide their fenced code blocks as snippets
+//! - `--var key=value` sets the value of a template variable used as `{{key}}` in the snippets
+//! - `--freeze` provides the snippets from a copy of the deck taken now
 //! - save file name
 //! - save initial next position: 0
 //! - config file location depends on OS. saved in ~/.lazycoder
+//! - a `.lazycoder` directory in the working directory or one of its ancestors keeps the state of that project
+//!   instead, unless `--global` is used
 //!
 //! `lazycoder next`
 //! - reads from config file
@@ -23,137 +28,531 @@
 //! - increments counter (number times)
 //! - returns nothing
 //!
+//! `lazycoder goto <target>`
+//! - moves the counter to a position (`12`), relative to the current one (`+3`, `-2`), relative to the last snippet
+//!   (`$`, `$-1`), or to the snippet with an id or title (or starting with it)
+//! - fails without moving if the target isn't in the deck or matches several snippets, listing the nearest ones
+//!
+//! `lazycoder undo` / `lazycoder redo`
+//! - undoes or redoes the last change of the position made by start, next, forward, rewind, or goto
+//!
+//! `lazycoder history`
+//! - prints the changes of the position, marking the undone ones
+//!
+//! `lazycoder status [--format text|json]`
+//! - prints the deck, the position out of the total, and the title and a preview of the next snippets
+//! - doesn't provide any snippet, unlike `peek`
+//!
+//! `lazycoder list`
+//! - prints every snippet with its position, id or title, number of lines, and first line
+//! - marks the next snippet with `>`
+//!
+//! `lazycoder refresh`
+//! - updates the copy of a frozen deck
+//! - prints the positions of the snippets that changed
+//!
+//! `lazycoder relink [/filepath/demo.lazycoder]`
+//! - commands fail if the deck is no longer in its path, suggesting where it may have moved
+//! - makes the session use the deck at its new path, keeping the position
+//! - without a path, uses the file near the previous path with the same contents
+//!
+//! `lazycoder sessions [list|switch <name>|rename <old> <new>|delete <name>]`
+//! - each session keeps its own deck and position
+//! - `--session <name>` selects the session of any command, otherwise the active one is used
+//! - `start` makes its session the active one
+//!
+//! `lazycoder check [--fix] </filepath/demo.lazycoder>`
+//! - reports the problems of the deck with their file, line and column
+//! - fails if any of them is an error
+//! - `--fix` rewrites the deck in canonical form first
+//!
+//! `lazycoder config [get <key>|set <key> <value>|unset <key>|list]`
+//! - manages the user settings, stored in `settings.toml` in the config directory
+//! - the position and the rest of the state are stored in the state directory
+//!
+//! `lazycoder paths`
+//! - prints the config directory, settings file, state directory, session file, and project root in use
+//! - `--config-dir <dir>` or `LAZYCODER_CONFIG_DIR` replaces the config directory, which also keeps the state outside
+//!   projects
+//! - `LAZYCODER_STATE` replaces the state file of the default session, keeping the rest of the state next to it
+//! - `LAZYCODER_STATE_DIR` replaces the state directory
+//!
+//! `lazycoder --output json <command>`
+//! - prints a single line with a JSON object with the snippet, position, deck, and error, if any, of any command
+//! - the object has a `schema_version`, that increases whenever a field is removed or changes its meaning
+//!
 mod cli_args;
 mod config;
+mod deck;
+mod deck_checker;
+mod deck_status;
+mod frozen_deck;
+mod goto_target;
 mod lazy_coder_error;
+mod markdown_handler;
+mod output;
+mod position_journal;
+mod settings;
 mod snippet_handler;
+mod snippet_index;
+mod state_file;
+mod template;
 
 use clap::Parser;
 use eyre::{Result, WrapErr, eyre};
 use log::{debug, error, info};
 use mockall_double::double;
-use std::path::Path;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+};
 
-use cli_args::{CliArgs, Command};
+use cli_args::{CliArgs, Command, ConfigCommand, OutputFormat, SessionsCommand};
 #[double]
 use config::Config;
+use deck::DeckFormat;
+use goto_target::GotoTarget;
+use output::{CommandError, Output};
 
 fn main() -> Result<()> {
     let cli = CliArgs::parse();
+    // The directories are chosen first, since the logger reads the settings.
+    if cli.global {
+        Config::use_global_state();
+    }
+    if let Some(dir) = cli.config_dir {
+        Config::use_config_dir(dir);
+    }
+    let mut logger = env_logger::Builder::from_default_env();
     if let Some(level) = cli.level {
-        env_logger::builder().filter(None, level).init();
-    } else {
-        env_logger::init();
+        logger.filter(None, level);
+    }
+    // Invalid settings are reported by the command that uses them.
+    if let Some(log_file) = Config::settings()
+        .ok()
+        .and_then(|settings| settings.log_file)
+    {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_file)
+            .wrap_err_with(|| format!("Failed to open log file {}", log_file.display()))?;
+        logger.target(env_logger::Target::Pipe(Box::new(file)));
     }
+    logger.init();
 
-    match cli.command {
-        Command::Start { filename } => start(&filename)?,
-        Command::Next {} => next()?,
-        Command::Peek {} => peek()?,
+    let mut out = Output::new(cli.output, cli.command.name());
+    let result = run(cli.command, cli.session, &mut out);
+    out.finish(result)
+}
+
+/// Runs the subcommand, sending its results to the output.
+fn run(command: Command, session: Option<String>, out: &mut Output) -> Result<()> {
+    match command {
+        Command::Start {
+            filename,
+            format,
+            vars,
+            freeze,
+        } => start(
+            &filename,
+            format,
+            vars.into_iter().collect(),
+            freeze,
+            session,
+            out,
+        ),
+        Command::Next {} => next(session, out),
+        Command::Peek {} => peek(session, out),
         Command::Forward { count } => {
             let count = count.unwrap_or(1);
-            forward(count)?;
+            forward(count, session, out)
         }
         Command::Rewind { count } => {
             let count = count.unwrap_or(1);
-            rewind(count)?;
+            rewind(count, session, out)
         }
+        Command::Goto { target } => goto(&target, session, out),
+        Command::Undo {} => undo(session, out),
+        Command::Redo {} => redo(session, out),
+        Command::History {} => history(session, out),
+        Command::Status { format } => status(format, session, out),
+        Command::List {} => list(session, out),
+        Command::Refresh {} => refresh(session, out),
+        Command::Relink { filename } => relink(filename, session, out),
+        Command::Sessions { command } => sessions(command.unwrap_or(SessionsCommand::List {}), out),
+        Command::Check {
+            filename,
+            format,
+            fix,
+        } => check(&filename, format, fix, out),
+        Command::Config { command } => config(command, out),
+        Command::Paths {} => paths(session, out),
     }
-    Ok(())
 }
 
-/// Restart the configuration for the given path.
-fn start(filename: &Path) -> Result<()> {
+/// Adds the deck and the position of the session to the JSON output.
+fn report_state(cfg: &Config, out: &mut Output) {
+    if out.is_json() {
+        out.state(cfg.deck_path(), cfg.position());
+    }
+}
+
+/// Restart the configuration for the given path, optionally freezing a copy of the deck.
+fn start(
+    filename: &Path,
+    format: Option<DeckFormat>,
+    vars: BTreeMap<String, String>,
+    freeze: bool,
+    session: Option<String>,
+    out: &mut Output,
+) -> Result<()> {
     info!("Setting to work {}", filename.display());
 
-    Config::new(filename)
-        .map(|_| {
+    Config::new(filename, format, vars, session)
+        .and_then(|mut cfg| {
+            if freeze {
+                cfg.freeze()?;
+            }
+            report_state(&cfg, out);
+            Ok(())
+        })
+        .map(|()| {
             debug!("Configuration successfully created.");
         })
         .map_err(|err| {
             error!("Failed to create configuration: {err}.");
-            eyre!("Failed to create configuration: {err}")
+            eyre!(CommandError::new("Failed to create configuration", &err))
         })
 }
 
 /// Print next snippet and advance.
-fn next() -> Result<()> {
+fn next(session: Option<String>, out: &mut Output) -> Result<()> {
     info!("Next");
-    peek_or_next(true)
+    peek_or_next(true, session, out)
 }
 
 /// Print next snippet and don't advance.
-fn peek() -> Result<()> {
+fn peek(session: Option<String>, out: &mut Output) -> Result<()> {
     info!("peek");
-    peek_or_next(false)
+    peek_or_next(false, session, out)
 }
 
 /// Print next snippet and optionally advance.
-fn peek_or_next(advance: bool) -> Result<()> {
-    let mut cfg = Config::from_file().wrap_err("Failed to read config file")?;
+fn peek_or_next(advance: bool, session: Option<String>, out: &mut Output) -> Result<()> {
+    let mut cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
     let result = if advance { cfg.next() } else { cfg.peek() };
+    report_state(&cfg, out);
     result
         .map(|snippet| {
-            print!("{snippet}");
+            out.snippet(snippet);
         })
         .map_err(|err| {
-            error!(
-                "Failed to obtain {} snippet: {}.",
-                if advance { "next" } else { "current" },
-                err
+            let context = format!(
+                "Failed to obtain {} snippet",
+                if advance { "next" } else { "current" }
             );
-            eyre!(
-                "Failed to obtain {} snippet: {}.",
-                if advance { "next" } else { "current" },
-                err
-            )
+            error!("{context}: {err}.");
+            eyre!(CommandError::new(&context, &err))
         })
 }
 
 /// Increases the counter by the number provided in the argument. It returns a result of the operation.
-fn forward(count: usize) -> Result<()> {
+fn forward(count: usize, session: Option<String>, out: &mut Output) -> Result<()> {
     info!("Forward {count}");
-    let mut cfg = Config::from_file().wrap_err("Failed to read config file")?;
-    cfg.forward(count).map_err(|err| {
+    let mut cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    let result = cfg.forward(count);
+    report_state(&cfg, out);
+    result.map_err(|err| {
         error!("Failed to forward: {err}.");
-        eyre!("Failed to forward: {err}.")
+        eyre!(CommandError::new("Failed to forward", &err))
     })
 }
 
 /// Decreases the counter by the number provided in the argument. It returns a result of the operation.
-fn rewind(count: usize) -> Result<()> {
+fn rewind(count: usize, session: Option<String>, out: &mut Output) -> Result<()> {
     info!("Rewind {count}");
-    let mut cfg = Config::from_file().wrap_err("Failed to read config file")?;
-    cfg.rewind(count).map_err(|err| {
+    let mut cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    let result = cfg.rewind(count);
+    report_state(&cfg, out);
+    result.map_err(|err| {
         error!("Failed to rewind: {err}.");
-        eyre!("Failed to rewind: {err}.")
+        eyre!(CommandError::new("Failed to rewind", &err))
     })
 }
 
+/// Moves the counter to the snippet of the target, if it is in the deck.
+fn goto(target: &GotoTarget, session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("Goto {target}");
+    let mut cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    let result = cfg.goto(target);
+    report_state(&cfg, out);
+    result.map_err(|err| {
+        error!("Failed to go to {target}: {err}.");
+        eyre!(CommandError::new(
+            &format!("Failed to go to {target}"),
+            &err
+        ))
+    })
+}
+
+/// Restores the position before the last change.
+fn undo(session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("Undo");
+    let mut cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    let result = cfg.undo();
+    report_state(&cfg, out);
+    result.map_err(|err| {
+        error!("Failed to undo: {err}.");
+        eyre!(CommandError::new("Failed to undo", &err))
+    })
+}
+
+/// Repeats the last undone change of the position.
+fn redo(session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("Redo");
+    let mut cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    let result = cfg.redo();
+    report_state(&cfg, out);
+    result.map_err(|err| {
+        error!("Failed to redo: {err}.");
+        eyre!(CommandError::new("Failed to redo", &err))
+    })
+}
+
+/// Prints the changes of the position, oldest first.
+fn history(session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("History");
+    let cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    report_state(&cfg, out);
+    let journal = cfg.history();
+    let mut entries = journal.entries().peekable();
+    if entries.peek().is_none() {
+        out.line("No position changes");
+    }
+    let mut changes = Vec::new();
+    for (entry, undone) in entries {
+        out.line(format_args!(
+            "{entry}{}",
+            if undone { " (undone)" } else { "" }
+        ));
+        changes.push(json!({
+            "operation": entry.operation,
+            "from": entry.from,
+            "to": entry.to,
+            "time": entry.time,
+            "undone": undone,
+        }));
+    }
+    out.data(&changes).wrap_err("Failed to encode history")
+}
+
+/// Prints the deck, the position out of the total, and previews of the next snippets.
+fn status(format: OutputFormat, session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("Status");
+    let cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    report_state(&cfg, out);
+    let status = cfg.status().map_err(|err| {
+        error!("Failed to obtain status: {err}.");
+        eyre!(CommandError::new("Failed to obtain status", &err))
+    })?;
+    match format {
+        OutputFormat::Text => out.text(&status),
+        OutputFormat::Json => {
+            out.line(serde_json::to_string_pretty(&status).wrap_err("Failed to encode status")?);
+        }
+    }
+    out.data(&status).wrap_err("Failed to encode status")
+}
+
+/// Prints all the snippets of the deck, marking the next one.
+fn list(session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("List");
+    let cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    report_state(&cfg, out);
+    let snippets = cfg.list().map_err(|err| {
+        error!("Failed to list snippets: {err}.");
+        eyre!(CommandError::new("Failed to list snippets", &err))
+    })?;
+    out.text(deck_status::listing(&snippets));
+    out.data(&snippets).wrap_err("Failed to encode snippets")
+}
+
+/// Updates the copy of a frozen deck and prints the positions of the snippets that changed.
+fn refresh(session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("Refresh");
+    let mut cfg = Config::from_file(session).wrap_err("Failed to read config file")?;
+    let result = cfg.refresh();
+    report_state(&cfg, out);
+    let changes = result.map_err(|err| {
+        error!("Failed to refresh: {err}.");
+        eyre!(CommandError::new("Failed to refresh", &err))
+    })?;
+    if changes.is_empty() {
+        out.line("No snippets changed");
+    } else {
+        let positions: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        out.line(format_args!("Changed snippets: {}", positions.join(", ")));
+    }
+    out.data(&json!({ "changed": changes }))
+        .wrap_err("Failed to encode changes")
+}
+
+/// Makes the session use the deck at its new path, or the one found near its previous path, and prints that path.
+fn relink(filename: Option<PathBuf>, session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("Relink");
+    let path = Config::relink(filename, session).map_err(|err| {
+        error!("Failed to relink: {err}.");
+        eyre!(CommandError::new("Failed to relink", &err))
+    })?;
+    out.line(format_args!("Relinked to {}", path.display()));
+    out.deck(path);
+    Ok(())
+}
+
+/// Lists, switches, renames, or deletes sessions.
+fn sessions(command: SessionsCommand, out: &mut Output) -> Result<()> {
+    info!("Sessions");
+    let result = match command {
+        SessionsCommand::List {} => Config::sessions().map(|sessions| {
+            for (name, active) in &sessions {
+                out.line(format_args!("{} {name}", if *active { '*' } else { ' ' }));
+            }
+            Some(
+                sessions
+                    .into_iter()
+                    .map(|(name, active)| json!({ "name": name, "active": active }))
+                    .collect::<Vec<_>>(),
+            )
+        }),
+        SessionsCommand::Switch { name } => Config::switch_session(name).map(|()| None),
+        SessionsCommand::Rename { from, to } => Config::rename_session(from, to).map(|()| None),
+        SessionsCommand::Delete { name } => Config::delete_session(name).map(|()| None),
+    };
+    let sessions = result.map_err(|err| {
+        error!("Failed to manage sessions: {err}.");
+        eyre!(CommandError::new("Failed to manage sessions", &err))
+    })?;
+    match sessions {
+        Some(sessions) => out.data(&sessions).wrap_err("Failed to encode sessions"),
+        None => Ok(()),
+    }
+}
+
+/// Reports the problems of the deck, optionally fixing them first. It fails if any of them is an error.
+fn check(filename: &Path, format: Option<DeckFormat>, fix: bool, out: &mut Output) -> Result<()> {
+    info!("Checking {}", filename.display());
+    let format = format.unwrap_or_else(|| DeckFormat::from_path(filename));
+    let report = Config::settings()
+        .and_then(|settings| {
+            deck_checker::check_deck(filename, format, fix, &settings.deck_defaults())
+        })
+        .map_err(|err| {
+            error!("Failed to check deck: {err}.");
+            eyre!(CommandError::new("Failed to check deck", &err))
+        })?;
+    for file in &report.fixed {
+        out.line(format_args!("Fixed {}", file.display()));
+    }
+    for diagnostic in &report.diagnostics {
+        out.line(diagnostic);
+    }
+    out.data(&report).wrap_err("Failed to encode problems")?;
+    match report.errors() {
+        0 => Ok(()),
+        errors => Err(eyre!("{errors} error(s) found in the deck")),
+    }
+}
+
+/// Prints, changes, or lists the user settings.
+fn config(command: ConfigCommand, out: &mut Output) -> Result<()> {
+    info!("Config");
+    let result = match command {
+        ConfigCommand::Get { key } => Config::settings()
+            .and_then(|settings| settings.get(&key))
+            .map(|value| {
+                if let Some(value) = &value {
+                    out.line(value);
+                }
+                Some(json!({ "key": key, "value": value }))
+            }),
+        ConfigCommand::Set { key, value } => Config::set_setting(&key, &value).map(|()| None),
+        ConfigCommand::Unset { key } => Config::unset_setting(&key).map(|()| None),
+        ConfigCommand::List {} => Config::settings().map(|settings| {
+            let settings = settings.list();
+            for (key, value) in &settings {
+                out.line(format_args!("{key} = {value}"));
+            }
+            Some(json!(settings.into_iter().collect::<BTreeMap<_, _>>()))
+        }),
+    };
+    let settings = result.map_err(|err| {
+        error!("Failed to manage settings: {err}.");
+        eyre!(CommandError::new("Failed to manage settings", &err))
+    })?;
+    match settings {
+        Some(settings) => out.data(&settings).wrap_err("Failed to encode settings"),
+        None => Ok(()),
+    }
+}
+
+/// Print the paths of the files and directories used for the session.
+fn paths(session: Option<String>, out: &mut Output) -> Result<()> {
+    info!("Paths");
+    let paths = Config::paths(session).map_err(|err| {
+        error!("Failed to resolve paths: {err}.");
+        eyre!(CommandError::new("Failed to resolve paths", &err))
+    })?;
+    for (name, path) in &paths {
+        out.line(format_args!("{name}: {}", path.display()));
+    }
+    out.data(&paths.into_iter().collect::<BTreeMap<_, _>>())
+        .wrap_err("Failed to encode paths")
+}
+
 #[cfg(test)]
 mod tests {
     use mockall::predicate;
-    use std::path::PathBuf;
     use std::sync::Mutex;
 
     use crate::config::MockConfig;
+    use crate::deck_status::DeckStatus;
     use crate::lazy_coder_error::LazyCoderError;
+    use crate::position_journal::{Operation, PositionJournal};
+    use crate::settings::Settings;
 
     use super::*;
 
     static MTX: Mutex<()> = Mutex::new(());
 
+    fn text_output() -> Output {
+        Output::new(OutputFormat::Text, "test")
+    }
+
     #[test]
     fn start_creates_config_and_reports_ok() {
         let _mtx = MTX.lock();
         let path = PathBuf::from("/some/confid/file");
         let context = MockConfig::new_context();
-        context.expect().returning(|_| {
+        context.expect().returning(|_, _, _, _| {
             let config_mock = MockConfig::default();
             Ok(config_mock)
         });
 
-        assert!(start(&path).is_ok(), "Unexpected result");
+        assert!(
+            start(
+                &path,
+                None,
+                BTreeMap::new(),
+                false,
+                None,
+                &mut text_output()
+            )
+            .is_ok(),
+            "Unexpected result"
+        );
     }
 
     #[test]
@@ -163,16 +562,95 @@ mod tests {
         let context = MockConfig::new_context();
         context
             .expect()
-            .returning(|_| Err(LazyCoderError::ConfigDirError));
+            .returning(|_, _, _, _| Err(LazyCoderError::ConfigDirError));
 
-        assert!(start(&path).is_err(), "Unexpected result");
+        assert!(
+            start(
+                &path,
+                None,
+                BTreeMap::new(),
+                false,
+                None,
+                &mut text_output()
+            )
+            .is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn start_passes_format_variables_and_session_to_config() {
+        let _mtx = MTX.lock();
+        let path = PathBuf::from("/some/talk.txt");
+        let context = MockConfig::new_context();
+        context
+            .expect()
+            .with(
+                predicate::always(),
+                predicate::eq(Some(DeckFormat::Markdown)),
+                predicate::eq(BTreeMap::from([(
+                    String::from("customer"),
+                    String::from("ACME"),
+                )])),
+                predicate::eq(Some(String::from("meetup"))),
+            )
+            .returning(|_, _, _, _| Ok(MockConfig::default()));
+
+        assert!(
+            start(
+                &path,
+                Some(DeckFormat::Markdown),
+                BTreeMap::from([(String::from("customer"), String::from("ACME"))]),
+                false,
+                Some(String::from("meetup")),
+                &mut text_output()
+            )
+            .is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn start_freezes_deck_if_requested() {
+        let _mtx = MTX.lock();
+        let path = PathBuf::from("/some/confid/file");
+        let context = MockConfig::new_context();
+        context.expect().returning(|_, _, _, _| {
+            let mut config_mock = MockConfig::default();
+            config_mock.expect_freeze().once().returning(|| Ok(()));
+            Ok(config_mock)
+        });
+
+        assert!(
+            start(&path, None, BTreeMap::new(), true, None, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn start_returns_error_if_deck_cannot_be_frozen() {
+        let _mtx = MTX.lock();
+        let path = PathBuf::from("/some/confid/file");
+        let context = MockConfig::new_context();
+        context.expect().returning(|_, _, _, _| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_freeze()
+                .returning(|| Err(LazyCoderError::SnippetFileNotFound));
+            Ok(config_mock)
+        });
+
+        assert!(
+            start(&path, None, BTreeMap::new(), true, None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
     }
 
     #[test]
     fn next_uses_config_and_reports_ok() {
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_next()
@@ -180,7 +658,7 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(next().is_ok(), "Unexpected result");
+        assert!(next(None, &mut text_output()).is_ok(), "Unexpected result");
     }
 
     #[test]
@@ -189,16 +667,16 @@ mod tests {
         let context = MockConfig::from_file_context();
         context
             .expect()
-            .returning(|| Err(LazyCoderError::ConfigDirError));
+            .returning(|_| Err(LazyCoderError::ConfigDirError));
 
-        assert!(next().is_err(), "Unexpected result");
+        assert!(next(None, &mut text_output()).is_err(), "Unexpected result");
     }
 
     #[test]
     fn next_returns_error_if_config_operation_fails() {
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_next()
@@ -206,14 +684,73 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(next().is_err(), "Unexpected result");
+        assert!(next(None, &mut text_output()).is_err(), "Unexpected result");
+    }
+
+    #[test]
+    fn next_reports_snippet_and_state_as_json() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_next()
+                .returning(|| Ok(String::from("This is an snippet")));
+            config_mock
+                .expect_deck_path()
+                .returning(|| PathBuf::from("/talks/traits.lazycoder"));
+            config_mock.expect_position().returning(|| 4);
+            Ok(config_mock)
+        });
+        let mut out = Output::new(OutputFormat::Json, "next");
+
+        let result = next(None, &mut out);
+        let report = out.into_report(&result);
+
+        assert!(report.ok, "Unexpected report: {report:?}");
+        assert_eq!(report.snippet.as_deref(), Some("This is an snippet"));
+        assert_eq!(
+            report.deck.as_deref(),
+            Some(Path::new("/talks/traits.lazycoder"))
+        );
+        assert_eq!(report.position, Some(4));
+    }
+
+    #[test]
+    fn next_reports_error_kind_as_json() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_next()
+                .returning(|| Err(LazyCoderError::RunOutOfSnippets));
+            config_mock
+                .expect_deck_path()
+                .returning(|| PathBuf::from("/talks/traits.lazycoder"));
+            config_mock.expect_position().returning(|| 10);
+            Ok(config_mock)
+        });
+        let mut out = Output::new(OutputFormat::Json, "next");
+
+        let result = next(None, &mut out);
+        let report = out.into_report(&result);
+
+        assert!(!report.ok, "Unexpected report: {report:?}");
+        assert_eq!(report.position, Some(10));
+        let error = report.error.expect("Missing error");
+        assert_eq!(error.kind, "run_out_of_snippets");
+        assert_eq!(
+            error.message,
+            "Failed to obtain next snippet: out of range of available snippets."
+        );
     }
 
     #[test]
     fn peek_uses_config_and_reports_ok() {
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_peek()
@@ -221,7 +758,7 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(peek().is_ok(), "Unexpected result");
+        assert!(peek(None, &mut text_output()).is_ok(), "Unexpected result");
     }
 
     #[test]
@@ -230,16 +767,16 @@ mod tests {
         let context = MockConfig::from_file_context();
         context
             .expect()
-            .returning(|| Err(LazyCoderError::ConfigDirError));
+            .returning(|_| Err(LazyCoderError::ConfigDirError));
 
-        assert!(peek().is_err(), "Unexpected result");
+        assert!(peek(None, &mut text_output()).is_err(), "Unexpected result");
     }
 
     #[test]
     fn peek_returns_error_if_config_operation_fails() {
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_peek()
@@ -247,7 +784,7 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(peek().is_err(), "Unexpected result");
+        assert!(peek(None, &mut text_output()).is_err(), "Unexpected result");
     }
 
     #[test]
@@ -255,7 +792,7 @@ mod tests {
         const FORWARD_NUM: usize = 5;
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_forward()
@@ -264,7 +801,10 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(forward(FORWARD_NUM).is_ok(), "Unexpected result");
+        assert!(
+            forward(FORWARD_NUM, None, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
     }
 
     #[test]
@@ -274,9 +814,12 @@ mod tests {
         let context = MockConfig::from_file_context();
         context
             .expect()
-            .returning(|| Err(LazyCoderError::ConfigDirError));
+            .returning(|_| Err(LazyCoderError::ConfigDirError));
 
-        assert!(forward(FORWARD_NUM).is_err(), "Unexpected result");
+        assert!(
+            forward(FORWARD_NUM, None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
     }
 
     #[test]
@@ -284,7 +827,7 @@ mod tests {
         const FORWARD_NUM: usize = 5;
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_forward()
@@ -293,7 +836,10 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(forward(FORWARD_NUM).is_err(), "Unexpected result");
+        assert!(
+            forward(FORWARD_NUM, None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
     }
 
     #[test]
@@ -301,7 +847,7 @@ mod tests {
         const REWIND_NUM: usize = 3;
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_rewind()
@@ -310,7 +856,10 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(rewind(REWIND_NUM).is_ok(), "Unexpected result");
+        assert!(
+            rewind(REWIND_NUM, None, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
     }
 
     #[test]
@@ -320,9 +869,12 @@ mod tests {
         let context = MockConfig::from_file_context();
         context
             .expect()
-            .returning(|| Err(LazyCoderError::ConfigDirError));
+            .returning(|_| Err(LazyCoderError::ConfigDirError));
 
-        assert!(rewind(REWIND_NUM).is_err(), "Unexpected result");
+        assert!(
+            rewind(REWIND_NUM, None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
     }
 
     #[test]
@@ -330,7 +882,7 @@ mod tests {
         const REWIND_NUM: usize = 5;
         let _mtx = MTX.lock();
         let context = MockConfig::from_file_context();
-        context.expect().returning(|| {
+        context.expect().returning(|_| {
             let mut config_mock = MockConfig::default();
             config_mock
                 .expect_rewind()
@@ -339,6 +891,489 @@ mod tests {
             Ok(config_mock)
         });
 
-        assert!(rewind(REWIND_NUM).is_err(), "Unexpected result");
+        assert!(
+            rewind(REWIND_NUM, None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn relink_passes_path_and_session_to_config() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::relink_context();
+        context
+            .expect()
+            .with(
+                predicate::eq(Some(PathBuf::from("talks/traits.lazycoder"))),
+                predicate::eq(Some(String::from("meetup"))),
+            )
+            .returning(|_, _| Ok(PathBuf::from("/talks/traits.lazycoder")));
+
+        assert!(
+            relink(
+                Some(PathBuf::from("talks/traits.lazycoder")),
+                Some(String::from("meetup")),
+                &mut text_output()
+            )
+            .is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn relink_returns_error_if_deck_is_not_found() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::relink_context();
+        context.expect().returning(|_, _| {
+            Err(LazyCoderError::DeckMoved {
+                path: PathBuf::from("/talks/traits.lazycoder"),
+                candidates: Vec::new(),
+            })
+        });
+
+        assert!(
+            relink(None, None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn goto_uses_config_and_reports_ok() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_goto()
+                .with(predicate::eq(GotoTarget::FromEnd(1)))
+                .returning(|_| Ok(()));
+            Ok(config_mock)
+        });
+
+        assert!(
+            goto(&GotoTarget::FromEnd(1), None, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn goto_returns_error_if_target_is_not_found() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock.expect_goto().returning(|_| {
+                Err(LazyCoderError::TargetNotFound {
+                    target: String::from("intro"),
+                    nearest: Vec::new(),
+                })
+            });
+            Ok(config_mock)
+        });
+
+        assert!(
+            goto(
+                &GotoTarget::Label(String::from("intro")),
+                None,
+                &mut text_output()
+            )
+            .is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn refresh_uses_config_and_reports_ok() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock.expect_refresh().returning(|| Ok(vec![1, 3]));
+            Ok(config_mock)
+        });
+
+        assert!(
+            refresh(None, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn refresh_returns_error_if_config_operation_fails() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_refresh()
+                .returning(|| Err(LazyCoderError::DeckNotFrozen));
+            Ok(config_mock)
+        });
+
+        assert!(
+            refresh(None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn next_reads_config_of_session() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context
+            .expect()
+            .with(predicate::eq(Some(String::from("meetup"))))
+            .returning(|_| {
+                let mut config_mock = MockConfig::default();
+                config_mock
+                    .expect_next()
+                    .returning(|| Ok(String::from("This is an snippet")));
+                Ok(config_mock)
+            });
+
+        assert!(
+            next(Some(String::from("meetup")), &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn sessions_are_listed() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::sessions_context();
+        context.expect().returning(|| {
+            Ok(vec![
+                (String::from("default"), false),
+                (String::from("meetup"), true),
+            ])
+        });
+
+        assert!(
+            sessions(SessionsCommand::List {}, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn sessions_are_listed_as_json() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::sessions_context();
+        context
+            .expect()
+            .returning(|| Ok(vec![(String::from("meetup"), true)]));
+        let mut out = Output::new(OutputFormat::Json, "sessions");
+
+        let result = sessions(SessionsCommand::List {}, &mut out);
+
+        assert_eq!(
+            out.into_report(&result).data,
+            Some(json!([{ "name": "meetup", "active": true }]))
+        );
+    }
+
+    #[test]
+    fn session_is_switched() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::switch_session_context();
+        context
+            .expect()
+            .with(predicate::eq(String::from("meetup")))
+            .returning(|_| Ok(()));
+
+        assert!(
+            sessions(
+                SessionsCommand::Switch {
+                    name: String::from("meetup")
+                },
+                &mut text_output()
+            )
+            .is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn session_is_renamed() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::rename_session_context();
+        context
+            .expect()
+            .with(
+                predicate::eq(String::from("meetup")),
+                predicate::eq(String::from("conference")),
+            )
+            .returning(|_, _| Ok(()));
+
+        assert!(
+            sessions(
+                SessionsCommand::Rename {
+                    from: String::from("meetup"),
+                    to: String::from("conference")
+                },
+                &mut text_output()
+            )
+            .is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn session_deletion_error_is_reported() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::delete_session_context();
+        context
+            .expect()
+            .returning(|name| Err(LazyCoderError::SessionNotFound(name)));
+
+        assert!(
+            sessions(
+                SessionsCommand::Delete {
+                    name: String::from("meetup")
+                },
+                &mut text_output()
+            )
+            .is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn setting_is_set() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::set_setting_context();
+        context
+            .expect()
+            .with(predicate::eq("end_of_deck"), predicate::eq("wrap"))
+            .returning(|_, _| Ok(()));
+
+        assert!(
+            config(
+                ConfigCommand::Set {
+                    key: String::from("end_of_deck"),
+                    value: String::from("wrap")
+                },
+                &mut text_output()
+            )
+            .is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn setting_is_unset() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::unset_setting_context();
+        context
+            .expect()
+            .with(predicate::eq("log_file"))
+            .returning(|_| Ok(()));
+
+        assert!(
+            config(
+                ConfigCommand::Unset {
+                    key: String::from("log_file")
+                },
+                &mut text_output()
+            )
+            .is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn settings_are_listed() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::settings_context();
+        context.expect().returning(|| {
+            Ok(Settings {
+                separator: Some(String::from("%%%")),
+                ..Settings::default()
+            })
+        });
+
+        assert!(
+            config(ConfigCommand::List {}, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn unknown_setting_is_reported() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::settings_context();
+        context.expect().returning(|| Ok(Settings::default()));
+
+        assert!(
+            config(
+                ConfigCommand::Get {
+                    key: String::from("sparator")
+                },
+                &mut text_output()
+            )
+            .is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn paths_of_session_are_printed() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::paths_context();
+        context
+            .expect()
+            .with(predicate::eq(Some(String::from("meetup"))))
+            .returning(|_| Ok(vec![("state_dir", PathBuf::from("/some/state/path"))]));
+
+        assert!(
+            paths(Some(String::from("meetup")), &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn paths_error_is_reported() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::paths_context();
+        context
+            .expect()
+            .returning(|_| Err(LazyCoderError::ConfigDirError));
+
+        assert!(
+            paths(None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn undo_uses_config_and_reports_ok() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock.expect_undo().once().returning(|| Ok(()));
+            Ok(config_mock)
+        });
+
+        assert!(undo(None, &mut text_output()).is_ok(), "Unexpected result");
+    }
+
+    #[test]
+    fn undo_returns_error_if_config_operation_fails() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_undo()
+                .returning(|| Err(LazyCoderError::NothingToUndo));
+            Ok(config_mock)
+        });
+
+        assert!(undo(None, &mut text_output()).is_err(), "Unexpected result");
+    }
+
+    #[test]
+    fn redo_returns_error_if_config_operation_fails() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_redo()
+                .returning(|| Err(LazyCoderError::NothingToRedo));
+            Ok(config_mock)
+        });
+
+        assert!(redo(None, &mut text_output()).is_err(), "Unexpected result");
+    }
+
+    #[test]
+    fn history_uses_config_and_reports_ok() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock.expect_history().returning(|| {
+                let mut journal = PositionJournal::new(Path::new("/some/talk.lazycoder"));
+                journal.record(Operation::Next, 0, 1);
+                journal
+            });
+            Ok(config_mock)
+        });
+
+        assert!(
+            history(None, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn status_is_printed_as_json() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock.expect_status().returning(|| {
+                Ok(DeckStatus {
+                    deck: PathBuf::from("/some/talk.lazycoder"),
+                    position: 1,
+                    total: 3,
+                    next_title: None,
+                    upcoming: Vec::new(),
+                })
+            });
+            Ok(config_mock)
+        });
+
+        assert!(
+            status(OutputFormat::Json, None, &mut text_output()).is_ok(),
+            "Unexpected result"
+        );
+    }
+
+    #[test]
+    fn list_uses_config_and_reports_ok() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock.expect_list().returning(|| Ok(Vec::new()));
+            Ok(config_mock)
+        });
+
+        assert!(list(None, &mut text_output()).is_ok(), "Unexpected result");
+    }
+
+    #[test]
+    fn list_returns_error_if_config_operation_fails() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_list()
+                .returning(|| Err(LazyCoderError::SnippetFileNotFound));
+            Ok(config_mock)
+        });
+
+        assert!(list(None, &mut text_output()).is_err(), "Unexpected result");
+    }
+
+    #[test]
+    fn status_returns_error_if_config_operation_fails() {
+        let _mtx = MTX.lock();
+        let context = MockConfig::from_file_context();
+        context.expect().returning(|_| {
+            let mut config_mock = MockConfig::default();
+            config_mock
+                .expect_status()
+                .returning(|| Err(LazyCoderError::SnippetFileNotFound));
+            Ok(config_mock)
+        });
+
+        assert!(
+            status(OutputFormat::Text, None, &mut text_output()).is_err(),
+            "Unexpected result"
+        );
     }
 }
@@ -0,0 +1,291 @@
+//! Markdown decks
+//!
+//! Each fenced code block of a Markdown file is a snippet, in order. The info string of the block provides its
+//! language and the prose between the previous block and this one becomes its speaker notes. The last heading in that
+//! prose is used as the title of the snippet and the first level 1 heading of the file as the title of the deck.
+use std::path::{Path, PathBuf};
+
+use crate::{
+    deck::{self, DeckSettings, Snippet, SnippetMetadata},
+    lazy_coder_error::LazyCoderError,
+    snippet_handler::{DeckReader, ReaderShell, SnippetProvider},
+};
+
+pub struct MarkdownHandler<'a> {
+    path: PathBuf,
+    /// Settings of the deck, since Markdown files don't define them.
+    defaults: DeckSettings,
+    reader: Box<dyn DeckReader + 'a>,
+}
+
+impl<'a> MarkdownHandler<'a> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<MarkdownHandler<'a>, LazyCoderError> {
+        if path.as_ref().is_file() {
+            Ok(MarkdownHandler {
+                path: path.as_ref().to_path_buf(),
+                defaults: DeckSettings::default(),
+                reader: Box::new(ReaderShell),
+            })
+        } else {
+            Err(LazyCoderError::SnippetFileNotFound)
+        }
+    }
+
+    /// Uses the settings for the deck, except its title, that comes from the document.
+    pub fn with_defaults(mut self, defaults: DeckSettings) -> MarkdownHandler<'a> {
+        self.defaults = defaults;
+        self
+    }
+
+    fn parse(&self) -> Result<(DeckSettings, Vec<Snippet>), LazyCoderError> {
+        let text = self
+            .reader
+            .read_to_string(&self.path)
+            .map_err(LazyCoderError::SnippetFileError)?;
+        let (text, crlf) = deck::normalize_line_endings(&text);
+        parse_markdown(&text, &self.path, crlf, &self.defaults)
+    }
+
+    #[cfg(test)]
+    fn set_reader<R: DeckReader + 'a>(&mut self, reader: R) {
+        self.reader = Box::new(reader);
+    }
+}
+
+impl SnippetProvider for MarkdownHandler<'_> {
+    fn get_snippet(&self, position: usize) -> Result<Snippet, LazyCoderError> {
+        let (_, mut snippets) = self.parse()?;
+        if position < snippets.len() {
+            Ok(snippets.swap_remove(position))
+        } else {
+            Err(LazyCoderError::RunOutOfSnippets)
+        }
+    }
+
+    fn snippet_count(&self) -> Result<usize, LazyCoderError> {
+        Ok(self.parse()?.1.len())
+    }
+
+    fn snippets(&self) -> Result<Vec<Snippet>, LazyCoderError> {
+        Ok(self.parse()?.1)
+    }
+
+    fn settings(&self) -> Result<DeckSettings, LazyCoderError> {
+        Ok(self.parse()?.0)
+    }
+}
+
+/// Opening fence of a code block: fence character, fence length, and info string.
+struct Fence<'a> {
+    character: char,
+    length: usize,
+    info: &'a str,
+}
+
+impl<'a> Fence<'a> {
+    /// Recognizes a line that opens a fenced code block.
+    fn opening(line: &'a str) -> Option<Fence<'a>> {
+        let trimmed = line.trim_start_matches(' ');
+        if line.len() - trimmed.len() > 3 {
+            return None;
+        }
+        let character = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let length = trimmed.len() - trimmed.trim_start_matches(character).len();
+        let info = trimmed[length..].trim();
+        if length < 3 || (character == '`' && info.contains('`')) {
+            None
+        } else {
+            Some(Fence {
+                character,
+                length,
+                info,
+            })
+        }
+    }
+
+    /// Checks if the line closes the code block opened by this fence.
+    fn is_closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim_start_matches(' ');
+        let rest = trimmed.trim_start_matches(self.character);
+        line.len() - trimmed.len() <= 3
+            && trimmed.len() - rest.len() >= self.length
+            && rest.trim().is_empty()
+    }
+}
+
+/// Extracts the snippets from the fenced code blocks of a Markdown document.
+fn parse_markdown(
+    text: &str,
+    file: &Path,
+    crlf: bool,
+    defaults: &DeckSettings,
+) -> Result<(DeckSettings, Vec<Snippet>), LazyCoderError> {
+    let mut settings = defaults.clone();
+    let mut snippets = Vec::new();
+    let mut prose = String::new();
+    let mut title = None;
+    let mut lines = text.split_inclusive('\n').enumerate();
+    while let Some((index, line)) = lines.next() {
+        let Some(fence) = Fence::opening(line.trim_end_matches('\n')) else {
+            if let Some(heading) = heading(line) {
+                if settings.title.is_none() && line.starts_with("# ") {
+                    settings.title = Some(heading.to_owned());
+                }
+                title = Some(heading.to_owned());
+            }
+            prose.push_str(line);
+            continue;
+        };
+        let mut code = String::new();
+        let mut closed = false;
+        for (_, line) in lines.by_ref() {
+            if fence.is_closed_by(line.trim_end_matches('\n')) {
+                closed = true;
+                break;
+            }
+            code.push_str(line);
+        }
+        if !closed {
+            return Err(LazyCoderError::DeckParseError {
+                file: file.to_path_buf(),
+                line: index + 1,
+                message: String::from("code block is not closed"),
+            });
+        }
+        let notes = prose.trim();
+        let mut snippet = Snippet {
+            metadata: SnippetMetadata {
+                title: title.take(),
+                notes: (!notes.is_empty()).then(|| notes.to_owned()),
+                language: fence.info.split_whitespace().next().map(str::to_owned),
+                ..SnippetMetadata::default()
+            },
+            text: code,
+            cursor: None,
+        };
+        snippet.apply_settings(&settings, crlf);
+        snippets.push(snippet);
+        prose.clear();
+    }
+    Ok((settings, snippets))
+}
+
+/// Text of an ATX heading, if the line is one.
+fn heading(line: &str) -> Option<&str> {
+    let rest = line.trim_start_matches('#');
+    let level = line.len() - rest.len();
+    if (1..=6).contains(&level) && (rest.starts_with(' ') || rest.trim().is_empty()) {
+        Some(rest.trim().trim_end_matches('#').trim_end())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use crate::snippet_handler::MockDeckReader;
+
+    use super::*;
+
+    static DECK: &str = "# Traits in depth\n\nWe start with a struct.\n\n```rust\nstruct Circle;\n```\n\n## The trait\n\nThen we add a trait.\n\n~~~~ rust ignore\ntrait Shape {}\n```\nnot a fence end\n~~~~\n\n```\nfn main() {}\n```\n";
+
+    fn markdown_handler_with(text: &'static str) -> MarkdownHandler<'static> {
+        let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
+        let mut sut = MarkdownHandler::new(temp_file.path()).unwrap();
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader
+            .expect_read_to_string()
+            .returning(move |_| Ok(String::from(text)));
+        sut.set_reader(mock_reader);
+        sut
+    }
+
+    #[test]
+    fn code_blocks_are_snippets_in_order() {
+        let sut = markdown_handler_with(DECK);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "struct Circle;\n"
+                && snippet.metadata.language.as_deref() == Some("rust")
+                && snippet.metadata.title.as_deref() == Some("Traits in depth")
+                && snippet.metadata.notes.as_deref() == Some("# Traits in depth\n\nWe start with a struct.")),
+            "Unexpected result: {result:?}"
+        );
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "trait Shape {}\n```\nnot a fence end\n"
+                && snippet.metadata.language.as_deref() == Some("rust")
+                && snippet.metadata.title.as_deref() == Some("The trait")),
+            "Unexpected result: {result:?}"
+        );
+        let result = sut.get_snippet(2);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "fn main() {}\n"
+                && snippet.metadata.language.is_none()
+                && snippet.metadata.title.is_none()
+                && snippet.metadata.notes.is_none()),
+            "Unexpected result: {result:?}"
+        );
+        assert_eq!(sut.snippets().map(|snippets| snippets.len()).ok(), Some(3));
+    }
+
+    #[test]
+    fn cursor_marker_in_code_block_is_extracted() {
+        let sut = markdown_handler_with("```rust\nfn main() {⟨cursor⟩}\n```\n");
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "fn main() {}\n"
+                && snippet.cursor == Some(11)),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn crlf_markdown_is_parsed() {
+        let sut = markdown_handler_with("\u{feff}Intro\r\n\r\n```rust\r\nfn main() {}\r\n```\r\n");
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "fn main() {}\n"
+                && snippet.metadata.notes.as_deref() == Some("Intro")),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn unexisting_snippet_returns_error() {
+        let sut = markdown_handler_with(DECK);
+
+        let result = sut.get_snippet(3);
+        assert!(
+            matches!(result, Err(LazyCoderError::RunOutOfSnippets)),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn unclosed_code_block_reports_its_line() {
+        let sut = markdown_handler_with("Some prose\n\n```rust\nfn main() {}\n");
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 3, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn deck_title_is_first_level_1_heading() {
+        let sut = markdown_handler_with(DECK);
+
+        let result = sut.settings();
+        assert!(
+            matches!(result, Ok(ref settings) if settings.title.as_deref() == Some("Traits in depth")),
+            "Unexpected result: {result:?}"
+        );
+    }
+}
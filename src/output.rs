@@ -0,0 +1,326 @@
+//! Output of the subcommands
+//!
+//! Subcommands print text for people by default. With `--output json`, each one prints a single line with a JSON
+//! object instead, also when it fails, so scripts and status bars don't have to parse the text. The object carries the
+//! version of its schema, that increases whenever a field is removed or changes its meaning.
+use std::{error, fmt, path::PathBuf};
+
+use serde::Serialize;
+use serde_derive::Serialize;
+
+use crate::{cli_args::OutputFormat, lazy_coder_error::LazyCoderError, state_file::stored_path};
+
+/// Version of the schema of the JSON output.
+pub const SCHEMA_VERSION: u32 = 1;
+/// Kind of the errors that aren't caused by a `LazyCoderError`.
+const OTHER_KIND: &str = "other";
+
+/// Result of a subcommand, as printed in JSON.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Report {
+    pub schema_version: u32,
+    /// Name of the subcommand.
+    pub command: &'static str,
+    /// Whether the subcommand succeeded.
+    pub ok: bool,
+    /// Path of the deck of the session, as an array of its bytes if it isn't valid UTF-8.
+    #[serde(serialize_with = "stored_path::optional")]
+    pub deck: Option<PathBuf>,
+    /// Number of snippets provided after the subcommand, i.e. the position of the next one.
+    pub position: Option<usize>,
+    /// Snippet provided by the subcommand.
+    pub snippet: Option<String>,
+    /// Other results of the subcommand, with a structure that depends on it.
+    pub data: Option<serde_json::Value>,
+    pub error: Option<ErrorReport>,
+}
+
+/// Error of a failed subcommand.
+#[derive(Serialize, PartialEq, Debug)]
+pub struct ErrorReport {
+    /// Name of the `LazyCoderError` that caused it, in snake case, or `other`.
+    pub kind: &'static str,
+    /// Message shown to the user, with its causes.
+    pub message: String,
+}
+
+impl ErrorReport {
+    /// Report of the error, with the kind of the first error of its chain that has one.
+    pub fn of(err: &eyre::Report) -> ErrorReport {
+        let kind = err
+            .chain()
+            .find_map(|cause| {
+                cause
+                    .downcast_ref::<CommandError>()
+                    .map(|command_error| command_error.kind)
+                    .or_else(|| {
+                        cause
+                            .downcast_ref::<LazyCoderError>()
+                            .map(LazyCoderError::kind)
+                    })
+            })
+            .unwrap_or(OTHER_KIND);
+        ErrorReport {
+            kind,
+            message: format!("{err:#}"),
+        }
+    }
+}
+
+/// Error that made a subcommand fail, with the message for the user and the kind of the error that caused it.
+#[derive(Debug)]
+pub struct CommandError {
+    message: String,
+    kind: &'static str,
+}
+
+impl CommandError {
+    /// Error with the context of the subcommand followed by the message of the error.
+    pub fn new(context: &str, err: &LazyCoderError) -> CommandError {
+        CommandError {
+            message: format!("{context}: {err}."),
+            kind: err.kind(),
+        }
+    }
+}
+
+impl error::Error for CommandError {}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Destination of the results of a subcommand, that prints the text at once or collects them for the JSON report.
+pub struct Output {
+    format: OutputFormat,
+    report: Report,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, command: &'static str) -> Output {
+        Output {
+            format,
+            report: Report {
+                schema_version: SCHEMA_VERSION,
+                command,
+                ok: true,
+                deck: None,
+                position: None,
+                snippet: None,
+                data: None,
+                error: None,
+            },
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    /// Prints the text as is in text format.
+    pub fn text(&self, text: impl fmt::Display) {
+        if !self.is_json() {
+            print!("{text}");
+        }
+    }
+
+    /// Prints the text followed by a new line in text format.
+    pub fn line(&self, text: impl fmt::Display) {
+        if !self.is_json() {
+            println!("{text}");
+        }
+    }
+
+    /// Prints the snippet in text format, or adds it to the JSON report.
+    pub fn snippet(&mut self, snippet: String) {
+        if self.is_json() {
+            self.report.snippet = Some(snippet);
+        } else {
+            print!("{snippet}");
+        }
+    }
+
+    /// Adds the path of the deck to the JSON report.
+    pub fn deck(&mut self, deck: PathBuf) {
+        if self.is_json() {
+            self.report.deck = Some(deck);
+        }
+    }
+
+    /// Adds the path of the deck and the position of the session to the JSON report.
+    pub fn state(&mut self, deck: PathBuf, position: usize) {
+        if self.is_json() {
+            self.report.deck = Some(deck);
+            self.report.position = Some(position);
+        }
+    }
+
+    /// Adds the results specific to the subcommand to the JSON report.
+    pub fn data<T: Serialize>(&mut self, data: &T) -> Result<(), serde_json::Error> {
+        if self.is_json() {
+            self.report.data = Some(serde_json::to_value(data)?);
+        }
+        Ok(())
+    }
+
+    /// JSON report of the subcommand with its result.
+    pub fn into_report(self, result: &eyre::Result<()>) -> Report {
+        Report {
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(ErrorReport::of),
+            ..self.report
+        }
+    }
+
+    /// Prints the JSON report of the subcommand, if it is the format in use, and passes the result on.
+    pub fn finish(self, result: eyre::Result<()>) -> eyre::Result<()> {
+        if self.is_json() {
+            let report = self.into_report(&result);
+            match serde_json::to_string(&report) {
+                Ok(json) => println!("{json}"),
+                // The results of the subcommand can't be encoded, so it fails, as the report says.
+                Err(err) => {
+                    let message = format!("Failed to encode report: {err}");
+                    println!(
+                        "{}",
+                        serde_json::to_string(&Report {
+                            ok: false,
+                            deck: None,
+                            data: None,
+                            error: Some(ErrorReport {
+                                kind: OTHER_KIND,
+                                message: message.clone(),
+                            }),
+                            ..report
+                        })
+                        .expect("Failing to encode a report without results")
+                    );
+                    return result.and(Err(eyre::eyre!(message)));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::{WrapErr, eyre};
+
+    use super::*;
+
+    #[test]
+    fn report_has_results_of_subcommand() {
+        let mut output = Output::new(OutputFormat::Json, "next");
+        output.snippet(String::from("fn main() {}"));
+        output.state(PathBuf::from("/talks/traits.lazycoder"), 3);
+
+        let report = output.into_report(&Ok(()));
+
+        assert_eq!(
+            report,
+            Report {
+                schema_version: SCHEMA_VERSION,
+                command: "next",
+                ok: true,
+                deck: Some(PathBuf::from("/talks/traits.lazycoder")),
+                position: Some(3),
+                snippet: Some(String::from("fn main() {}")),
+                data: None,
+                error: None,
+            }
+        );
+    }
+
+    #[test]
+    fn report_is_encoded_with_all_fields() {
+        let mut output = Output::new(OutputFormat::Json, "refresh");
+        output.data(&[1, 4]).unwrap();
+
+        let json = serde_json::to_value(output.into_report(&Ok(()))).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "schema_version": SCHEMA_VERSION,
+                "command": "refresh",
+                "ok": true,
+                "deck": null,
+                "position": null,
+                "snippet": null,
+                "data": [1, 4],
+                "error": null,
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_deck_is_encoded_as_bytes() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let mut output = Output::new(OutputFormat::Json, "peek");
+        output.state(PathBuf::from(OsStr::from_bytes(b"/talks/caf\xe9")), 3);
+
+        let json = serde_json::to_value(output.into_report(&Ok(()))).unwrap();
+
+        assert_eq!(json["ok"], true);
+        assert_eq!(json["deck"], serde_json::json!(b"/talks/caf\xe9"));
+    }
+
+    #[test]
+    fn text_output_leaves_results_out_of_report() {
+        let mut output = Output::new(OutputFormat::Text, "paths");
+        output.state(PathBuf::from("/talks/traits.lazycoder"), 3);
+        output.data(&["/config"]).unwrap();
+
+        let report = output.into_report(&Ok(()));
+
+        assert_eq!(report.deck, None);
+        assert_eq!(report.position, None);
+        assert_eq!(report.data, None);
+    }
+
+    #[test]
+    fn error_of_command_has_kind_of_its_cause() {
+        let err = eyre!(CommandError::new(
+            "Failed to forward",
+            &LazyCoderError::OperationOutOfRange
+        ));
+
+        let report = Output::new(OutputFormat::Json, "forward").into_report(&Err(err));
+
+        assert!(!report.ok);
+        assert_eq!(
+            report.error,
+            Some(ErrorReport {
+                kind: "operation_out_of_range",
+                message: String::from("Failed to forward: operation out of range."),
+            })
+        );
+    }
+
+    #[test]
+    fn wrapped_error_has_kind_of_its_cause() {
+        let err = Err::<(), _>(LazyCoderError::SnippetFileNotFound)
+            .wrap_err("Failed to read config file")
+            .unwrap_err();
+
+        assert_eq!(
+            ErrorReport::of(&err),
+            ErrorReport {
+                kind: "snippet_file_not_found",
+                message: String::from("Failed to read config file: snippet file not found"),
+            }
+        );
+    }
+
+    #[test]
+    fn error_without_cause_has_other_kind() {
+        let err = eyre!("2 error(s) found in the deck");
+
+        assert_eq!(ErrorReport::of(&err).kind, OTHER_KIND);
+    }
+}
@@ -0,0 +1,343 @@
+//! Journal of positions
+//!
+//! A bounded record of the changes of the position of a session, so a mistaken command can be undone. Undone changes
+//! can be redone until a new change is recorded.
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    lazy_coder_error::LazyCoderError,
+    state_file::{stored_path, write_atomic},
+};
+
+/// Maximum number of changes kept. The oldest ones are dropped first.
+const MAX_ENTRIES: usize = 100;
+
+/// Command that changed the position.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Start,
+    Next,
+    Forward,
+    Rewind,
+    Goto,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::Start => "start",
+            Operation::Next => "next",
+            Operation::Forward => "forward",
+            Operation::Rewind => "rewind",
+            Operation::Goto => "goto",
+        };
+        f.pad(name)
+    }
+}
+
+/// Change of the position.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct JournalEntry {
+    pub operation: Operation,
+    pub from: usize,
+    pub to: usize,
+    /// Time of the change, in seconds since the Unix epoch.
+    pub time: u64,
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<7} {} -> {}",
+            format_time(self.time),
+            self.operation,
+            self.from,
+            self.to
+        )
+    }
+}
+
+/// Changes of the position in a deck, oldest first. The last `undone` ones have been undone.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Default, Debug)]
+pub struct PositionJournal {
+    #[serde(with = "stored_path")]
+    file_path: PathBuf,
+    #[serde(default)]
+    undone: usize,
+    #[serde(default)]
+    entries: Vec<JournalEntry>,
+}
+
+impl PositionJournal {
+    /// Creates an empty journal for the deck.
+    pub fn new(file_path: &Path) -> PositionJournal {
+        PositionJournal {
+            file_path: file_path.to_path_buf(),
+            ..PositionJournal::default()
+        }
+    }
+
+    /// Path of the deck whose positions are recorded.
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
+
+    /// Keeps the changes for the deck after it has been moved to another path.
+    pub fn moved_to(&mut self, file_path: &Path) {
+        file_path.clone_into(&mut self.file_path);
+    }
+
+    /// Position after the last change that hasn't been undone.
+    pub fn position(&self) -> Option<usize> {
+        self.applied().last().map(|entry| entry.to)
+    }
+
+    /// Records a change of the position, discarding the changes that have been undone.
+    pub fn record(&mut self, operation: Operation, from: usize, to: usize) {
+        self.entries.truncate(self.entries.len() - self.undone);
+        self.undone = 0;
+        self.entries.push(JournalEntry {
+            operation,
+            from,
+            to,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+    }
+
+    /// Undoes the last change that hasn't been undone, returning it.
+    pub fn undo(&mut self) -> Option<&JournalEntry> {
+        let index = self.applied().len().checked_sub(1)?;
+        self.undone += 1;
+        self.entries.get(index)
+    }
+
+    /// Redoes the first change that has been undone, returning it.
+    pub fn redo(&mut self) -> Option<&JournalEntry> {
+        self.undone = self.undone.checked_sub(1)?;
+        self.entries.get(self.entries.len() - self.undone - 1)
+    }
+
+    /// Changes, oldest first, with whether they have been undone.
+    pub fn entries(&self) -> impl Iterator<Item = (&JournalEntry, bool)> {
+        let applied = self.applied().len();
+        self.entries
+            .iter()
+            .enumerate()
+            .map(move |(index, entry)| (entry, index >= applied))
+    }
+
+    /// Reads a journal from the file.
+    ///
+    /// If the file says that more changes have been undone than it has, e.g. because it has been edited, all of them
+    /// are considered undone.
+    pub fn load(path: &Path) -> Result<PositionJournal, LazyCoderError> {
+        let text = fs::read_to_string(path).map_err(LazyCoderError::ConfigFileError)?;
+        let mut journal: PositionJournal = toml::from_str(&text)?;
+        if journal.undone > journal.entries.len() {
+            warn!(
+                "Journal {} has {} undone changes out of {}, undoing all of them",
+                path.display(),
+                journal.undone,
+                journal.entries.len()
+            );
+            journal.undone = journal.entries.len();
+        }
+        Ok(journal)
+    }
+
+    /// Writes the journal to the file, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), LazyCoderError> {
+        let text = toml::to_string(self).expect("Failing to encode TOML");
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(write_atomic(path, text)?)
+    }
+
+    fn applied(&self) -> &[JournalEntry] {
+        &self.entries[..self.entries.len() - self.undone]
+    }
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time.
+fn format_time(time: u64) -> String {
+    let days = time / 86_400;
+    let seconds = time % 86_400;
+    // Civil date from days since the epoch, counting years from March so leap days are at their end.
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn journal_with_positions(positions: &[usize]) -> PositionJournal {
+        let mut journal = PositionJournal::new(Path::new("/some/talk.lazycoder"));
+        for pair in positions.windows(2) {
+            journal.record(Operation::Forward, pair[0], pair[1]);
+        }
+        journal
+    }
+
+    #[test]
+    fn recorded_change_sets_position() {
+        let mut journal = PositionJournal::new(Path::new("/some/talk.lazycoder"));
+        assert_eq!(journal.position(), None);
+
+        journal.record(Operation::Next, 3, 4);
+
+        assert_eq!(journal.position(), Some(4));
+        assert!(matches!(
+            journal.entries().collect::<Vec<_>>()[..],
+            [(
+                JournalEntry {
+                    operation: Operation::Next,
+                    from: 3,
+                    to: 4,
+                    ..
+                },
+                false
+            )]
+        ));
+    }
+
+    #[test]
+    fn undone_changes_are_redone_in_order() {
+        let mut journal = journal_with_positions(&[0, 1, 5]);
+
+        assert_eq!(journal.undo().map(|entry| entry.from), Some(1));
+        assert_eq!(journal.undo().map(|entry| entry.from), Some(0));
+        assert_eq!(journal.undo(), None);
+        assert_eq!(journal.position(), None);
+        assert_eq!(journal.redo().map(|entry| entry.to), Some(1));
+        assert_eq!(journal.redo().map(|entry| entry.to), Some(5));
+        assert_eq!(journal.redo(), None);
+        assert_eq!(journal.position(), Some(5));
+    }
+
+    #[test]
+    fn recorded_change_discards_undone_ones() {
+        let mut journal = journal_with_positions(&[0, 1, 5]);
+        journal.undo();
+
+        journal.record(Operation::Rewind, 1, 0);
+
+        assert_eq!(journal.redo(), None);
+        assert_eq!(
+            journal
+                .entries()
+                .map(|(entry, _)| entry.operation)
+                .collect::<Vec<_>>(),
+            [Operation::Forward, Operation::Rewind]
+        );
+    }
+
+    #[test]
+    fn undone_changes_are_marked() {
+        let mut journal = journal_with_positions(&[0, 1, 5]);
+        journal.undo();
+
+        assert_eq!(
+            journal
+                .entries()
+                .map(|(_, undone)| undone)
+                .collect::<Vec<_>>(),
+            [false, true]
+        );
+    }
+
+    #[test]
+    fn oldest_changes_are_dropped() {
+        let positions: Vec<usize> = (0..=MAX_ENTRIES + 5).collect();
+
+        let journal = journal_with_positions(&positions);
+
+        assert_eq!(journal.entries().count(), MAX_ENTRIES);
+        assert_eq!(
+            journal.entries().next().map(|(entry, _)| entry.from),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn saved_journal_is_loaded() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("history").join("default.toml");
+        let mut journal = journal_with_positions(&[0, 1, 5]);
+        journal.undo();
+
+        journal.save(&path).expect("Unable to save journal");
+
+        let result = PositionJournal::load(&path);
+        assert!(
+            matches!(result, Ok(ref loaded) if *loaded == journal),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn journal_with_too_many_undone_changes_is_loaded_with_all_undone() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("default.toml");
+        fs::write(
+            &path,
+            "file_path = \"/talks/traits.lazycoder\"\nundone = 5\n\n[[entries]]\noperation = \"next\"\nfrom = 0\nto = 1\ntime = 0\n",
+        )
+        .expect("Unable to write journal");
+
+        let mut journal = PositionJournal::load(&path).expect("Unable to load journal");
+
+        assert_eq!(journal.position(), None);
+        assert_eq!(journal.entries().filter(|(_, undone)| *undone).count(), 1);
+        assert!(journal.undo().is_none());
+        assert_eq!(journal.redo().map(|entry| entry.to), Some(1));
+    }
+
+    #[test]
+    fn entry_is_displayed_with_utc_time() {
+        let entry = JournalEntry {
+            operation: Operation::Next,
+            from: 3,
+            to: 4,
+            time: 1_709_210_096,
+        };
+
+        assert_eq!(entry.to_string(), "2024-02-29 12:34:56 next    3 -> 4");
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+    }
+}
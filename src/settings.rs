@@ -0,0 +1,363 @@
+//! User settings
+//!
+//! Preferences that apply to every deck and session, stored in `settings.toml` in the configuration directory and
+//! managed with `lazycoder config`. They are kept apart from the runtime state, so the state can be discarded without
+//! losing them. Deck headers take precedence over them.
+use std::{fs, io, path::Path, path::PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    deck::{self, DeckSettings, EndOfDeck},
+    lazy_coder_error::LazyCoderError,
+    state_file::write_atomic,
+};
+
+/// Names of the settings, in the order they are listed.
+pub const KEYS: [&str; 6] = [
+    "separator",
+    "end_of_deck",
+    "fallback_text",
+    "output",
+    "log_file",
+    "default_session",
+];
+
+/// Number of spaces that replace each tab when tabs are expanded.
+const TAB_WIDTH: usize = 4;
+
+/// Change applied to the text of the snippets before they are provided.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputTransform {
+    /// Removes the newlines at the end of the snippet.
+    TrimTrailingNewline,
+    /// Replaces each tab with spaces.
+    ExpandTabs,
+}
+
+/// User settings. Those that aren't set use the built-in defaults.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Separator of the decks that don't define one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub separator: Option<String>,
+    /// End-of-deck policy of the decks that don't define one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_of_deck: Option<EndOfDeck>,
+    /// Fallback text of the decks that don't define one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_text: Option<String>,
+    /// Transforms applied, in order, to the provided snippets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output: Vec<OutputTransform>,
+    /// File where the log is written instead of the standard error.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<PathBuf>,
+    /// Session used when none is provided and none has been made active.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_session: Option<String>,
+}
+
+impl Settings {
+    /// Reads the settings from the file. A missing file has the default settings.
+    pub fn load(path: &Path) -> Result<Settings, LazyCoderError> {
+        match fs::read_to_string(path) {
+            Ok(text) => toml::from_str::<Settings>(&text)?.validated(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+            Err(err) => Err(LazyCoderError::ConfigFileError(err)),
+        }
+    }
+
+    /// Writes the settings to the file, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), LazyCoderError> {
+        let text = toml::to_string(self).expect("Failing to encode TOML");
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(write_atomic(path, text)?)
+    }
+
+    /// Value of a setting as shown to the user, or `None` if it isn't set. Lists are separated by commas.
+    pub fn get(&self, key: &str) -> Result<Option<String>, LazyCoderError> {
+        if !KEYS.contains(&key) {
+            return Err(LazyCoderError::UnknownSetting(key.to_owned()));
+        }
+        let table = toml::Table::try_from(self).expect("Failing to encode settings");
+        Ok(table.get(key).map(display_value))
+    }
+
+    /// Changes the value of a setting, or unsets it if the value is `None`. Lists are separated by commas.
+    pub fn set(&mut self, key: &str, value: Option<&str>) -> Result<(), LazyCoderError> {
+        if !KEYS.contains(&key) {
+            return Err(LazyCoderError::UnknownSetting(key.to_owned()));
+        }
+        let mut table = toml::Table::try_from(&*self).expect("Failing to encode settings");
+        match value {
+            Some(value) if key == "output" => table.insert(
+                key.to_owned(),
+                toml::Value::Array(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(toml::Value::from)
+                        .collect(),
+                ),
+            ),
+            Some(value) => table.insert(key.to_owned(), toml::Value::from(value)),
+            None => table.remove(key),
+        };
+        *self = table
+            .try_into::<Settings>()
+            .map_err(|_| LazyCoderError::InvalidSettingValue {
+                key: key.to_owned(),
+                value: value.unwrap_or_default().to_owned(),
+            })?
+            .validated()?;
+        Ok(())
+    }
+
+    /// These settings, if their values are valid beyond their type. The separator must be a single line that isn't
+    /// blank, as in deck headers.
+    fn validated(self) -> Result<Settings, LazyCoderError> {
+        match &self.separator {
+            Some(separator) if !deck::is_valid_separator(separator) => {
+                Err(LazyCoderError::InvalidSettingValue {
+                    key: String::from("separator"),
+                    value: separator.clone(),
+                })
+            }
+            _ => Ok(self),
+        }
+    }
+
+    /// Settings that are set, with their values, in the order of [`KEYS`].
+    pub fn list(&self) -> Vec<(&'static str, String)> {
+        KEYS.iter()
+            .filter_map(|key| Some((*key, self.get(key).ok()??)))
+            .collect()
+    }
+
+    /// Settings for the decks that don't define them in their header.
+    pub fn deck_defaults(&self) -> DeckSettings {
+        let mut defaults = DeckSettings::default();
+        if let Some(separator) = &self.separator {
+            defaults.separator.clone_from(separator);
+        }
+        if let Some(end_of_deck) = self.end_of_deck {
+            defaults.end_of_deck = end_of_deck;
+        }
+        defaults.fallback_text.clone_from(&self.fallback_text);
+        defaults
+    }
+
+    /// Applies the output transforms to the text of a snippet.
+    pub fn transform(&self, text: String) -> String {
+        self.output
+            .iter()
+            .fold(text, |text, transform| match transform {
+                OutputTransform::TrimTrailingNewline => {
+                    text.trim_end_matches(['\r', '\n']).to_owned()
+                }
+                OutputTransform::ExpandTabs => text.replace('\t', &" ".repeat(TAB_WIDTH)),
+            })
+    }
+}
+
+/// Text of a setting value, without the quotes of strings.
+fn display_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(text) => text.clone(),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(display_value)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn missing_file_has_default_settings() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+
+        let result = Settings::load(&dir.path().join("settings.toml"));
+
+        assert!(
+            matches!(result, Ok(ref settings) if *settings == Settings::default()),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn saved_settings_are_loaded() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("lazycoder").join("settings.toml");
+        let settings = Settings {
+            end_of_deck: Some(EndOfDeck::Wrap),
+            output: vec![OutputTransform::ExpandTabs],
+            ..Settings::default()
+        };
+
+        settings.save(&path).expect("Unable to save settings");
+
+        assert_eq!(
+            fs::read_to_string(&path).expect("Unable to read settings"),
+            "end_of_deck = \"wrap\"\noutput = [\"expand_tabs\"]\n"
+        );
+        let result = Settings::load(&path);
+        assert!(
+            matches!(result, Ok(ref loaded) if *loaded == settings),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn unknown_setting_in_file_is_rejected() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("settings.toml");
+        fs::write(&path, "separatr = \"%%%\"\n").expect("Unable to write settings");
+
+        let result = Settings::load(&path);
+
+        assert!(
+            matches!(result, Err(LazyCoderError::ConfigEncoding(_))),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn set_values_are_got_and_listed() {
+        let mut settings = Settings::default();
+
+        settings.set("separator", Some("%%%")).unwrap();
+        settings
+            .set("output", Some("trim_trailing_newline, expand_tabs"))
+            .unwrap();
+
+        assert_eq!(settings.separator.as_deref(), Some("%%%"));
+        assert_eq!(
+            settings.get("output").unwrap().as_deref(),
+            Some("trim_trailing_newline,expand_tabs")
+        );
+        assert_eq!(settings.get("log_file").unwrap(), None);
+        assert_eq!(
+            settings.list(),
+            [
+                ("separator", String::from("%%%")),
+                ("output", String::from("trim_trailing_newline,expand_tabs"))
+            ]
+        );
+    }
+
+    #[test]
+    fn unset_value_is_removed() {
+        let mut settings = Settings {
+            default_session: Some(String::from("meetup")),
+            ..Settings::default()
+        };
+
+        settings.set("default_session", None).unwrap();
+
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn invalid_value_is_rejected() {
+        let mut settings = Settings::default();
+
+        let result = settings.set("end_of_deck", Some("loop"));
+
+        assert!(
+            matches!(result, Err(LazyCoderError::InvalidSettingValue { ref key, ref value }) if key == "end_of_deck" && value == "loop"),
+            "Unexpected result: {result:?}"
+        );
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn blank_separator_is_rejected() {
+        let mut settings = Settings::default();
+
+        for separator in ["", "   "] {
+            let result = settings.set("separator", Some(separator));
+
+            assert!(
+                matches!(result, Err(LazyCoderError::InvalidSettingValue { ref key, ref value }) if key == "separator" && value == separator),
+                "Unexpected result: {result:?}"
+            );
+        }
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn blank_separator_in_file_is_rejected() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("settings.toml");
+        fs::write(&path, "separator = \" \"\n").expect("Unable to write settings");
+
+        let result = Settings::load(&path);
+
+        assert!(
+            matches!(result, Err(LazyCoderError::InvalidSettingValue { ref key, .. }) if key == "separator"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn unknown_setting_is_rejected() {
+        let mut settings = Settings::default();
+
+        assert!(matches!(
+            settings.set("sparator", Some("%%%")),
+            Err(LazyCoderError::UnknownSetting(ref key)) if key == "sparator"
+        ));
+        assert!(matches!(
+            settings.get("sparator"),
+            Err(LazyCoderError::UnknownSetting(_))
+        ));
+    }
+
+    #[test]
+    fn deck_defaults_use_settings() {
+        let settings = Settings {
+            separator: Some(String::from("%%%")),
+            end_of_deck: Some(EndOfDeck::Fallback),
+            fallback_text: Some(String::from("// That's all folks!")),
+            ..Settings::default()
+        };
+
+        let defaults = settings.deck_defaults();
+
+        assert_eq!(defaults.separator, "%%%");
+        assert_eq!(defaults.end_of_deck, EndOfDeck::Fallback);
+        assert_eq!(
+            defaults.fallback_text.as_deref(),
+            Some("// That's all folks!")
+        );
+    }
+
+    #[test]
+    fn output_transforms_are_applied_in_order() {
+        let settings = Settings {
+            output: vec![
+                OutputTransform::ExpandTabs,
+                OutputTransform::TrimTrailingNewline,
+            ],
+            ..Settings::default()
+        };
+
+        assert_eq!(
+            settings.transform(String::from("fn main() {\n\tlet x = 1;\n}\n\n")),
+            "fn main() {\n    let x = 1;\n}"
+        );
+    }
+}
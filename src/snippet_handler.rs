@@ -1,72 +1,410 @@
 use std::{
-    fs,
-    path::{Path, PathBuf},
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    time::UNIX_EPOCH,
 };
 
 #[cfg(test)]
 use mockall::automock;
 
-use crate::lazy_coder_error::LazyCoderError;
+use log::{debug, warn};
+
+use crate::{
+    deck::{self, DeckSettings, RawOffsets, Snippet},
+    lazy_coder_error::LazyCoderError,
+    snippet_index::{IndexedFile, IndexedSnippet, SnippetIndex},
+};
+
+/// Maximum nesting of included files, to protect against cycles that can't be detected from the paths.
+const MAX_INCLUDE_DEPTH: usize = 32;
 
 #[cfg_attr(test, automock)]
-trait WholeFileReader {
-    fn read_to_string(&self) -> std::io::Result<String>;
+pub(crate) trait DeckReader {
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    /// Reads the bytes of the file from `start` up to, but not including, `end`.
+    fn read_range(&self, path: &Path, start: u64, end: u64) -> io::Result<String>;
+    /// Modification time, in nanoseconds since the Unix epoch, and size of the file.
+    fn modified_and_size(&self, path: &Path) -> io::Result<(u64, u64)>;
 }
 
-struct ReaderShell {
-    filename: PathBuf,
-}
+pub(crate) struct ReaderShell;
 
-impl ReaderShell {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<ReaderShell, LazyCoderError> {
-        if path.as_ref().is_file() {
-            Ok(ReaderShell {
-                filename: path.as_ref().to_path_buf(),
-            })
-        } else {
-            Err(LazyCoderError::SnippetFileNotFound)
-        }
+impl DeckReader for ReaderShell {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn read_range(&self, path: &Path, start: u64, end: u64) -> io::Result<String> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut text = String::new();
+        file.take(end.saturating_sub(start))
+            .read_to_string(&mut text)?;
+        Ok(text)
     }
-}
 
-impl WholeFileReader for ReaderShell {
-    fn read_to_string(&self) -> std::io::Result<String> {
-        fs::read_to_string(&self.filename)
+    fn modified_and_size(&self, path: &Path) -> io::Result<(u64, u64)> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(io::Error::other)?
+            .as_nanos();
+        Ok((
+            u64::try_from(modified).map_err(io::Error::other)?,
+            metadata.len(),
+        ))
     }
 }
 
 #[cfg_attr(test, automock)]
 pub trait SnippetProvider {
-    fn get_snippet(&self, position: usize) -> Result<String, LazyCoderError>;
+    fn get_snippet(&self, position: usize) -> Result<Snippet, LazyCoderError>;
+    fn snippet_count(&self) -> Result<usize, LazyCoderError>;
+    fn snippets(&self) -> Result<Vec<Snippet>, LazyCoderError>;
+    fn settings(&self) -> Result<DeckSettings, LazyCoderError>;
+}
+
+/// Text of a snippet, before parsing its header, and where it comes from.
+struct Chunk {
+    file: PathBuf,
+    line: usize,
+    text: String,
+    settings: Rc<DeckSettings>,
+    crlf: bool,
+}
+
+impl Chunk {
+    fn parse(&self) -> Result<Snippet, LazyCoderError> {
+        let mut snippet = Snippet::parse(&self.text, &self.file, self.line)?;
+        snippet.unescape(&self.settings);
+        snippet.apply_settings(&self.settings, self.crlf);
+        Ok(snippet)
+    }
+}
+
+/// Files and snippets of a deck, with the included files expanded in place.
+#[derive(Default)]
+struct Deck {
+    files: Vec<IndexedFile>,
+    chunks: Vec<Chunk>,
+    /// Location of each chunk in the files of the deck.
+    locations: Vec<IndexedSnippet>,
 }
 
 pub struct SnippetHandler<'a> {
-    reader: Box<dyn WholeFileReader + 'a>,
+    path: PathBuf,
+    index_path: Option<PathBuf>,
+    /// Settings used for the files of the deck that don't define them.
+    defaults: DeckSettings,
+    reader: Box<dyn DeckReader + 'a>,
 }
 
 impl<'a> SnippetHandler<'a> {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<SnippetHandler<'a>, LazyCoderError> {
-        Ok(SnippetHandler {
-            reader: Box::new(ReaderShell::new(path)?),
-        })
+        if path.as_ref().is_file() {
+            Ok(SnippetHandler {
+                path: path.as_ref().to_path_buf(),
+                index_path: None,
+                defaults: DeckSettings::default(),
+                reader: Box::new(ReaderShell),
+            })
+        } else {
+            Err(LazyCoderError::SnippetFileNotFound)
+        }
+    }
+
+    /// Creates a snippet handler that keeps the offsets of the snippets of the deck in an index file, so getting a
+    /// snippet doesn't require reading and splitting the whole deck.
+    pub fn with_index<P: AsRef<Path>>(
+        path: P,
+        index_path: PathBuf,
+    ) -> Result<SnippetHandler<'a>, LazyCoderError> {
+        let mut handler = SnippetHandler::new(path)?;
+        handler.index_path = Some(index_path);
+        Ok(handler)
+    }
+
+    /// Uses the settings for the files of the deck that don't define them.
+    pub fn with_defaults(mut self, defaults: DeckSettings) -> SnippetHandler<'a> {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Reads the deck, following its include directives.
+    fn deck(&self) -> Result<Deck, LazyCoderError> {
+        let mut deck = Deck::default();
+        self.collect_chunks(&self.path, None, &mut Vec::new(), &mut deck)?;
+        let settings = &deck.files[0].settings;
+        debug!(
+            "Deck {:?} by {:?}",
+            settings.title.as_deref().unwrap_or_default(),
+            settings.author.as_deref().unwrap_or_default()
+        );
+        Ok(deck)
+    }
+
+    /// Appends the snippets of the deck file to `deck`, recursively following its include directives.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path to the deck file.
+    /// * `parent` - settings of the including file, if any.
+    /// * `including` - files that are being included, used to detect cycles.
+    /// * `deck` - files and snippets found so far.
+    fn collect_chunks(
+        &self,
+        path: &Path,
+        parent: Option<&DeckSettings>,
+        including: &mut Vec<PathBuf>,
+        deck: &mut Deck,
+    ) -> Result<Rc<DeckSettings>, LazyCoderError> {
+        // Metadata is taken before reading, so changes while reading invalidate the index.
+        let (modified, size) = if self.index_path.is_some() {
+            self.reader
+                .modified_and_size(path)
+                .map_err(LazyCoderError::SnippetFileError)?
+        } else {
+            (0, 0)
+        };
+        let raw = self
+            .reader
+            .read_to_string(path)
+            .map_err(LazyCoderError::SnippetFileError)?;
+        let (text, crlf) = deck::normalize_line_endings(&raw);
+        let raw_offsets = RawOffsets::new(&raw);
+        let (mut settings, body, mut line) = DeckSettings::parse(&text, path, &self.defaults)?;
+        if let Some(parent) = parent {
+            settings.inherit(parent);
+        }
+        let file = deck.files.len();
+        deck.files.push(IndexedFile {
+            path: path.to_path_buf(),
+            modified,
+            size,
+            hash: deck::content_hash(raw.as_bytes()),
+            crlf,
+            settings: settings.clone(),
+        });
+        let settings = Rc::new(settings);
+        let separator = settings.snippet_separator();
+        let mut offset = text.len() - body.len();
+        including.push(path.to_path_buf());
+        for chunk in body.split(&separator) {
+            if let Some(directives) = deck::include_directives(chunk, path, line)? {
+                for (directive_line, target) in directives {
+                    let included = resolve_include(path, target);
+                    let parse_error = |message: String| LazyCoderError::DeckParseError {
+                        file: path.to_path_buf(),
+                        line: directive_line,
+                        message,
+                    };
+                    if including.contains(&included) {
+                        return Err(parse_error(format!(
+                            "include cycle: {} is already being included",
+                            included.display()
+                        )));
+                    }
+                    if including.len() >= MAX_INCLUDE_DEPTH {
+                        return Err(parse_error(String::from("includes are nested too deeply")));
+                    }
+                    self.collect_chunks(&included, Some(&settings), including, deck)
+                        .map_err(|err| match err {
+                            LazyCoderError::SnippetFileError(err) => parse_error(format!(
+                                "cannot read included file {}: {err}",
+                                included.display()
+                            )),
+                            err => err,
+                        })?;
+                }
+            } else {
+                deck.chunks.push(Chunk {
+                    file: path.to_path_buf(),
+                    line,
+                    text: chunk.to_owned(),
+                    settings: Rc::clone(&settings),
+                    crlf,
+                });
+                deck.locations.push(IndexedSnippet {
+                    file,
+                    line,
+                    start: raw_offsets.raw(offset) as u64,
+                    end: raw_offsets.raw(offset + chunk.len()) as u64,
+                });
+            }
+            line += chunk.matches('\n').count() + separator.matches('\n').count();
+            offset += chunk.len() + separator.len();
+        }
+        including.pop();
+        Ok(settings)
+    }
+
+    /// Reads a deck file with normalized line endings, and whether it has CRLF line endings.
+    fn read_deck(&self, path: &Path) -> Result<(String, bool), LazyCoderError> {
+        let text = self
+            .reader
+            .read_to_string(path)
+            .map_err(LazyCoderError::SnippetFileError)?;
+        Ok(deck::normalize_line_endings(&text))
+    }
+
+    /// Index of the deck, rebuilt and saved if the stored one doesn't match the files of the deck anymore.
+    ///
+    /// Failing to save the index isn't an error, since it is only needed to speed up the next lookups.
+    fn current_index(&self, index_path: &Path) -> Result<SnippetIndex, LazyCoderError> {
+        if let Some(mut index) = SnippetIndex::load(index_path) {
+            match self.check_index(&mut index) {
+                IndexState::Valid => return Ok(index),
+                IndexState::Touched => {
+                    debug!("Deck was touched, updating index {}", index_path.display());
+                    save_index(&index, index_path);
+                    return Ok(index);
+                }
+                IndexState::Stale => debug!("Index {} is stale", index_path.display()),
+            }
+        }
+        let deck = self.deck()?;
+        let index = SnippetIndex::new(self.defaults.clone(), deck.files, deck.locations);
+        save_index(&index, index_path);
+        Ok(index)
+    }
+
+    /// Checks if the index matches the files of the deck, updating the modification times of the files that have
+    /// been touched without changing their contents.
+    fn check_index(&self, index: &mut SnippetIndex) -> IndexState {
+        if index.defaults != self.defaults
+            || index
+                .files
+                .first()
+                .is_none_or(|file| file.path != self.path)
+        {
+            return IndexState::Stale;
+        }
+        let mut state = IndexState::Valid;
+        for file in &mut index.files {
+            let Ok((modified, size)) = self.reader.modified_and_size(&file.path) else {
+                return IndexState::Stale;
+            };
+            if size != file.size {
+                return IndexState::Stale;
+            }
+            if modified != file.modified {
+                let Ok(text) = self.reader.read_to_string(&file.path) else {
+                    return IndexState::Stale;
+                };
+                if deck::content_hash(text.as_bytes()) != file.hash {
+                    return IndexState::Stale;
+                }
+                file.modified = modified;
+                state = IndexState::Touched;
+            }
+        }
+        state
+    }
+
+    /// Gets a snippet reading only its bytes from the deck file.
+    fn indexed_snippet(
+        &self,
+        index: &SnippetIndex,
+        position: usize,
+    ) -> Result<Snippet, LazyCoderError> {
+        let location = index
+            .snippets
+            .get(position)
+            .ok_or(LazyCoderError::RunOutOfSnippets)?;
+        let file = &index.files[location.file];
+        let raw = self
+            .reader
+            .read_range(&file.path, location.start, location.end)
+            .map_err(LazyCoderError::SnippetFileError)?;
+        let (text, _) = deck::normalize_line_endings(&raw);
+        Chunk {
+            file: file.path.clone(),
+            line: location.line,
+            text,
+            settings: Rc::new(file.settings.clone()),
+            crlf: file.crlf,
+        }
+        .parse()
     }
 
     #[cfg(test)]
-    fn set_reader<R: WholeFileReader + 'a>(&mut self, reader: R) {
+    fn set_reader<R: DeckReader + 'a>(&mut self, reader: R) {
         self.reader = Box::new(reader);
     }
 }
 
 impl SnippetProvider for SnippetHandler<'_> {
-    fn get_snippet(&self, position: usize) -> Result<String, LazyCoderError> {
-        match self.reader.read_to_string() {
-            Ok(string) => match string.split("\n---\n\n").nth(position) {
-                Some(snippet) => Ok(snippet.to_owned()),
-                None => Err(LazyCoderError::RunOutOfSnippets),
-            },
-            Err(err) => Err(LazyCoderError::SnippetFileError(err)),
+    fn get_snippet(&self, position: usize) -> Result<Snippet, LazyCoderError> {
+        if let Some(index_path) = &self.index_path {
+            let index = self.current_index(index_path)?;
+            return self.indexed_snippet(&index, position);
+        }
+        let deck = self.deck()?;
+        deck.chunks
+            .get(position)
+            .ok_or(LazyCoderError::RunOutOfSnippets)?
+            .parse()
+    }
+
+    fn snippet_count(&self) -> Result<usize, LazyCoderError> {
+        if let Some(index_path) = &self.index_path {
+            return Ok(self.current_index(index_path)?.snippets.len());
+        }
+        Ok(self.deck()?.chunks.len())
+    }
+
+    fn snippets(&self) -> Result<Vec<Snippet>, LazyCoderError> {
+        if let Some(index_path) = &self.index_path {
+            let index = self.current_index(index_path)?;
+            return (0..index.snippets.len())
+                .map(|position| self.indexed_snippet(&index, position))
+                .collect();
+        }
+        self.deck()?.chunks.iter().map(Chunk::parse).collect()
+    }
+
+    fn settings(&self) -> Result<DeckSettings, LazyCoderError> {
+        if let Some(index_path) = &self.index_path {
+            let mut index = self.current_index(index_path)?;
+            return Ok(index.files.swap_remove(0).settings);
+        }
+        let (deck, _) = self.read_deck(&self.path)?;
+        Ok(DeckSettings::parse(&deck, &self.path, &self.defaults)?.0)
+    }
+}
+
+/// Result of checking a stored index against the files of the deck.
+enum IndexState {
+    Valid,
+    /// Valid, but some files have new modification times.
+    Touched,
+    Stale,
+}
+
+/// Saves the index, logging a warning if it can't be done.
+fn save_index(index: &SnippetIndex, index_path: &Path) {
+    if let Err(err) = index.save(index_path) {
+        warn!("Unable to save index {}: {err}", index_path.display());
+    }
+}
+
+/// Resolves the path of an included file relative to the file that includes it, without accessing the file system.
+pub(crate) fn resolve_include(including_file: &Path, target: &str) -> PathBuf {
+    let base = including_file.parent().unwrap_or_else(|| Path::new(""));
+    let mut resolved = PathBuf::new();
+    for component in base.join(target).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if resolved.file_name().is_some() => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
         }
     }
+    resolved
 }
 
 #[cfg(test)]
@@ -74,9 +412,12 @@ mod tests {
     use std::{
         io::{Error, ErrorKind},
         path::PathBuf,
+        time::{Duration, SystemTime},
     };
 
-    use tempfile::NamedTempFile;
+    use tempfile::{NamedTempFile, tempdir};
+
+    use crate::deck::EndOfDeck;
 
     use super::*;
 
@@ -105,10 +446,10 @@ mod tests {
         let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
         let path = temp_file.path();
         let mut sut = SnippetHandler::new(path).unwrap();
-        let mut mock_reader = MockWholeFileReader::new();
+        let mut mock_reader = MockDeckReader::new();
         mock_reader
             .expect_read_to_string()
-            .returning(|| Err(Error::new(ErrorKind::NotFound, "")));
+            .returning(|_| Err(Error::new(ErrorKind::NotFound, "")));
 
         sut.set_reader(mock_reader);
 
@@ -129,8 +470,8 @@ mod tests {
         let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
         let path = temp_file.path();
         let mut sut = SnippetHandler::new(path).unwrap();
-        let mut mock_reader = MockWholeFileReader::new();
-        mock_reader.expect_read_to_string().returning(|| {
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
             Ok(String::from(
                 "First snippet\n\n---\n\nSecond snippet\n\n---\n\nThird snippet\n",
             ))
@@ -144,7 +485,7 @@ mod tests {
             "Unexpected error when getting snippet: {result:?}"
         );
         if let Ok(snippet) = result {
-            assert_eq!(snippet, "First snippet\n");
+            assert_eq!(snippet.text, "First snippet\n");
         }
     }
 
@@ -153,8 +494,8 @@ mod tests {
         let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
         let path = temp_file.path();
         let mut sut = SnippetHandler::new(path).unwrap();
-        let mut mock_reader = MockWholeFileReader::new();
-        mock_reader.expect_read_to_string().returning(|| {
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
             Ok(String::from(
                 "First snippet\n\n---\n\nSecond snippet\n\n---\n\nThird snippet\n",
             ))
@@ -168,7 +509,7 @@ mod tests {
             "Unexpected error when getting snippet: {result:?}"
         );
         if let Ok(snippet) = result {
-            assert_eq!(snippet, "Second snippet\n");
+            assert_eq!(snippet.text, "Second snippet\n");
         }
     }
 
@@ -177,8 +518,8 @@ mod tests {
         let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
         let path = temp_file.path();
         let mut sut = SnippetHandler::new(path).unwrap();
-        let mut mock_reader = MockWholeFileReader::new();
-        mock_reader.expect_read_to_string().returning(|| {
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
             Ok(String::from(
                 "First snippet\n\n---\n\nSecond snippet\n\n---\n\nThird snippet\n",
             ))
@@ -192,7 +533,7 @@ mod tests {
             "Unexpected error when getting snippet: {result:?}"
         );
         if let Ok(snippet) = result {
-            assert_eq!(snippet, "Third snippet\n");
+            assert_eq!(snippet.text, "Third snippet\n");
         }
     }
 
@@ -201,8 +542,8 @@ mod tests {
         let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
         let path = temp_file.path();
         let mut sut = SnippetHandler::new(path).unwrap();
-        let mut mock_reader = MockWholeFileReader::new();
-        mock_reader.expect_read_to_string().returning(|| {
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
             Ok(String::from(
                 "First snippet\n\n---\n\nSecond snippet\n\n---\n\nThird snippet\n",
             ))
@@ -223,10 +564,81 @@ mod tests {
         let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
         let path = temp_file.path();
         let mut sut = SnippetHandler::new(path).unwrap();
-        let mut mock_reader = MockWholeFileReader::new();
+        let mut mock_reader = MockDeckReader::new();
         mock_reader
             .expect_read_to_string()
-            .returning(|| Ok(String::new()));
+            .returning(|_| Ok(String::new()));
+
+        sut.set_reader(mock_reader);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            result.is_ok(),
+            "Unexpected error when getting snippet: {result:?}"
+        );
+        if let Ok(snippet) = result {
+            assert_eq!(snippet.text, "");
+        }
+    }
+
+    #[test]
+    fn snippet_header_is_not_part_of_the_text() {
+        let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
+        let path = temp_file.path();
+        let mut sut = SnippetHandler::new(path).unwrap();
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
+            Ok(String::from(
+                "First snippet\n\n---\n\n+++\nid = \"second\"\ntitle = \"The second one\"\n+++\nSecond snippet\n",
+            ))
+        });
+
+        sut.set_reader(mock_reader);
+
+        let result = sut.get_snippet(1);
+        assert!(
+            result.is_ok(),
+            "Unexpected error when getting snippet: {result:?}"
+        );
+        if let Ok(snippet) = result {
+            assert_eq!(snippet.text, "Second snippet\n");
+            assert_eq!(snippet.metadata.id.as_deref(), Some("second"));
+            assert_eq!(snippet.metadata.title.as_deref(), Some("The second one"));
+        }
+    }
+
+    #[test]
+    fn invalid_snippet_header_reports_deck_line() {
+        let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
+        let path = temp_file.path();
+        let mut sut = SnippetHandler::new(path).unwrap();
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
+            Ok(String::from(
+                "First snippet\n\n---\n\n+++\nid = \"second\"\n",
+            ))
+        });
+
+        sut.set_reader(mock_reader);
+
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { line: 5, .. })),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn deck_header_separator_is_used_to_split_snippets() {
+        let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
+        let path = temp_file.path();
+        let mut sut = SnippetHandler::new(path).unwrap();
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
+            Ok(String::from(
+                "===\nseparator = \"%%%\"\nlanguage = \"yaml\"\n===\nkey: 1\n---\n\nother: 2\n\n%%%\n\nSecond snippet\n",
+            ))
+        });
 
         sut.set_reader(mock_reader);
 
@@ -236,7 +648,375 @@ mod tests {
             "Unexpected error when getting snippet: {result:?}"
         );
         if let Ok(snippet) = result {
-            assert_eq!(snippet, "");
+            assert_eq!(snippet.text, "key: 1\n---\n\nother: 2\n");
+            assert_eq!(snippet.metadata.language.as_deref(), Some("yaml"));
+        }
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "Second snippet\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn deck_settings_are_returned() {
+        let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
+        let path = temp_file.path();
+        let mut sut = SnippetHandler::new(path).unwrap();
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|_| {
+            Ok(String::from(
+                "===\ntitle = \"Traits\"\nend_of_deck = \"wrap\"\n===\nFirst snippet\n",
+            ))
+        });
+
+        sut.set_reader(mock_reader);
+
+        let result = sut.settings();
+        assert!(
+            matches!(result, Ok(ref settings) if settings.title.as_deref() == Some("Traits")
+                && settings.end_of_deck == EndOfDeck::Wrap),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    fn handler_with_files(
+        files: &'static [(&'static str, &'static str)],
+    ) -> SnippetHandler<'static> {
+        let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
+        let mut sut = SnippetHandler::new(temp_file.path()).unwrap();
+        sut.path = PathBuf::from("/talks/main.lazycoder");
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader.expect_read_to_string().returning(|path| {
+            files
+                .iter()
+                .find(|(name, _)| Path::new(name) == path)
+                .map(|(_, text)| String::from(*text))
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, "not found"))
+        });
+        sut.set_reader(mock_reader);
+        sut
+    }
+
+    #[test]
+    fn included_snippets_are_expanded_in_place() {
+        let sut = handler_with_files(&[
+            (
+                "/talks/main.lazycoder",
+                "First\n\n---\n\n!include part2.lazycoder\n!include ./part3.lazycoder\n\n---\n\nLast\n",
+            ),
+            ("/talks/part2.lazycoder", "Second\n\n---\n\nThird\n"),
+            ("/talks/part3.lazycoder", "Fourth\n"),
+        ]);
+
+        let texts = (0..5)
+            .map(|position| sut.get_snippet(position).map(|snippet| snippet.text))
+            .collect::<Result<Vec<_>, _>>();
+        assert!(
+            matches!(texts, Ok(ref texts)
+                if *texts == ["First\n", "Second\n", "Third\n", "Fourth\n", "Last\n"]),
+            "Unexpected snippets: {texts:?}"
+        );
+        assert!(matches!(
+            sut.get_snippet(5),
+            Err(LazyCoderError::RunOutOfSnippets)
+        ));
+    }
+
+    #[test]
+    fn nested_includes_are_relative_to_the_including_file() {
+        let sut = handler_with_files(&[
+            ("/talks/main.lazycoder", "!include parts/one.lazycoder\n"),
+            (
+                "/talks/parts/one.lazycoder",
+                "!include ../other/two.lazycoder\n",
+            ),
+            (
+                "/talks/other/two.lazycoder",
+                "===\nlanguage = \"rust\"\n===\nfn main() {}\n",
+            ),
+        ]);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "fn main() {}\n"
+                && snippet.metadata.language.as_deref() == Some("rust")),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let sut = handler_with_files(&[
+            (
+                "/talks/main.lazycoder",
+                "First\n\n---\n\n!include part2.lazycoder\n",
+            ),
+            (
+                "/talks/part2.lazycoder",
+                "Second\n\n---\n\n!include main.lazycoder\n",
+            ),
+        ]);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { ref file, line: 5, .. })
+                if file == Path::new("/talks/part2.lazycoder")),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn missing_included_file_reports_the_including_file_and_line() {
+        let sut = handler_with_files(&[(
+            "/talks/main.lazycoder",
+            "First\n\n---\n\n!include missing.lazycoder\n",
+        )]);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { ref file, line: 5, .. })
+                if file == Path::new("/talks/main.lazycoder")),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn parse_error_in_included_file_reports_that_file() {
+        let sut = handler_with_files(&[
+            (
+                "/talks/main.lazycoder",
+                "First\n\n---\n\n!include part2.lazycoder\n",
+            ),
+            (
+                "/talks/part2.lazycoder",
+                "Second\n\n---\n\n+++\nid = \"third\"\nThird\n",
+            ),
+        ]);
+
+        let result = sut.get_snippet(2);
+        assert!(
+            matches!(result, Err(LazyCoderError::DeckParseError { ref file, line: 5, .. })
+                if file == Path::new("/talks/part2.lazycoder")),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn cursor_marker_is_extracted_with_included_file_settings() {
+        let sut = handler_with_files(&[
+            (
+                "/talks/main.lazycoder",
+                "===\ncursor_marker = \"<|>\"\n===\nfn main() {<|>}\n\n---\n\n!include part2.lazycoder\n",
+            ),
+            ("/talks/part2.lazycoder", "let x = <|>;\n⟨cursor⟩\n"),
+        ]);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "fn main() {}\n"
+                && snippet.cursor == Some(11)),
+            "Unexpected result: {result:?}"
+        );
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "let x = ;\n⟨cursor⟩\n"
+                && snippet.cursor == Some(8)),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn crlf_deck_with_byte_order_mark_is_split_in_snippets() {
+        let sut = handler_with_files(&[(
+            "/talks/main.lazycoder",
+            "\u{feff}===\r\ntitle = \"Windows\"\r\n===\r\nFirst\r\n\r\n---\r\n\r\nSecond\r\n",
+        )]);
+
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "Second\n"),
+            "Unexpected result: {result:?}"
+        );
+        let result = sut.settings();
+        assert!(
+            matches!(result, Ok(ref settings) if settings.title.as_deref() == Some("Windows")),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings_are_kept_if_requested() {
+        let sut = handler_with_files(&[(
+            "/talks/main.lazycoder",
+            "===\r\nline_endings = \"keep\"\r\n===\r\nFirst\r\nline\r\n\r\n---\r\n\r\nSecond\r\n",
+        )]);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "First\r\nline\r\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn escaped_separator_is_a_literal_line_of_the_snippet() {
+        let sut = handler_with_files(&[(
+            "/talks/main.lazycoder",
+            "\\+++\ntitle = \"Hugo\"\n\\+++\n\n\\---\n\nname: doc\n\n---\n\nSecond\n",
+        )]);
+
+        let result = sut.get_snippet(0);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "+++\ntitle = \"Hugo\"\n+++\n\n---\n\nname: doc\n"
+                && snippet.metadata.title.is_none()),
+            "Unexpected result: {result:?}"
+        );
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "Second\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn indexed_snippets_match_the_deck() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("main.lazycoder");
+        fs::write(
+            &path,
+            "\u{feff}===\r\ntitle = \"Windows\"\r\n===\r\nFirst\r\n\r\n---\r\n\r\n!include part2.lazycoder\r\n\r\n---\r\n\r\n+++\r\nid = \"last\"\r\n+++\r\nLast ⟨cursor⟩\r\n",
+        )
+        .expect("Unable to write deck");
+        fs::write(
+            dir.path().join("part2.lazycoder"),
+            "Second\n\n---\n\nThird\n",
+        )
+        .expect("Unable to write included deck");
+        let index_path = dir.path().join("deck.index");
+        let sut = SnippetHandler::with_index(&path, index_path.clone()).unwrap();
+        let unindexed = SnippetHandler::new(&path).unwrap();
+
+        for position in 0..4 {
+            let result = sut.get_snippet(position);
+            assert!(
+                matches!((&result, unindexed.get_snippet(position)), (Ok(snippet), Ok(expected)) if *snippet == expected),
+                "Unexpected result for snippet {position}: {result:?}"
+            );
+        }
+        assert!(matches!(
+            sut.get_snippet(4),
+            Err(LazyCoderError::RunOutOfSnippets)
+        ));
+        assert_eq!(sut.snippet_count().ok(), unindexed.snippet_count().ok());
+        assert!(
+            matches!((sut.snippets(), unindexed.snippets()), (Ok(snippets), Ok(expected)) if snippets.len() == 4 && snippets == expected)
+        );
+        assert!(
+            matches!(sut.settings(), Ok(settings) if settings.title.as_deref() == Some("Windows"))
+        );
+        assert!(index_path.is_file());
+    }
+
+    #[test]
+    fn indexed_snippet_is_read_without_reading_the_deck() {
+        let temp_file = NamedTempFile::new().expect("Unable to create temporary file");
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let mut sut =
+            SnippetHandler::with_index(temp_file.path(), dir.path().join("deck.index")).unwrap();
+        let mut mock_reader = MockDeckReader::new();
+        mock_reader
+            .expect_modified_and_size()
+            .returning(|_| Ok((1, 37)));
+        mock_reader
+            .expect_read_to_string()
+            .times(1)
+            .returning(|_| Ok(String::from("First snippet\n\n---\n\nSecond snippet\n")));
+        mock_reader
+            .expect_read_range()
+            .withf(|_, start, end| *start == 20 && *end == 35)
+            .returning(|_, _, _| Ok(String::from("Second snippet\n")));
+        sut.set_reader(mock_reader);
+
+        for _ in 0..2 {
+            let result = sut.get_snippet(1);
+            assert!(
+                matches!(result, Ok(ref snippet) if snippet.text == "Second snippet\n"),
+                "Unexpected result: {result:?}"
+            );
         }
     }
+
+    #[test]
+    fn index_is_rebuilt_when_the_deck_changes() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("main.lazycoder");
+        fs::write(&path, "First\n\n---\n\nSecond\n").expect("Unable to write deck");
+        let sut = SnippetHandler::with_index(&path, dir.path().join("deck.index")).unwrap();
+        assert!(matches!(sut.get_snippet(1), Ok(snippet) if snippet.text == "Second\n"));
+
+        fs::write(&path, "New first\n\n---\n\nNew second\n").expect("Unable to write deck");
+
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "New second\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn index_is_rebuilt_when_the_defaults_change() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("main.lazycoder");
+        let index_path = dir.path().join("deck.index");
+        fs::write(&path, "First\n\n%%%\n\nSecond\n").expect("Unable to write deck");
+        let sut = SnippetHandler::with_index(&path, index_path.clone()).unwrap();
+        assert!(matches!(
+            sut.get_snippet(1),
+            Err(LazyCoderError::RunOutOfSnippets)
+        ));
+
+        let sut = SnippetHandler::with_index(&path, index_path)
+            .unwrap()
+            .with_defaults(DeckSettings {
+                separator: String::from("%%%"),
+                ..DeckSettings::default()
+            });
+
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "Second\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn touched_deck_keeps_its_index() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("main.lazycoder");
+        let index_path = dir.path().join("deck.index");
+        fs::write(&path, "First\n\n---\n\nSecond\n").expect("Unable to write deck");
+        let sut = SnippetHandler::with_index(&path, index_path.clone()).unwrap();
+        assert!(sut.get_snippet(0).is_ok());
+        let modified = SystemTime::now() + Duration::from_mins(1);
+        File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(modified))
+            .expect("Unable to touch deck");
+
+        let result = sut.get_snippet(1);
+        assert!(
+            matches!(result, Ok(ref snippet) if snippet.text == "Second\n"),
+            "Unexpected result: {result:?}"
+        );
+        let index = SnippetIndex::load(&index_path).expect("Index not saved");
+        assert_eq!(
+            Some(index.files[0].modified),
+            ReaderShell
+                .modified_and_size(&path)
+                .ok()
+                .map(|(modified, _)| modified)
+        );
+    }
 }
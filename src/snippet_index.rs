@@ -0,0 +1,162 @@
+//! Persistent index of the snippets of a deck
+//!
+//! The index stores where each snippet is in the deck files, so getting a snippet only requires a seek and a bounded
+//! read instead of reading and splitting the whole deck. It is valid while every file of the deck keeps its
+//! modification time and size, or at least its contents hash.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{deck::DeckSettings, state_file::write_atomic};
+
+/// Version of the index format. Indexes with other versions are rebuilt.
+const INDEX_VERSION: u32 = 2;
+
+/// File of the deck, the main one or an included one.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct IndexedFile {
+    pub path: PathBuf,
+    /// Modification time, in nanoseconds since the Unix epoch.
+    pub modified: u64,
+    pub size: u64,
+    pub hash: String,
+    pub crlf: bool,
+    pub settings: DeckSettings,
+}
+
+/// Location of a snippet in the files of the deck.
+#[derive(Deserialize, Serialize, PartialEq, Clone, Debug)]
+pub struct IndexedSnippet {
+    /// Position of the file in the list of files of the index.
+    pub file: usize,
+    pub line: usize,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Index of the snippets of a deck. The first file is the main one.
+#[derive(Deserialize, Serialize, PartialEq, Debug)]
+pub struct SnippetIndex {
+    version: u32,
+    /// Settings used for the files that don't define them, that change how they are split.
+    pub defaults: DeckSettings,
+    pub files: Vec<IndexedFile>,
+    pub snippets: Vec<IndexedSnippet>,
+}
+
+impl SnippetIndex {
+    pub fn new(
+        defaults: DeckSettings,
+        files: Vec<IndexedFile>,
+        snippets: Vec<IndexedSnippet>,
+    ) -> SnippetIndex {
+        SnippetIndex {
+            version: INDEX_VERSION,
+            defaults,
+            files,
+            snippets,
+        }
+    }
+
+    /// Reads the index from the file. Returns `None` if it doesn't exist, can't be decoded, or has another version.
+    pub fn load(path: &Path) -> Option<SnippetIndex> {
+        let text = fs::read_to_string(path).ok()?;
+        match toml::from_str::<SnippetIndex>(&text) {
+            Ok(index) if index.version == INDEX_VERSION => Some(index),
+            Ok(_) => {
+                debug!("Index {} has another version", path.display());
+                None
+            }
+            Err(err) => {
+                debug!("Index {} can't be decoded: {err}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Writes the index to the file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        write_atomic(path, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tempfile::tempdir;
+
+    use crate::deck::EndOfDeck;
+
+    use super::*;
+
+    fn index() -> SnippetIndex {
+        SnippetIndex::new(
+            DeckSettings {
+                separator: String::from("%%%"),
+                ..DeckSettings::default()
+            },
+            vec![IndexedFile {
+                path: PathBuf::from("/talks/main.lazycoder"),
+                modified: 1_700_000_000_000_000_000,
+                size: 42,
+                hash: String::from("cbf29ce484222325"),
+                crlf: false,
+                settings: DeckSettings {
+                    title: Some(String::from("Traits")),
+                    end_of_deck: EndOfDeck::Wrap,
+                    vars: BTreeMap::from([(String::from("name"), String::from("shapes"))]),
+                    ..DeckSettings::default()
+                },
+            }],
+            vec![
+                IndexedSnippet {
+                    file: 0,
+                    line: 1,
+                    start: 0,
+                    end: 10,
+                },
+                IndexedSnippet {
+                    file: 0,
+                    line: 5,
+                    start: 15,
+                    end: 42,
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn saved_index_is_loaded() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("deck.index");
+
+        index().save(&path).expect("Unable to save index");
+
+        assert_eq!(SnippetIndex::load(&path), Some(index()));
+    }
+
+    #[test]
+    fn missing_index_is_not_loaded() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+
+        assert_eq!(SnippetIndex::load(&dir.path().join("deck.index")), None);
+    }
+
+    #[test]
+    fn index_with_other_version_is_not_loaded() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("deck.index");
+        let mut old_index = index();
+        old_index.version = 0;
+
+        old_index.save(&path).expect("Unable to save index");
+
+        assert_eq!(SnippetIndex::load(&path), None);
+    }
+}
@@ -0,0 +1,367 @@
+//! Safe access to the state files
+//!
+//! Several lazycoder processes may run at the same time, e.g. when espanso triggers `next` twice in quick succession.
+//! State files are written to a temporary file that replaces them atomically, so they are never seen half-written,
+//! and each read-modify-write cycle holds an advisory lock, so the cycles of different processes don't interleave.
+//! Paths stored in them survive not being valid UTF-8.
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::lazy_coder_error::LazyCoderError;
+
+/// Time between attempts to take a lock that is held by another process.
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+/// File left in a state directory once the state of older versions has been moved to it.
+const MIGRATED_FILE: &str = ".migrated";
+
+/// Writes the contents to a temporary file in the same directory and renames it to the path.
+pub fn write_atomic<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other(format!("{} isn't a file", path.display())))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Exclusive advisory lock on a file, released when it is dropped.
+#[derive(Debug)]
+pub struct StateLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl StateLock {
+    /// Takes the lock on the file, creating it if needed, and waiting for other processes to release it.
+    ///
+    /// Fails with [`LazyCoderError::StateLocked`] if it can't be taken before the timeout.
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<StateLock, LazyCoderError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        let start = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => {
+                    return Ok(StateLock {
+                        path: path.to_path_buf(),
+                        _file: file,
+                    });
+                }
+                Err(TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                    thread::sleep(RETRY_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(LazyCoderError::StateLocked(path.to_path_buf()));
+                }
+                Err(TryLockError::Error(err)) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Locks are equal if they are taken on the same file.
+impl PartialEq for StateLock {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+/// Moves the state files of older versions from their directory to the state directory, only the first time.
+///
+/// Files whose names are `moved` are moved unless the state directory already has them, since those are newer, and
+/// files whose names are `discarded` are removed. Files that can't be moved are left where they are.
+pub fn migrate_dir(
+    legacy_dir: &Path,
+    dir: &Path,
+    moved: impl Fn(&str) -> bool,
+    discarded: impl Fn(&str) -> bool,
+) -> io::Result<()> {
+    let marker = dir.join(MIGRATED_FILE);
+    if marker.exists() {
+        return Ok(());
+    }
+    match fs::read_dir(legacy_dir) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let name_text = name.to_string_lossy();
+                let target = dir.join(&name);
+                let result = if discarded(&name_text) {
+                    fs::remove_file(entry.path())
+                } else if !moved(&name_text) {
+                    Ok(())
+                } else if target.exists() {
+                    warn!(
+                        "Keeping {} instead of the older {}",
+                        target.display(),
+                        entry.path().display()
+                    );
+                    Ok(())
+                } else {
+                    info!("Moving {} to {}", entry.path().display(), dir.display());
+                    fs::rename(entry.path(), target)
+                };
+                if let Err(err) = result {
+                    warn!("Failed to migrate {}: {err}", entry.path().display());
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    write_atomic(marker, "")
+}
+
+/// Encoding of paths in the state files, to be used with `#[serde(with = "stored_path")]`.
+///
+/// Paths are stored as strings if they are valid UTF-8, and as arrays of their bytes otherwise, since TOML strings
+/// can't hold them.
+pub mod stored_path {
+    use std::path::{Path, PathBuf};
+
+    use serde::{Deserialize as _, Deserializer, Serialize as _, Serializer, de::Error as _};
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum Encoded<'a> {
+        Text(&'a str),
+        Bytes(&'a [u8]),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Decoded {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+
+    impl Encoded<'_> {
+        fn of(path: &Path) -> Encoded<'_> {
+            match path.to_str() {
+                Some(text) => Encoded::Text(text),
+                None => Encoded::Bytes(path.as_os_str().as_encoded_bytes()),
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        Encoded::of(path).serialize(serializer)
+    }
+
+    /// Encodes an optional path the same way, to be used with `#[serde(serialize_with = "stored_path::optional")]`,
+    /// that requires taking a reference to the option.
+    #[allow(clippy::ref_option)]
+    pub fn optional<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        path.as_deref().map(Encoded::of).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        match Decoded::deserialize(deserializer)? {
+            Decoded::Text(text) => Ok(PathBuf::from(text)),
+            Decoded::Bytes(bytes) => from_bytes(bytes)
+                .ok_or_else(|| D::Error::custom("path isn't valid in this platform")),
+        }
+    }
+
+    #[cfg(unix)]
+    #[allow(clippy::unnecessary_wraps)]
+    fn from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+        use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+        Some(PathBuf::from(OsString::from_vec(bytes)))
+    }
+
+    /// Other platforms only store paths that aren't valid UTF-8 when they aren't valid Unicode, so they can't be
+    /// decoded safely.
+    #[cfg(not(unix))]
+    fn from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+        String::from_utf8(bytes).ok().map(PathBuf::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn migrate(legacy_dir: &Path, dir: &Path) -> io::Result<()> {
+        migrate_dir(
+            legacy_dir,
+            dir,
+            |name| name == "lazycoder.toml",
+            |name| name.starts_with("lazycoder-"),
+        )
+    }
+
+    #[test]
+    fn legacy_state_is_moved_once() {
+        let legacy_dir = tempdir().expect("Unable to create temporary directory");
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let legacy_path = legacy_dir.path().join("lazycoder.toml");
+        fs::write(&legacy_path, "position = 1\n").expect("Unable to write file");
+        fs::write(legacy_dir.path().join("lazycoder-1.index"), "").expect("Unable to write file");
+        fs::write(legacy_dir.path().join("settings.toml"), "").expect("Unable to write file");
+
+        migrate(legacy_dir.path(), dir.path()).expect("Unable to migrate");
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("lazycoder.toml")).expect("Unable to read file"),
+            "position = 1\n"
+        );
+        assert!(!legacy_dir.path().join("lazycoder-1.index").exists());
+        assert!(legacy_dir.path().join("settings.toml").exists());
+
+        fs::write(&legacy_path, "position = 7\n").expect("Unable to write file");
+        fs::remove_file(dir.path().join("lazycoder.toml")).expect("Unable to remove file");
+        migrate(legacy_dir.path(), dir.path()).expect("Unable to migrate");
+
+        assert!(legacy_path.exists());
+        assert!(!dir.path().join("lazycoder.toml").exists());
+    }
+
+    #[test]
+    fn legacy_state_does_not_replace_newer_state() {
+        let legacy_dir = tempdir().expect("Unable to create temporary directory");
+        let dir = tempdir().expect("Unable to create temporary directory");
+        fs::write(legacy_dir.path().join("lazycoder.toml"), "position = 1\n")
+            .expect("Unable to write file");
+        fs::write(dir.path().join("lazycoder.toml"), "position = 5\n")
+            .expect("Unable to write file");
+
+        migrate(legacy_dir.path(), dir.path()).expect("Unable to migrate");
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("lazycoder.toml")).expect("Unable to read file"),
+            "position = 5\n"
+        );
+        assert!(legacy_dir.path().join("lazycoder.toml").exists());
+    }
+
+    #[test]
+    fn missing_legacy_dir_is_migrated() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+
+        let result = migrate(&dir.path().join("missing"), dir.path());
+
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+        assert!(dir.path().join(MIGRATED_FILE).exists());
+    }
+
+    #[test]
+    fn atomic_write_replaces_contents_without_leftovers() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("lazycoder.toml");
+        fs::write(&path, "position = 1\n").expect("Unable to write file");
+
+        write_atomic(&path, "position = 2\n").expect("Unable to write atomically");
+
+        assert_eq!(
+            fs::read_to_string(&path).expect("Unable to read file"),
+            "position = 2\n"
+        );
+        assert_eq!(
+            fs::read_dir(dir.path())
+                .expect("Unable to read directory")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn atomic_write_to_missing_directory_fails() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+
+        let result = write_atomic(dir.path().join("missing").join("lazycoder.toml"), "");
+
+        assert!(result.is_err(), "Unexpected result: {result:?}");
+    }
+
+    #[test]
+    fn held_lock_times_out() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("lazycoder.lock");
+        let _lock = StateLock::acquire(&path, Duration::ZERO).expect("Unable to take lock");
+
+        let result = StateLock::acquire(&path, Duration::from_millis(30));
+
+        assert!(
+            matches!(result, Err(LazyCoderError::StateLocked(ref locked)) if *locked == path),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[derive(serde_derive::Deserialize, serde_derive::Serialize, PartialEq, Debug)]
+    struct Stored {
+        #[serde(with = "stored_path")]
+        path: PathBuf,
+    }
+
+    #[test]
+    fn utf8_path_is_stored_as_string() {
+        let stored = Stored {
+            path: PathBuf::from("/talks/traits.lazycoder"),
+        };
+
+        let text = toml::to_string(&stored).expect("Unable to encode path");
+
+        assert_eq!(text, "path = \"/talks/traits.lazycoder\"\n");
+        assert_eq!(toml::from_str::<Stored>(&text).ok(), Some(stored));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_path_is_stored_as_bytes() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let stored = Stored {
+            path: PathBuf::from(OsStr::from_bytes(b"/talks/caf\xe9")),
+        };
+
+        let text = toml::to_string(&stored).expect("Unable to encode path");
+
+        assert_eq!(
+            text,
+            "path = [47, 116, 97, 108, 107, 115, 47, 99, 97, 102, 233]\n"
+        );
+        assert_eq!(toml::from_str::<Stored>(&text).ok(), Some(stored));
+    }
+
+    #[test]
+    fn released_lock_is_taken_again() {
+        let dir = tempdir().expect("Unable to create temporary directory");
+        let path = dir.path().join("lazycoder.lock");
+        drop(StateLock::acquire(&path, Duration::ZERO).expect("Unable to take lock"));
+
+        let result = StateLock::acquire(&path, Duration::ZERO);
+
+        assert!(result.is_ok(), "Unexpected result: {result:?}");
+    }
+}
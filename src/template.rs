@@ -0,0 +1,124 @@
+//! Template variables
+//!
+//! Snippets may contain placeholders like `{{project_name}}` that are substituted when the snippet is provided, if their
+//! deck enables templates.
+//! Variable names contain only letters, digits and underscores, and don't start with a digit. Anything else between
+//! double braces, like `{{}}` in a Rust format string, is kept as is. Use `\{{` to write a literal `{{`.
+use crate::lazy_coder_error::LazyCoderError;
+
+static OPENING: &str = "{{";
+static CLOSING: &str = "}}";
+
+/// Checks if the text may contain placeholders, so the variables are only looked up when needed.
+pub fn has_placeholders(text: &str) -> bool {
+    text.contains(OPENING)
+}
+
+/// Substitutes the placeholders of the text with the values provided by `lookup`.
+///
+/// Returns an error with the name of the first variable that `lookup` doesn't know.
+pub fn expand<F>(text: &str, lookup: F) -> Result<String, LazyCoderError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(OPENING) {
+        let after = &rest[start + OPENING.len()..];
+        if let Some(before) = rest[..start].strip_suffix('\\') {
+            expanded.push_str(before);
+            expanded.push_str(OPENING);
+            rest = after;
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        if let Some((end, name)) = after
+            .find(CLOSING)
+            .map(|end| (end, after[..end].trim()))
+            .filter(|(_, name)| is_variable_name(name))
+        {
+            let value =
+                lookup(name).ok_or_else(|| LazyCoderError::UnknownVariable(name.to_owned()))?;
+            expanded.push_str(&value);
+            rest = &after[end + CLOSING.len()..];
+        } else {
+            expanded.push_str(OPENING);
+            rest = after;
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Checks if the text is a valid variable name.
+pub fn is_variable_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "project_name" => Some(String::from("shapes")),
+            "customer" => Some(String::from("ACME")),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn placeholders_are_substituted() {
+        let result = expand(
+            "cargo new {{project_name}}\n// For {{ customer }}: {{project_name}}\n",
+            lookup,
+        );
+
+        assert!(
+            matches!(result, Ok(ref text) if text == "cargo new shapes\n// For ACME: shapes\n"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn unknown_variable_is_an_error() {
+        let result = expand("Hello {{customer}} from {{company}}", lookup);
+
+        assert!(
+            matches!(result, Err(LazyCoderError::UnknownVariable(ref name)) if name == "company"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn text_that_is_not_a_placeholder_is_kept() {
+        let result = expand("println!(\"{{}} {{:?}}\", a, b); let x = {{ 1 }};", lookup);
+
+        assert!(
+            matches!(result, Ok(ref text) if text == "println!(\"{{}} {{:?}}\", a, b); let x = {{ 1 }};"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn escaped_placeholder_is_kept_literally() {
+        let result = expand("format!(\"\\{{name}}\") for {{customer}}", lookup);
+
+        assert!(
+            matches!(result, Ok(ref text) if text == "format!(\"{{name}}\") for ACME"),
+            "Unexpected result: {result:?}"
+        );
+    }
+
+    #[test]
+    fn variable_names_are_validated() {
+        assert!(is_variable_name("project_name"));
+        assert!(is_variable_name("_private2"));
+        assert!(!is_variable_name("2nd"));
+        assert!(!is_variable_name("project-name"));
+        assert!(!is_variable_name(""));
+    }
+}